@@ -9,12 +9,14 @@ use models::{Channel, Id, Membership, Message, User};
 use schema::{channels, memberships, messages, users};
 
 use chrono::Utc;
+use diesel::connection::SimpleConnection;
 use diesel::{
-    insert_into, Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl,
-    RunQueryDsl,
+    insert_into, sql_query, Connection, ExpressionMethods, OptionalExtension, PgConnection,
+    QueryDsl, RunQueryDsl,
 };
 use failure::{format_err, Error};
 use std::env;
+use std::time::Duration;
 
 pub struct Api {
     conn: PgConnection,
@@ -29,6 +31,22 @@ impl Api {
         Ok(Self { conn })
     }
 
+    /// Check the database answers a trivial query within `timeout`.
+    /// The timeout is enforced by the server, so it covers a busy or
+    /// locked database but not a connection that has gone dead.
+    pub fn health_check(&self, timeout: Duration) -> Result<(), Error> {
+        self.conn.transaction::<_, Error, _>(|| {
+            // SET LOCAL only lasts until the end of this transaction
+            self.conn.batch_execute(&format!(
+                "SET LOCAL statement_timeout = {}",
+                // 0 would mean no timeout at all
+                timeout.as_millis().max(1)
+            ))?;
+            sql_query("SELECT 1").execute(&self.conn)?;
+            Ok(())
+        })
+    }
+
     /// Register a new user
     pub fn register_user(&self, email: &str) -> Result<User, Error> {
         insert_into(users::table)
@@ -0,0 +1,151 @@
+//! Compares request throughput of the old single mutex design with the
+//! current store, which only locks for reads and writes.
+//!
+//! Run with `cargo bench`. Every thread replays the same mix of requests:
+//! index pages, user lookups (including building the JSON response) and
+//! user updates.
+
+use chrono::Utc;
+use hyper_microservice::store::{MemoryStore, UserStore};
+use hyper_microservice::user::{UserData, UserEntry, UserId};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USERS: usize = 1_000;
+const REQUESTS_PER_THREAD: usize = 200_000;
+const THREADS: &[usize] = &[1, 2, 4, 8];
+
+/// A request the benchmark can send.
+#[derive(Clone, Copy)]
+enum Request {
+    Index,
+    Get(UserId),
+    Update(UserId),
+}
+
+/// The parts of a server design that the benchmark exercises.
+trait Design: Send + Sync {
+    fn handle(&self, request: Request) -> usize;
+}
+
+/// The design before the store was reworked: one mutex, locked for the
+/// whole response no matter what the request was.
+struct GlobalMutex {
+    users: Mutex<BTreeMap<UserId, UserData>>,
+}
+
+impl Design for GlobalMutex {
+    fn handle(&self, request: Request) -> usize {
+        let mut users = self.users.lock().unwrap();
+        match request {
+            Request::Index => "<html></html>".len(),
+            Request::Get(id) => {
+                let user = &users[&id];
+                serde_json::to_string(&UserEntry { id, user })
+                    .unwrap()
+                    .len()
+            }
+            Request::Update(id) => {
+                users.insert(id, user(id));
+                0
+            }
+        }
+    }
+}
+
+/// The current design: the store locks on its own and only as long as
+/// it needs to, and the index page doesn't touch it at all.
+struct SharedStore {
+    users: Arc<dyn UserStore>,
+}
+
+impl Design for SharedStore {
+    fn handle(&self, request: Request) -> usize {
+        match request {
+            Request::Index => "<html></html>".len(),
+            Request::Get(id) => {
+                let user = self.users.get(id).unwrap().user;
+                serde_json::to_string(&UserEntry { id, user: &user })
+                    .unwrap()
+                    .len()
+            }
+            Request::Update(id) => {
+                self.users.update(id, user(id), None).unwrap();
+                0
+            }
+        }
+    }
+}
+
+fn user(id: UserId) -> UserData {
+    UserData {
+        name: format!("user {}", id),
+        email: format!("user{}@example.com", id),
+        created_at: Utc::now(),
+    }
+}
+
+/// A cheap, repeatable request mix: 10% index, 80% reads, 10% writes.
+fn request(n: usize) -> Request {
+    let id = (n * 7919 % USERS) as UserId;
+    match n % 10 {
+        0 => Request::Index,
+        1 => Request::Update(id),
+        _ => Request::Get(id),
+    }
+}
+
+/// Runs the workload on `threads` threads and returns requests per second.
+fn run(design: Arc<dyn Design>, threads: usize) -> f64 {
+    let start = Instant::now();
+    let handles = (0..threads)
+        .map(|t| {
+            let design = design.clone();
+            thread::spawn(move || {
+                let mut sink = 0;
+                for n in 0..REQUESTS_PER_THREAD {
+                    sink += design.handle(request(n + t));
+                }
+                sink
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let elapsed = start.elapsed().max(Duration::from_nanos(1));
+    (threads * REQUESTS_PER_THREAD) as f64 / elapsed.as_secs_f64()
+}
+
+fn main() {
+    let mut map = BTreeMap::new();
+    let store = MemoryStore::new();
+    for id in 0..USERS as UserId {
+        map.insert(id, user(id));
+        store.insert(user(id)).unwrap();
+    }
+    let old: Arc<dyn Design> = Arc::new(GlobalMutex {
+        users: Mutex::new(map),
+    });
+    let new: Arc<dyn Design> = Arc::new(SharedStore {
+        users: Arc::new(store),
+    });
+
+    println!(
+        "{:>8} {:>16} {:>16} {:>8}",
+        "threads", "mutex req/s", "rwlock req/s", "speedup"
+    );
+    for &threads in THREADS {
+        let old_rate = run(old.clone(), threads);
+        let new_rate = run(new.clone(), threads);
+        println!(
+            "{:>8} {:>16.0} {:>16.0} {:>7.2}x",
+            threads,
+            old_rate,
+            new_rate,
+            new_rate / old_rate
+        );
+    }
+}
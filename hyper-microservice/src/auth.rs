@@ -0,0 +1,183 @@
+//! Bearer token authentication.
+//!
+//! `Authenticate` resolves `Authorization: Bearer <token>` against a
+//! session store and attaches the `Caller` to the request. Reading users
+//! stays open to everyone. Changing a user takes a session for that user
+//! or an admin, creating users and batches take an admin.
+
+use crate::negotiate::{self, Format};
+use crate::sessions::SessionStore;
+use crate::user::{ErrorBody, UserId};
+use hyper::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use hyper::{Body, Request, Response, StatusCode};
+use log::error;
+use middleware::{rate_limit, BoxFuture, Middleware, Next};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Who sent a request, put into the request extensions by `Authenticate`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Caller {
+    /// Authentication is turned off, everyone may do everything.
+    Anyone,
+    /// No credentials were sent.
+    Anonymous,
+    /// A valid session for `uid`.
+    User { uid: UserId, admin: bool },
+}
+
+/// What a request needs to be allowed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    /// The user itself or an admin.
+    Owner(UserId),
+    Admin,
+}
+
+/// Why a request isn't allowed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Denied {
+    /// Credentials are needed but none were sent: 401.
+    Unauthenticated,
+    /// The caller may not do this: 403.
+    Forbidden,
+}
+
+impl Caller {
+    /// Checks that the caller has the wanted access.
+    pub fn check(self, access: Access) -> Result<(), Denied> {
+        match (self, access) {
+            (Caller::Anyone, _) => Ok(()),
+            (Caller::Anonymous, _) => Err(Denied::Unauthenticated),
+            (Caller::User { admin: true, .. }, _) => Ok(()),
+            (Caller::User { uid, .. }, Access::Owner(owner)) if uid == owner => Ok(()),
+            (Caller::User { .. }, _) => Err(Denied::Forbidden),
+        }
+    }
+
+    /// The caller of a request that went through `Authenticate`. A request
+    /// that didn't is treated as anonymous, so a missing middleware never
+    /// opens things up.
+    pub fn of(req: &Request<Body>) -> Caller {
+        req.extensions()
+            .get::<Caller>()
+            .copied()
+            .unwrap_or(Caller::Anonymous)
+    }
+}
+
+/// Sessions to check tokens against, and the users that are admins.
+pub struct Auth {
+    pub sessions: Arc<dyn SessionStore>,
+    pub admins: HashSet<UserId>,
+}
+
+/// Resolves the caller of every request. Without `Auth` every request
+/// comes from `Caller::Anyone`.
+pub struct Authenticate {
+    auth: Option<Arc<Auth>>,
+}
+
+impl Authenticate {
+    pub fn new(auth: Option<Auth>) -> Self {
+        Authenticate {
+            auth: auth.map(Arc::new),
+        }
+    }
+}
+
+impl Middleware for Authenticate {
+    fn handle(&self, mut req: Request<Body>, next: Next) -> BoxFuture {
+        let auth = match &self.auth {
+            Some(auth) => auth.clone(),
+            None => {
+                req.extensions_mut().insert(Caller::Anyone);
+                return next.run(req);
+            }
+        };
+        let format = Format::preferred(req.headers()).unwrap_or(Format::Json);
+
+        let token = match req.headers().get(AUTHORIZATION) {
+            None => {
+                req.extensions_mut().insert(Caller::Anonymous);
+                return next.run(req);
+            }
+            Some(value) => match bearer_token(value) {
+                Some(token) => token,
+                None => {
+                    let response = unauthorized(format, "expected a bearer token", None);
+                    return Box::pin(async { response });
+                }
+            },
+        };
+
+        Box::pin(async move {
+            // Session stores block, keep them off the async workers
+            let sessions = auth.sessions.clone();
+            let lookup = tokio::task::spawn_blocking(move || sessions.lookup(&token)).await;
+            let uid = match lookup {
+                Ok(Ok(Some(uid))) => uid,
+                Ok(Ok(None)) => {
+                    return unauthorized(format, "unknown session", Some("invalid_token"));
+                }
+                Ok(Err(err)) => {
+                    error!("Can't check session: {}", err);
+                    let body = ErrorBody::new("can't check sessions right now");
+                    return negotiate::respond(format, StatusCode::SERVICE_UNAVAILABLE, &body);
+                }
+                Err(err) => {
+                    error!("Session lookup failed: {}", err);
+                    let body = ErrorBody::new("can't check sessions right now");
+                    return negotiate::respond(format, StatusCode::SERVICE_UNAVAILABLE, &body);
+                }
+            };
+            let admin = auth.admins.contains(&uid);
+            req.extensions_mut().insert(Caller::User { uid, admin });
+            next.run(req).await
+        })
+    }
+}
+
+/// Rate limits signed in users by their id, so they share one limit
+/// wherever they call from, and everyone else by IP.
+pub fn rate_limit_key(req: &Request<Body>) -> String {
+    match Caller::of(req) {
+        Caller::User { uid, .. } => format!("user:{}", uid),
+        _ => rate_limit::client_ip(req),
+    }
+}
+
+/// Whether `Authenticate` or a handler turned a request away for its
+/// credentials, or sessions couldn't be checked at all.
+pub fn failed(response: &Response<Body>) -> bool {
+    matches!(
+        response.status(),
+        StatusCode::UNAUTHORIZED | StatusCode::SERVICE_UNAVAILABLE
+    )
+}
+
+/// Takes the token out of an `Authorization: Bearer <token>` value.
+fn bearer_token(value: &HeaderValue) -> Option<String> {
+    let value = value.to_str().ok()?.trim();
+    let (scheme, token) = value.split_once(' ')?;
+    let token = token.trim();
+    if scheme.eq_ignore_ascii_case("bearer") && !token.is_empty() {
+        Some(token.to_owned())
+    } else {
+        None
+    }
+}
+
+/// A 401 telling the client to use a bearer token.
+pub fn unauthorized(format: Format, message: &str, error: Option<&str>) -> Response<Body> {
+    let mut response =
+        negotiate::respond(format, StatusCode::UNAUTHORIZED, &ErrorBody::new(message));
+    let challenge = match error {
+        Some(error) => format!("Bearer error=\"{}\"", error),
+        None => "Bearer".to_owned(),
+    };
+    // Only fixed strings end up in here
+    let challenge = HeaderValue::from_str(&challenge).unwrap();
+    response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+    response
+}
@@ -0,0 +1,285 @@
+//! Bulk writes with `POST /users/batch`.
+//!
+//! The body holds a mode and a list of operations:
+//!
+//! ```json
+//! {
+//!     "mode": "best_effort",
+//!     "operations": [
+//!         {"op": "create", "user": {"name": "Alice", "email": "alice@example.com"}},
+//!         {"op": "update", "id": 3, "version": 2, "user": {"name": "Bob", "email": "bob@example.com"}},
+//!         {"op": "delete", "id": 4}
+//!     ]
+//! }
+//! ```
+//!
+//! `version` is optional and works like `If-Match`. In `atomic` mode, the
+//! default, either every operation is applied or none is. In `best_effort`
+//! mode each operation stands on its own. Either way the whole batch runs
+//! under a single store lock.
+//!
+//! The reply has one result per operation, in order, with the status code
+//! the matching single request would have gotten and the user id. When an
+//! atomic batch fails, operations that would have worked get a 424.
+
+use crate::handlers::check_user_input;
+use crate::negotiate::{escape, Render};
+use crate::store::{Applied, Change, StoreError, UserStore, Version};
+use crate::user::{ErrorBody, UserId, UserInput};
+use hyper::StatusCode;
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Most operations a single batch may hold.
+pub const MAX_OPERATIONS: usize = 1000;
+
+/// How failures of single operations affect the batch.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    #[default]
+    Atomic,
+    BestEffort,
+}
+
+/// The body of `POST /users/batch`. Operations are decoded one by one,
+/// so a malformed operation only fails itself.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BatchRequest {
+    #[serde(default)]
+    pub mode: Mode,
+    #[schemars(with = "Vec<Operation>")]
+    pub operations: Vec<Value>,
+}
+
+/// One operation of a batch.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(tag = "op", rename_all = "lowercase", deny_unknown_fields)]
+enum Operation {
+    Create {
+        user: UserInput,
+    },
+    Update {
+        id: UserId,
+        user: UserInput,
+        version: Option<Version>,
+    },
+    Delete {
+        id: UserId,
+        version: Option<Version>,
+    },
+}
+
+impl Operation {
+    /// The user the operation is about, if it names one.
+    fn id(&self) -> Option<UserId> {
+        match self {
+            Operation::Create { .. } => None,
+            Operation::Update { id, .. } | Operation::Delete { id, .. } => Some(*id),
+        }
+    }
+
+    fn into_change(self) -> Change {
+        match self {
+            Operation::Create { user } => Change::Insert(user.into_user()),
+            Operation::Update { id, user, version } => Change::Update {
+                id,
+                user: user.into_user(),
+                expected: version.map(|version| vec![version]),
+            },
+            Operation::Delete { id, version } => Change::Delete {
+                id,
+                expected: version.map(|version| vec![version]),
+            },
+        }
+    }
+}
+
+/// The outcome of one operation.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ItemResult {
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<UserId>,
+    /// The version after the operation, or the current one on a 412.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<Version>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+}
+
+impl ItemResult {
+    fn failed(status: StatusCode, id: Option<UserId>, error: ErrorBody) -> Self {
+        ItemResult {
+            status: status.as_u16(),
+            id,
+            version: None,
+            error: Some(error),
+        }
+    }
+
+    fn applied(applied: Applied) -> Self {
+        let (status, id, version) = match applied {
+            Applied::Inserted { id, version } => (StatusCode::CREATED, id, Some(version)),
+            Applied::Updated { id, version } => (StatusCode::OK, id, Some(version)),
+            Applied::Deleted { id } => (StatusCode::NO_CONTENT, id, None),
+        };
+        ItemResult {
+            status: status.as_u16(),
+            id: Some(id),
+            version,
+            error: None,
+        }
+    }
+
+    /// For operations that were fine but were dropped with the rest of
+    /// a failed atomic batch.
+    fn not_applied(id: Option<UserId>) -> Self {
+        let error = ErrorBody::new("not applied, another operation failed");
+        Self::failed(StatusCode::FAILED_DEPENDENCY, id, error)
+    }
+
+    fn store_error(err: StoreError, id: Option<UserId>) -> Self {
+        let (status, version) = match err {
+            StoreError::NotFound => (StatusCode::NOT_FOUND, None),
+            StoreError::Gone => (StatusCode::GONE, None),
+            StoreError::VersionMismatch { current } => {
+                (StatusCode::PRECONDITION_FAILED, Some(current))
+            }
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, None),
+        };
+        ItemResult {
+            version,
+            ..Self::failed(status, id, ErrorBody::new(err.to_string()))
+        }
+    }
+
+    fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// The reply to a batch.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct BatchResponse {
+    /// Whether the successful operations were kept.
+    pub committed: bool,
+    pub results: Vec<ItemResult>,
+}
+
+impl BatchResponse {
+    /// Whether every single operation went through.
+    pub fn all_succeeded(&self) -> bool {
+        self.results.iter().all(ItemResult::is_success)
+    }
+}
+
+impl Render for BatchResponse {
+    /// One line per operation with its status, id and error.
+    fn text(&self) -> String {
+        let mut lines = vec![format!("committed: {}", self.committed)];
+        for result in &self.results {
+            let mut line = result.status.to_string();
+            if let Some(id) = result.id {
+                line.push_str(&format!(" {}", id));
+            }
+            if let Some(error) = &result.error {
+                line.push_str(&format!(" {}", error.error));
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+
+    fn html(&self) -> String {
+        let mut html = format!(
+            "<h3>Batch {}</h3>\n<table>\n<tr><th>Status</th><th>Id</th><th>Error</th></tr>",
+            if self.committed {
+                "committed"
+            } else {
+                "not committed"
+            }
+        );
+        for result in &self.results {
+            html.push_str(&format!(
+                "\n<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                result.status,
+                result.id.map(|id| id.to_string()).unwrap_or_default(),
+                result
+                    .error
+                    .as_ref()
+                    .map(|error| escape(&error.error))
+                    .unwrap_or_default()
+            ));
+        }
+        html.push_str("\n</table>");
+        html
+    }
+
+    fn title(&self) -> String {
+        "Batch".into()
+    }
+}
+
+/// Checks every operation of a batch and runs the valid ones against the
+/// store. Only a failing store fails the whole batch.
+pub fn run(user_db: &dyn UserStore, request: BatchRequest) -> Result<BatchResponse, StoreError> {
+    let atomic = request.mode == Mode::Atomic;
+
+    // Decode and validate up front so the store only sees good changes
+    let mut results = Vec::with_capacity(request.operations.len());
+    let mut changes = Vec::new();
+    let mut pending = Vec::new();
+    for operation in request.operations {
+        let id = operation.get("id").and_then(Value::as_u64);
+        match decode(operation) {
+            Ok(operation) => {
+                pending.push((results.len(), operation.id()));
+                changes.push(operation.into_change());
+                results.push(None);
+            }
+            Err((status, error)) => results.push(Some(ItemResult::failed(status, id, error))),
+        }
+    }
+
+    let invalid = pending.len() < results.len();
+    let committed = if atomic && invalid {
+        // No point in touching the store, nothing would be kept
+        for (index, id) in pending {
+            results[index] = Some(ItemResult::not_applied(id));
+        }
+        false
+    } else {
+        let outcome = user_db.apply(changes, atomic)?;
+        for ((index, id), result) in pending.into_iter().zip(outcome.results) {
+            results[index] = Some(match result {
+                Ok(_) if !outcome.committed => ItemResult::not_applied(id),
+                Ok(applied) => ItemResult::applied(applied),
+                Err(err) => ItemResult::store_error(err, id),
+            });
+        }
+        outcome.committed
+    };
+
+    Ok(BatchResponse {
+        committed,
+        // Every slot got filled in one of the branches above
+        results: results.into_iter().map(Option::unwrap).collect(),
+    })
+}
+
+/// Decodes one operation, validating the user it carries.
+fn decode(operation: Value) -> Result<Operation, (StatusCode, ErrorBody)> {
+    let operation = serde_json::from_value::<Operation>(operation).map_err(|err| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorBody::new(err.to_string()),
+        )
+    })?;
+    if let Operation::Create { user } | Operation::Update { user, .. } = &operation {
+        check_user_input(user)?;
+    }
+    Ok(operation)
+}
@@ -0,0 +1,89 @@
+//! Entity tags for optimistic concurrency control.
+//!
+//! Every user has a version that goes up on each change, and it is sent
+//! to clients as a strong `ETag` such as `"3"`. Clients can then make
+//! requests conditional with `If-None-Match` (for reads) and `If-Match`
+//! (for writes).
+
+use crate::store::Version;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, IF_MATCH, IF_NONE_MATCH};
+
+/// Formats a version as a strong entity tag.
+pub fn etag(version: Version) -> HeaderValue {
+    // Digits in quotes are always a valid header value
+    HeaderValue::from_str(&format!("\"{}\"", version)).unwrap()
+}
+
+/// An entity tag as sent by a client.
+struct Tag<'a> {
+    weak: bool,
+    value: &'a str,
+}
+
+impl Tag<'_> {
+    fn version(&self) -> Option<Version> {
+        self.value.parse().ok()
+    }
+}
+
+/// What a conditional header asks for.
+enum Condition<'a> {
+    /// `*`, any current version will do.
+    Any,
+    /// A list of tags, anything that isn't a valid tag is left out.
+    Tags(Vec<Tag<'a>>),
+}
+
+/// Reads a conditional header, joining repeated headers into one list.
+fn condition(headers: &HeaderMap, name: HeaderName) -> Option<Condition<'_>> {
+    let mut tags = Vec::new();
+    let mut found = false;
+    for value in headers.get_all(name) {
+        found = true;
+        let value = value.to_str().unwrap_or("");
+        for tag in value.split(',').map(str::trim) {
+            if tag == "*" {
+                return Some(Condition::Any);
+            }
+            let (weak, tag) = match tag.strip_prefix("W/") {
+                Some(tag) => (true, tag),
+                None => (false, tag),
+            };
+            if tag.len() >= 2 && tag.starts_with('"') && tag.ends_with('"') {
+                tags.push(Tag {
+                    weak,
+                    value: &tag[1..tag.len() - 1],
+                });
+            }
+        }
+    }
+    if found {
+        Some(Condition::Tags(tags))
+    } else {
+        None
+    }
+}
+
+/// The versions a write is allowed to replace according to `If-Match`,
+/// or `None` when the write isn't conditional. Only strong tags can match.
+pub fn if_match(headers: &HeaderMap) -> Option<Vec<Version>> {
+    match condition(headers, IF_MATCH)? {
+        Condition::Any => None,
+        Condition::Tags(tags) => Some(
+            tags.iter()
+                .filter(|tag| !tag.weak)
+                .filter_map(Tag::version)
+                .collect(),
+        ),
+    }
+}
+
+/// Whether `If-None-Match` says the client already has `version`.
+/// Weak tags match too, as the header uses weak comparison.
+pub fn if_none_match(headers: &HeaderMap, version: Version) -> bool {
+    match condition(headers, IF_NONE_MATCH) {
+        None => false,
+        Some(Condition::Any) => true,
+        Some(Condition::Tags(tags)) => tags.iter().any(|tag| tag.version() == Some(version)),
+    }
+}
@@ -0,0 +1,585 @@
+use crate::auth::{self, Access, Caller, Denied};
+use crate::batch::{self, BatchRequest, BatchResponse};
+use crate::conditional;
+use crate::listing::{ListQuery, Page};
+use crate::negotiate::{self, escape, respond, Format, Render};
+use crate::openapi::{self, Operation};
+use crate::patch::{self, PatchFormat};
+use crate::router::{response_with_code, Params, ResponseFuture, Router};
+use crate::store::{StoreError, UserStore, Version};
+use crate::user::{ErrorBody, UserEntry, UserId, UserInput};
+use hyper::header::{HeaderName, HeaderValue, CONTENT_TYPE, ETAG};
+use hyper::{Body, Request, Response, StatusCode};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_derive::Serialize;
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+
+/// The landing page, with links to the collections.
+#[derive(Serialize, JsonSchema)]
+struct Index {
+    title: &'static str,
+    links: IndexLinks,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct IndexLinks {
+    users: &'static str,
+    user: &'static str,
+    batch: &'static str,
+}
+
+const INDEX: Index = Index {
+    title: "Rust Microservice Example",
+    links: IndexLinks {
+        users: "/users",
+        user: "/user/{id}",
+        batch: "/users/batch",
+    },
+};
+
+impl Render for Index {
+    fn text(&self) -> String {
+        self.title.to_owned()
+    }
+
+    fn html(&self) -> String {
+        format!(
+            "<h3>{}</h3>\n<p><a href=\"{}\">Users</a></p>",
+            escape(self.title),
+            escape(self.links.users)
+        )
+    }
+
+    fn title(&self) -> String {
+        "Rust Microservice".into()
+    }
+}
+
+// How often a PATCH is retried when another write slips in between
+// reading the user and storing the patched result
+const PATCH_ATTEMPTS: usize = 3;
+
+// Any storage backend can be shared, each one locks only what it needs
+pub type UserDb = Arc<dyn UserStore>;
+
+/// Registers every endpoint of the service, each followed by its
+/// documentation for `/openapi.json`.
+pub fn routes() -> Router<UserDb> {
+    let index_doc = || {
+        Operation::new("Landing page with links to the collections")
+            .reply::<Index>(200, "The links")
+            .status(406, "No acceptable format")
+    };
+    let mut router = Router::new();
+    router
+        // Root path: landing page with links
+        .get("/", negotiated(index))
+        .doc(index_doc())
+        .get("/index.htm", negotiated(index))
+        .doc(index_doc())
+        .get("/index.html", negotiated(index))
+        .doc(index_doc())
+        .get("/openapi.json", openapi_json)
+        .doc(Operation::new("This document").fixed_reply(
+            200,
+            "The OpenAPI document",
+            "application/json",
+        ))
+        // All users path
+        .get("/users", negotiated(list_users))
+        .doc(
+            Operation::new("Lists users a page at a time")
+                .query::<ListQuery>()
+                .reply::<Page>(200, "A page of users")
+                .error(400, "Bad query string")
+                .status(406, "No acceptable format"),
+        )
+        .post("/users/batch", negotiated(batch_users))
+        .doc(
+            Operation::new("Runs several creates, updates and deletes at once")
+                .body::<BatchRequest>()
+                .reply::<BatchResponse>(200, "Every operation went through")
+                .reply::<BatchResponse>(207, "Some operations failed, see the results")
+                .error(400, "Malformed batch")
+                .error(413, "Too many operations")
+                .error(415, "Body format can't be read")
+                .status(406, "No acceptable format")
+                .authenticated(),
+        )
+        // User REST Requests
+        .post("/user", negotiated(create_user))
+        .doc(
+            Operation::new("Creates a user, the id is picked by the service")
+                .body::<UserInput>()
+                .reply::<UserEntry>(201, "The new user, its version is in ETag")
+                .error(400, "Malformed body")
+                .error(415, "Body format can't be read")
+                .error(422, "Invalid user")
+                .status(406, "No acceptable format")
+                .authenticated(),
+        )
+        .post("/user/{id:u64}", negotiated(reject_user_id))
+        .doc(
+            Operation::new("Always fails, ids are picked by the service")
+                .status(400, "Use POST /user instead"),
+        )
+        .get("/user/{id:u64}", negotiated(get_user))
+        .doc(
+            Operation::new("Gets a user")
+                .header("If-None-Match", "ETags of versions the client already has")
+                .reply::<UserEntry>(200, "The user, its version is in ETag")
+                .status(304, "The client's version is current")
+                .status(404, "No such user")
+                .status(406, "No acceptable format")
+                .status(410, "The user was deleted"),
+        )
+        .put("/user/{id:u64}", negotiated(replace_user))
+        .doc(
+            Operation::new("Replaces a user, keeping its creation time")
+                .header(
+                    "If-Match",
+                    "Only replace if the user is at one of these ETags",
+                )
+                .body::<UserInput>()
+                .reply::<UserEntry>(200, "The replaced user, its version is in ETag")
+                .error(400, "Malformed body")
+                .status(404, "No such user")
+                .status(406, "No acceptable format")
+                .status(410, "The user was deleted")
+                .error(412, "The user has changed, the current version is in ETag")
+                .error(415, "Body format can't be read")
+                .error(422, "Invalid user")
+                .authenticated(),
+        )
+        .patch("/user/{id:u64}", negotiated(patch_user))
+        .doc(
+            Operation::new("Changes some fields of a user")
+                .header(
+                    "If-Match",
+                    "Only patch if the user is at one of these ETags",
+                )
+                .patch_body()
+                .reply::<UserEntry>(200, "The patched user, its version is in ETag")
+                .error(400, "Malformed patch")
+                .status(404, "No such user")
+                .status(406, "No acceptable format")
+                .error(409, "A JSON Patch test failed")
+                .status(410, "The user was deleted")
+                .error(412, "The user has changed, the current version is in ETag")
+                .error(415, "Not a patch format, see Accept-Patch")
+                .error(422, "The patch can't be applied or leaves an invalid user")
+                .authenticated(),
+        )
+        .delete("/user/{id:u64}", negotiated(delete_user))
+        .doc(
+            Operation::new("Deletes a user, its id is never used again")
+                .header(
+                    "If-Match",
+                    "Only delete if the user is at one of these ETags",
+                )
+                .status(200, "The user is gone")
+                .status(404, "No such user")
+                .status(406, "No acceptable format")
+                .status(410, "The user was already deleted")
+                .error(412, "The user has changed, the current version is in ETag")
+                .authenticated(),
+        );
+    router
+}
+
+/// Picks the response format before running a handler, answering 406
+/// right away if the client accepts nothing we produce. That way nothing
+/// is changed for a response the client won't take.
+fn negotiated<F, Fut>(
+    handler: F,
+) -> impl Fn(Request<Body>, Params, UserDb) -> ResponseFuture + Send + Sync + 'static
+where
+    F: Fn(Request<Body>, Params, UserDb, Format) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response<Body>> + Send + 'static,
+{
+    move |req, params, user_db| match Format::preferred(req.headers()) {
+        Some(format) => Box::pin(handler(req, params, user_db, format)),
+        None => Box::pin(async { negotiate::not_acceptable() }),
+    }
+}
+
+/// Gets the user id out of a route that declared `{id:u64}`.
+fn user_id(params: &Params) -> UserId {
+    params
+        .get::<UserId>("id")
+        .expect("route only matches numeric ids")
+}
+
+async fn index(
+    _req: Request<Body>,
+    _params: Params,
+    _user_db: UserDb,
+    format: Format,
+) -> Response<Body> {
+    respond(format, StatusCode::OK, &INDEX)
+}
+
+/// Serves the OpenAPI document, which is always JSON.
+async fn openapi_json(_req: Request<Body>, _params: Params, _user_db: UserDb) -> Response<Body> {
+    // Routes don't change while running, build the document once
+    static SPEC: OnceLock<String> = OnceLock::new();
+    let spec = SPEC.get_or_init(|| openapi::spec(&routes()).to_string());
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(spec.as_str()))
+        .unwrap()
+}
+
+/// Lists users a page at a time, see `ListQuery` for the options.
+async fn list_users(
+    req: Request<Body>,
+    _params: Params,
+    user_db: UserDb,
+    format: Format,
+) -> Response<Body> {
+    let query = match ListQuery::parse(req.uri().query()) {
+        Ok(query) => query,
+        Err(err) => {
+            return respond(format, StatusCode::BAD_REQUEST, &ErrorBody::new(err));
+        }
+    };
+    match user_db.list() {
+        Ok(list) => respond(format, StatusCode::OK, &query.apply("/users", &list)),
+        Err(err) => store_error_response(format, err),
+    }
+}
+
+/// Runs several creates, updates and deletes at once, see `batch`.
+/// Answers 200 if every operation went through, 207 if some didn't.
+async fn batch_users(
+    req: Request<Body>,
+    _params: Params,
+    user_db: UserDb,
+    format: Format,
+) -> Response<Body> {
+    if let Some(denied) = denied(&req, format, Access::Admin) {
+        return denied;
+    }
+    let request = match read_body::<BatchRequest>(req).await {
+        Ok(request) => request,
+        // The operations are checked one by one, so anything wrong at
+        // this level means the batch itself is malformed
+        Err((_, body)) => return respond(format, StatusCode::BAD_REQUEST, &body),
+    };
+    if request.operations.len() > batch::MAX_OPERATIONS {
+        let body = ErrorBody::new(format!(
+            "a batch holds at most {} operations",
+            batch::MAX_OPERATIONS
+        ));
+        return respond(format, StatusCode::PAYLOAD_TOO_LARGE, &body);
+    }
+    match batch::run(user_db.as_ref(), request) {
+        Ok(response) => {
+            let status = if response.all_succeeded() {
+                StatusCode::OK
+            } else {
+                StatusCode::MULTI_STATUS
+            };
+            respond(format, status, &response)
+        }
+        Err(err) => store_error_response(format, err),
+    }
+}
+
+/// Creates new user and returns it along with its id.
+async fn create_user(
+    req: Request<Body>,
+    _params: Params,
+    user_db: UserDb,
+    format: Format,
+) -> Response<Body> {
+    if let Some(denied) = denied(&req, format, Access::Admin) {
+        return denied;
+    }
+    let input = match read_user_input(req).await {
+        Ok(input) => input,
+        Err((status, body)) => return respond(format, status, &body),
+    };
+    let user = input.into_user();
+    match user_db.insert(user.clone()) {
+        Ok((id, version)) => {
+            let entry = UserEntry { id, user: &user };
+            with_etag(respond(format, StatusCode::CREATED, &entry), version)
+        }
+        Err(err) => store_error_response(format, err),
+    }
+}
+
+/// Disallows client to give a user id.
+async fn reject_user_id(
+    _req: Request<Body>,
+    _params: Params,
+    _user_db: UserDb,
+    _format: Format,
+) -> Response<Body> {
+    response_with_code(StatusCode::BAD_REQUEST)
+}
+
+/// Gets a user with a given id, or a 304 if the client's copy is current.
+async fn get_user(
+    req: Request<Body>,
+    params: Params,
+    user_db: UserDb,
+    format: Format,
+) -> Response<Body> {
+    let id = user_id(&params);
+    match user_db.get(id) {
+        Ok(stored) => {
+            let response = if conditional::if_none_match(req.headers(), stored.version) {
+                response_with_code(StatusCode::NOT_MODIFIED)
+            } else {
+                let entry = UserEntry {
+                    id,
+                    user: &stored.user,
+                };
+                respond(format, StatusCode::OK, &entry)
+            };
+            with_etag(response, stored.version)
+        }
+        Err(err) => store_error_response(format, err),
+    }
+}
+
+/// Replaces a user with a given id, keeping its creation time.
+/// Honors `If-Match` so clients don't overwrite each other's changes.
+async fn replace_user(
+    req: Request<Body>,
+    params: Params,
+    user_db: UserDb,
+    format: Format,
+) -> Response<Body> {
+    let id = user_id(&params);
+    if let Some(denied) = denied(&req, format, Access::Owner(id)) {
+        return denied;
+    }
+    let expected = conditional::if_match(req.headers());
+    let input = match read_user_input(req).await {
+        Ok(input) => input,
+        Err((status, body)) => return respond(format, status, &body),
+    };
+    // Access and replace, created_at never changes so reading it
+    // separately from the update is fine
+    let result = user_db.get(id).and_then(|existing| {
+        let user = input.into_user_created_at(existing.user.created_at);
+        user_db
+            .update(id, user.clone(), expected.as_deref())
+            .map(|version| (user, version))
+    });
+    match result {
+        Ok((user, version)) => {
+            let entry = UserEntry { id, user: &user };
+            with_etag(respond(format, StatusCode::OK, &entry), version)
+        }
+        Err(err) => store_error_response(format, err),
+    }
+}
+
+/// Changes some fields of a user, see the `patch` module for the formats.
+/// The patched user is validated like a full replacement, and `If-Match`
+/// is honored.
+async fn patch_user(
+    req: Request<Body>,
+    params: Params,
+    user_db: UserDb,
+    format: Format,
+) -> Response<Body> {
+    let id = user_id(&params);
+    if let Some(denied) = denied(&req, format, Access::Owner(id)) {
+        return denied;
+    }
+    let patch_format = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(PatchFormat::from_content_type);
+    let patch_format = match patch_format {
+        Some(patch_format) => patch_format,
+        None => {
+            let body = ErrorBody::new(format!(
+                "patches must be {} or {}",
+                patch::MERGE_PATCH,
+                patch::JSON_PATCH
+            ));
+            let mut response = respond(format, StatusCode::UNSUPPORTED_MEDIA_TYPE, &body);
+            response.headers_mut().insert(
+                HeaderName::from_static("accept-patch"),
+                HeaderValue::from_static(
+                    "application/merge-patch+json, application/json-patch+json",
+                ),
+            );
+            return response;
+        }
+    };
+    let expected = conditional::if_match(req.headers());
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(err) => {
+            return respond(
+                format,
+                StatusCode::BAD_REQUEST,
+                &ErrorBody::new(err.to_string()),
+            )
+        }
+    };
+
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let stored = match user_db.get(id) {
+            Ok(stored) => stored,
+            Err(err) => return store_error_response(format, err),
+        };
+        if let Some(versions) = &expected {
+            if !versions.contains(&stored.version) {
+                return store_error_response(
+                    format,
+                    StoreError::VersionMismatch {
+                        current: stored.version,
+                    },
+                );
+            }
+        }
+
+        // Patch the same document a GET would return
+        let entry = UserEntry {
+            id,
+            user: &stored.user,
+        };
+        let mut doc = serde_json::to_value(&entry).unwrap();
+        if let Err((status, body)) = patch::apply(patch_format, &mut doc, &body) {
+            return respond(format, status, &body);
+        }
+        let input = match serde_json::from_value::<UserInput>(doc) {
+            Ok(input) => input,
+            Err(err) => {
+                let body = ErrorBody::new(err.to_string());
+                return respond(format, StatusCode::UNPROCESSABLE_ENTITY, &body);
+            }
+        };
+        if let Err((status, body)) = check_user_input(&input) {
+            return respond(format, status, &body);
+        }
+
+        // Only store the result if nobody changed the user meanwhile
+        let user = input.into_user_created_at(stored.user.created_at);
+        match user_db.update(id, user.clone(), Some(&[stored.version])) {
+            Ok(version) => {
+                let entry = UserEntry { id, user: &user };
+                return with_etag(respond(format, StatusCode::OK, &entry), version);
+            }
+            Err(StoreError::VersionMismatch { .. })
+                if expected.is_none() && attempts < PATCH_ATTEMPTS =>
+            {
+                continue
+            }
+            Err(err) => return store_error_response(format, err),
+        }
+    }
+}
+
+/// Removes a selected user, honoring `If-Match`.
+async fn delete_user(
+    req: Request<Body>,
+    params: Params,
+    user_db: UserDb,
+    format: Format,
+) -> Response<Body> {
+    let id = user_id(&params);
+    if let Some(denied) = denied(&req, format, Access::Owner(id)) {
+        return denied;
+    }
+    let expected = conditional::if_match(req.headers());
+    match user_db.delete(id, expected.as_deref()) {
+        Ok(()) => response_with_code(StatusCode::OK),
+        Err(err) => store_error_response(format, err),
+    }
+}
+
+/// Checks that the caller may do what the request asks, or builds the
+/// response saying why not.
+fn denied(req: &Request<Body>, format: Format, access: Access) -> Option<Response<Body>> {
+    match Caller::of(req).check(access) {
+        Ok(()) => None,
+        Err(Denied::Unauthenticated) => Some(auth::unauthorized(
+            format,
+            "this needs an Authorization: Bearer token",
+            None,
+        )),
+        Err(Denied::Forbidden) => {
+            let message = match access {
+                Access::Owner(_) => "only the user itself or an admin may do this",
+                Access::Admin => "only admins may do this",
+            };
+            Some(respond(
+                format,
+                StatusCode::FORBIDDEN,
+                &ErrorBody::new(message),
+            ))
+        }
+    }
+}
+
+/// Reads the whole request body as a validated `UserInput`.
+async fn read_user_input(req: Request<Body>) -> Result<UserInput, (StatusCode, ErrorBody)> {
+    let input = read_body::<UserInput>(req).await?;
+    check_user_input(&input)?;
+    Ok(input)
+}
+
+/// Reads and decodes the whole request body according to its
+/// `Content-Type`, or says what status and error body the client should
+/// get instead. See `negotiate::decode`.
+async fn read_body<T: DeserializeOwned>(req: Request<Body>) -> Result<T, (StatusCode, ErrorBody)> {
+    let format = Format::of_body(req.headers())?;
+    let body = hyper::body::to_bytes(req.into_body())
+        .await
+        .map_err(|err| (StatusCode::BAD_REQUEST, ErrorBody::new(err.to_string())))?;
+    negotiate::decode(format, &body)
+}
+
+/// Validates a decoded user, collecting the rejected fields in a 422.
+pub(crate) fn check_user_input(input: &UserInput) -> Result<(), (StatusCode, ErrorBody)> {
+    input.validate().map_err(|fields| {
+        let body = ErrorBody {
+            error: "invalid user".into(),
+            fields,
+        };
+        (StatusCode::UNPROCESSABLE_ENTITY, body)
+    })
+}
+
+/// Turns a failed store operation into a response. Missing users are the
+/// client's problem, anything else is ours.
+fn store_error_response(format: Format, err: StoreError) -> Response<Body> {
+    match err {
+        StoreError::NotFound => response_with_code(StatusCode::NOT_FOUND),
+        StoreError::Gone => response_with_code(StatusCode::GONE),
+        StoreError::VersionMismatch { current } => {
+            let body = ErrorBody::new(format!("user has changed, current version is {}", current));
+            with_etag(
+                respond(format, StatusCode::PRECONDITION_FAILED, &body),
+                current,
+            )
+        }
+        err => respond(
+            format,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &ErrorBody::new(err.to_string()),
+        ),
+    }
+}
+
+/// Tags a response with the version of the user it is about.
+fn with_etag(mut response: Response<Body>, version: Version) -> Response<Body> {
+    response
+        .headers_mut()
+        .insert(ETAG, conditional::etag(version));
+    response
+}
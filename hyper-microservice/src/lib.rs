@@ -0,0 +1,13 @@
+pub mod auth;
+pub mod batch;
+pub mod conditional;
+pub mod handlers;
+pub mod listing;
+pub mod negotiate;
+pub mod openapi;
+pub mod patch;
+pub mod router;
+pub mod server;
+pub mod sessions;
+pub mod store;
+pub mod user;
@@ -0,0 +1,232 @@
+use crate::negotiate::{escape, Render};
+use crate::user::{UserData, UserEntry, UserId};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+
+/// Page size used when the client doesn't ask for one.
+pub const DEFAULT_LIMIT: usize = 20;
+/// Biggest page a client can ask for.
+pub const MAX_LIMIT: usize = 100;
+
+/// Query string accepted by `GET /users`.
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ListQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    /// Keeps users whose name contains this text, ignoring case.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Keeps users whose email contains this text, ignoring case.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_after: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_before: Option<DateTime<Utc>>,
+    /// Field to sort by, prefixed with `-` for descending order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+}
+
+/// Fields users can be sorted by.
+#[derive(Clone, Copy, Debug)]
+enum SortKey {
+    Id,
+    Name,
+    Email,
+    CreatedAt,
+}
+
+/// Links to walk through the collection.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Links {
+    #[serde(rename = "self")]
+    pub current: String,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+}
+
+/// The JSON envelope returned by `GET /users`.
+#[derive(Serialize, JsonSchema)]
+pub struct Page<'a> {
+    pub total: usize,
+    pub limit: usize,
+    pub offset: usize,
+    pub items: Vec<UserEntry<'a>>,
+    pub links: Links,
+}
+
+impl ListQuery {
+    /// Parses and checks a raw query string.
+    pub fn parse(query: Option<&str>) -> Result<Self, String> {
+        let query: ListQuery =
+            serde_urlencoded::from_str(query.unwrap_or("")).map_err(|err| err.to_string())?;
+        if query.limit == Some(0) || query.limit.is_some_and(|limit| limit > MAX_LIMIT) {
+            return Err(format!("limit must be between 1 and {}", MAX_LIMIT));
+        }
+        query.sort_key()?;
+        Ok(query)
+    }
+
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT)
+    }
+
+    fn offset(&self) -> usize {
+        self.offset.unwrap_or(0)
+    }
+
+    /// Reads the `sort` parameter as a key and a descending flag.
+    fn sort_key(&self) -> Result<(SortKey, bool), String> {
+        let sort = self.sort.as_deref().unwrap_or("id");
+        let (field, descending) = if let Some(field) = sort.strip_prefix('-') {
+            (field, true)
+        } else {
+            (sort, false)
+        };
+        let key = match field {
+            "id" => SortKey::Id,
+            "name" => SortKey::Name,
+            "email" => SortKey::Email,
+            "created_at" => SortKey::CreatedAt,
+            other => return Err(format!("can't sort by {}", other)),
+        };
+        Ok((key, descending))
+    }
+
+    fn keeps(&self, user: &UserData) -> bool {
+        let contains = |haystack: &str, needle: &Option<String>| {
+            needle
+                .as_ref()
+                .is_none_or(|needle| haystack.to_lowercase().contains(&needle.to_lowercase()))
+        };
+        contains(&user.name, &self.name)
+            && contains(&user.email, &self.email)
+            && self
+                .created_after
+                .is_none_or(|after| user.created_at > after)
+            && self
+                .created_before
+                .is_none_or(|before| user.created_at < before)
+    }
+
+    /// Builds a link to the same listing starting at another offset.
+    fn link(&self, path: &str, offset: usize) -> String {
+        let query = ListQuery {
+            limit: Some(self.limit()),
+            offset: Some(offset),
+            name: self.name.clone(),
+            email: self.email.clone(),
+            created_after: self.created_after,
+            created_before: self.created_before,
+            sort: self.sort.clone(),
+        };
+        // Only plain strings and numbers, this can't fail
+        format!("{}?{}", path, serde_urlencoded::to_string(&query).unwrap())
+    }
+
+    /// Filters, sorts and slices `users`, which must be ordered by id.
+    pub fn apply<'a>(&self, path: &str, users: &'a [(UserId, UserData)]) -> Page<'a> {
+        let mut matching = users
+            .iter()
+            .filter(|(_, user)| self.keeps(user))
+            .collect::<Vec<_>>();
+
+        // Validated in parse, so this is always Ok
+        let (key, descending) = self.sort_key().unwrap_or((SortKey::Id, false));
+        // sort_by is stable and users come in id order, so ties stay in id order
+        matching.sort_by(|(a_id, a), (b_id, b)| {
+            let ordering = match key {
+                SortKey::Id => a_id.cmp(b_id),
+                SortKey::Name => a.name.cmp(&b.name),
+                SortKey::Email => a.email.cmp(&b.email),
+                SortKey::CreatedAt => a.created_at.cmp(&b.created_at),
+            };
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+
+        let total = matching.len();
+        let limit = self.limit();
+        let offset = self.offset();
+        let items = matching
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|(id, user)| UserEntry { id: *id, user })
+            .collect();
+
+        // Offsets come from clients, so they may be anything up to usize::MAX
+        let next = offset
+            .checked_add(limit)
+            .filter(|end| *end < total)
+            .map(|end| self.link(path, end));
+        let prev = if offset > 0 {
+            Some(self.link(path, offset.saturating_sub(limit)))
+        } else {
+            None
+        };
+
+        Page {
+            total,
+            limit,
+            offset,
+            items,
+            links: Links {
+                current: self.link(path, offset),
+                next,
+                prev,
+            },
+        }
+    }
+}
+
+impl Render for Page<'_> {
+    /// Just the ids of the page, comma separated.
+    fn text(&self) -> String {
+        self.items
+            .iter()
+            .map(|entry| entry.id.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// A table of the page with links to each user and the other pages.
+    fn html(&self) -> String {
+        let mut html = format!(
+            "<h3>Users</h3>\n<p>{} users, showing {} from {}</p>\n<table>\n\
+             <tr><th>Id</th><th>Name</th><th>Email</th><th>Created</th></tr>",
+            self.total,
+            self.items.len(),
+            self.offset
+        );
+        for entry in &self.items {
+            html.push_str(&format!(
+                "\n<tr><td><a href=\"/user/{id}\">{id}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&entry.user.name),
+                escape(&entry.user.email),
+                entry.user.created_at.to_rfc3339(),
+                id = entry.id
+            ));
+        }
+        html.push_str("\n</table>");
+        let links = [("Previous", &self.links.prev), ("Next", &self.links.next)];
+        for (label, link) in links.iter() {
+            if let Some(link) = link {
+                html.push_str(&format!("\n<a href=\"{}\">{}</a>", escape(link), label));
+            }
+        }
+        html
+    }
+
+    fn title(&self) -> String {
+        "Users".into()
+    }
+}
@@ -1,168 +1,269 @@
-use futures::{future, Future};
-use hyper::service::service_fn;
-use hyper::{Body, Error, Method, Request, Response, Server, StatusCode};
-use lazy_static::lazy_static;
-use regex::Regex;
-use slab::Slab;
-use std::fmt;
+use clap::{crate_authors, crate_version, App, Arg};
+use hyper_microservice::auth::Auth;
+use hyper_microservice::handlers::UserDb;
+use hyper_microservice::server::{self, Options};
+use hyper_microservice::sessions::RedisSessions;
+use hyper_microservice::store::{FileStore, MemoryStore};
+use log::{debug, error, info, warn};
+use middleware::rate_limit::{MemoryBuckets, RedisBuckets};
+use middleware::{Quota, RateLimit, Rule};
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
 
-const INDEX: &str = r#"
-<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta charset="UTF-8">
-    <meta name="viewport" content="width=device-width, initial-scale=1.0">
-    <title>Rust Microservice</title>
-</head>
-<body>
-    <h3>Rust Microservice Example</h3>
-</body>
-</html>
-"#;
+const STORE_MEMORY: &str = "memory";
+const STORE_FILE: &str = "file";
+const DEFAULT_STORE_PATH: &str = "users.log";
+const DEFAULT_GRACE_PERIOD: &str = "10";
 
-// Types used for a makeshift database of users
-type UserId = u64;
-// Empty for simplicity, would normally have fields and require serialization
-// for db interaction/REST responses
-struct UserData;
-// Slab seems to be a mix of a hash map and a vector
-type UserDb = Arc<Mutex<Slab<UserData>>>;
+#[tokio::main]
+async fn main() {
+    // Start up the logger implementation, see RUST_LOG
+    pretty_env_logger::init();
 
-lazy_static! {
-    static ref INDEX_PATH: Regex = Regex::new("^/(index\\.html?)?$").unwrap();
-    static ref USER_PATH: Regex = Regex::new("^/user/((?P<user_id>\\d+?)/?)?$").unwrap();
-    static ref USERS_PATH: Regex = Regex::new("^/users/?$").unwrap();
-}
-
-fn main() {
-    // Set up server address
-    let addr: SocketAddr = ([127, 0, 0, 1], 8080).into();
-    let builder = Server::bind(&addr);
+    // Get command line args
+    let matches = App::new("User microservice")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .arg(
+            Arg::with_name("store")
+                .short("s")
+                .long("store")
+                .value_name("BACKEND")
+                .possible_values(&[STORE_MEMORY, STORE_FILE])
+                .default_value(STORE_MEMORY)
+                .help("where users are kept"),
+        )
+        .arg(
+            Arg::with_name("store-path")
+                .long("store-path")
+                .value_name("FILE")
+                .default_value(DEFAULT_STORE_PATH)
+                .help("log file used by the file backend"),
+        )
+        .arg(
+            Arg::with_name("grace-period")
+                .long("grace-period")
+                .value_name("SECONDS")
+                .default_value(DEFAULT_GRACE_PERIOD)
+                .validator(|value| {
+                    value
+                        .parse::<u64>()
+                        .map(drop)
+                        .map_err(|_| "must be a whole number of seconds".to_owned())
+                })
+                .help("how long in-flight requests may take to finish on shutdown"),
+        )
+        .arg(
+            Arg::with_name("sessions")
+                .long("sessions")
+                .value_name("REDIS_URL")
+                .help("checks bearer tokens against the sessions in this redis, e.g. redis://127.0.0.1/"),
+        )
+        .arg(
+            Arg::with_name("admin")
+                .long("admin")
+                .value_name("UID")
+                .multiple(true)
+                .number_of_values(1)
+                .requires("sessions")
+                .validator(|value| {
+                    value
+                        .parse::<u64>()
+                        .map(drop)
+                        .map_err(|_| "must be a user id".to_owned())
+                })
+                .help("lets the user with this id change any user, may be repeated"),
+        )
+        .arg(
+            Arg::with_name("rate-limit")
+                .long("rate-limit")
+                .value_name("QUOTA")
+                .validator(|value| value.parse::<Quota>().map(drop))
+                .help("how often each client may call, e.g. 100/min"),
+        )
+        .arg(
+            Arg::with_name("route-limit")
+                .long("route-limit")
+                .value_name("RULE")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|value| value.parse::<Rule>().map(drop))
+                .help("a quota for some routes instead, e.g. \"POST /users/batch=10/min\", may be repeated"),
+        )
+        .arg(
+            Arg::with_name("rate-limit-redis")
+                .long("rate-limit-redis")
+                .value_name("REDIS_URL")
+                .help("shares rate limits with other instances through this redis"),
+        )
+        .get_matches();
+    let grace_period = Duration::from_secs(
+        matches
+            .value_of("grace-period")
+            .and_then(|value| value.parse().ok())
+            .unwrap(),
+    );
 
     // Setup user DB
-    let user_db = Arc::new(Mutex::new(Slab::new()));
-
-    // Make a server from the builder
-    let server = builder.serve(move || {
-        let user_db = user_db.clone();
-        service_fn(move |req| microservice_handler(req, &user_db))
-    });
-
-    // Drop any errors for simplicity
-    // Maps errors from current type to the drop function
-    let server = server.map_err(drop);
-
-    // Actually runs the server
-    hyper::rt::run(server);
-}
-
-/// Provides functionality to handle HTTP requests to this server.
-fn microservice_handler(
-    req: Request<Body>,
-    user_db: &UserDb,
-) -> impl Future<Item = Response<Body>, Error = Error> {
-    // Match on request method and path
-    let response = {
-        let method = req.method();
-        let path = req.uri().path();
-        // Lock mutex for full scope of response
-        let mut users = user_db.lock().unwrap();
-
-        // Root path: return simple html landing page
-        if INDEX_PATH.is_match(path) {
-            if method == &Method::GET {
-                Response::new(INDEX.into())
-            } else {
-                response_with_code(StatusCode::METHOD_NOT_ALLOWED)
+    let user_db: UserDb = match matches.value_of("store") {
+        Some(STORE_FILE) => {
+            let path = matches.value_of("store-path").unwrap();
+            match FileStore::open(path) {
+                Ok(store) => Arc::new(store),
+                Err(err) => {
+                    error!("Can't open user store {}: {}", path, err);
+                    std::process::exit(1);
+                }
             }
+        }
+        _ => Arc::new(MemoryStore::new()),
+    };
 
-        // All users path
-        } else if USERS_PATH.is_match(path) {
-            if method == &Method::GET {
-                let list = users
-                    .iter()
-                    .map(|(id, _)| id.to_string())
-                    .collect::<Vec<String>>()
-                    .join(",");
-                Response::new(list.into())
-            } else {
-                response_with_code(StatusCode::METHOD_NOT_ALLOWED)
+    // Setup authentication
+    let auth = match matches.value_of("sessions") {
+        Some(url) => match RedisSessions::connect(url) {
+            Ok(sessions) => Some(Auth {
+                sessions: Arc::new(sessions),
+                admins: matches
+                    .values_of("admin")
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|uid| uid.parse().ok())
+                    .collect(),
+            }),
+            Err(err) => {
+                error!("Can't connect to sessions at {}: {}", url, err);
+                std::process::exit(1);
             }
+        },
+        None => {
+            warn!("No --sessions given, anyone may change any user");
+            None
+        }
+    };
 
-        // User REST Requests
-        } else if let Some(cap) = USER_PATH.captures(path) {
-            let user_id = cap.name("user_id").and_then(|m| {
-                m.as_str()
-                    .parse::<UserId>() // get the number as UserId
-                    .ok() // convert the result to an option
-                    .map(|x| x as usize) // convert to usize for Slab (ids are usize)
-            });
-            // Inner match on methods
-            match (method, user_id) {
-                /* POST */
-                // Create new user and return id
-                (&Method::POST, None) => {
-                    let id = users.insert(UserData);
-                    Response::new(id.to_string().into())
-                }
-                // Disallow client to give a user id
-                (&Method::POST, Some(_)) => response_with_code(StatusCode::BAD_REQUEST),
-                /* GET */
-                // Get a user with a given id
-                (&Method::GET, Some(id)) => {
-                    if let Some(data) = users.get(id) {
-                        Response::new(data.to_string().into())
-                    } else {
-                        response_with_code(StatusCode::NOT_FOUND)
-                    }
-                }
-                /* PUT */
-                // Update a user with a given id
-                (&Method::PUT, Some(id)) => {
-                    if let Some(user) = users.get_mut(id) {
-                        // Access and replace
-                        *user = UserData; // would have data in a real server
-                        response_with_code(StatusCode::OK)
-                    } else {
-                        response_with_code(StatusCode::NOT_FOUND)
-                    }
+    // Setup rate limiting, first matching --route-limit wins
+    let quota = matches
+        .value_of("rate-limit")
+        .and_then(|quota| quota.parse().ok());
+    let rules = matches
+        .values_of("route-limit")
+        .into_iter()
+        .flatten()
+        .filter_map(|rule| rule.parse().ok())
+        .collect::<Vec<Rule>>();
+    let rate_limit = if quota.is_none() && rules.is_empty() {
+        if matches.is_present("rate-limit-redis") {
+            warn!("--rate-limit-redis does nothing without --rate-limit or --route-limit");
+        }
+        None
+    } else {
+        let mut limit = match matches.value_of("rate-limit-redis") {
+            Some(url) => match RedisBuckets::connect(url).await {
+                Ok(buckets) => RateLimit::new(buckets),
+                Err(err) => {
+                    error!("Can't connect to rate limits at {}: {}", url, err);
+                    std::process::exit(1);
                 }
-                /* DELETE */
-                // Remove a selected user
-                (&Method::DELETE, Some(id)) => {
-                    if users.contains(id) {
-                        users.remove(id);
-                        response_with_code(StatusCode::OK)
-                    } else {
-                        response_with_code(StatusCode::NOT_FOUND)
-                    }
+            },
+            None => RateLimit::new(MemoryBuckets::new()),
+        };
+        if let Some(quota) = quota {
+            limit = limit.default_quota(quota);
+        }
+        Some(rules.into_iter().fold(limit, RateLimit::route))
+    };
+
+    // Set up server address
+    let addr: SocketAddr = ([127, 0, 0, 1], 8080).into();
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let stopped = async {
+        stop_rx.await.ok();
+    };
+    let server = match server::bind(
+        &addr,
+        user_db.clone(),
+        Options { auth, rate_limit },
+        stopped,
+    ) {
+        Ok((addr, server)) => {
+            info!("Listening on http://{}", addr);
+            server
+        }
+        Err(err) => {
+            error!("Can't bind to {}: {}", addr, err);
+            std::process::exit(1);
+        }
+    };
+
+    // Actually runs the server until it fails or we are asked to stop
+    let mut server = tokio::spawn(server);
+    tokio::select! {
+        result = &mut server => {
+            log_server_result(result);
+        }
+        _ = shutdown_signal() => {
+            info!(
+                "Shutting down, waiting up to {}s for in-flight requests",
+                grace_period.as_secs()
+            );
+            // Stop accepting and let open connections finish
+            let _ = stop_tx.send(());
+            match tokio::time::timeout(grace_period, &mut server).await {
+                Ok(result) => log_server_result(result),
+                Err(_) => {
+                    warn!("Grace period is over, dropping remaining connections");
+                    server.abort();
                 }
-                /* Default */
-                _ => response_with_code(StatusCode::METHOD_NOT_ALLOWED),
             }
-
-        // Nothing else matched: 404
-        } else {
-            response_with_code(StatusCode::NOT_FOUND)
         }
-    }; // end response block
-    future::ok(response)
+    }
+
+    // Whatever happened, don't lose writes
+    match user_db.flush() {
+        Ok(()) => debug!("User store flushed"),
+        Err(err) => error!("Can't flush user store: {}", err),
+    }
 }
 
-/// Creates simple HTTP responses with the given status code.
-fn response_with_code(status_code: StatusCode) -> Response<Body> {
-    Response::builder()
-        .status(status_code)
-        .body(Body::empty())
-        .unwrap()
+/// Logs how the server task ended.
+fn log_server_result(result: Result<Result<(), hyper::Error>, tokio::task::JoinError>) {
+    match result {
+        Ok(Ok(())) => info!("Server stopped"),
+        Ok(Err(err)) => error!("Server error: {}", err),
+        Err(err) => error!("Server task failed: {}", err),
+    }
 }
 
-/// Allow UserData to be represented as a String
-impl fmt::Display for UserData {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // Since the struct is empty, we don't show anything
-        f.write_str("{}")
+/// Resolves on the first SIGINT (Ctrl+C) or SIGTERM.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("Can't listen for SIGINT: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                error!("Can't listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
     }
 }
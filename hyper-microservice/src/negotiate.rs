@@ -0,0 +1,306 @@
+//! Content negotiation: picking the response format from `Accept` and
+//! decoding request bodies according to their `Content-Type`.
+//!
+//! Every response type can be sent as JSON, CBOR or MessagePack for
+//! machines, as plain text, and as a generated HTML page for browsers.
+//! Since all of them offer the same formats, the choice only depends on
+//! the request and is made before a handler runs. Request bodies may be
+//! JSON, CBOR or MessagePack. A body without a `Content-Type` is read as
+//! JSON, like before negotiation was supported.
+
+use crate::user::ErrorBody;
+use hyper::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE, VARY};
+use hyper::{Body, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::error::Category;
+
+/// The representations the service can produce.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Cbor,
+    MessagePack,
+    Html,
+    Text,
+}
+
+/// Our formats in the order we prefer them when the client doesn't care.
+pub(crate) const PREFERRED: [Format; 5] = [
+    Format::Json,
+    Format::Cbor,
+    Format::MessagePack,
+    Format::Html,
+    Format::Text,
+];
+
+/// The formats request bodies may come in.
+pub(crate) const DECODABLE: [Format; 3] = [Format::Json, Format::Cbor, Format::MessagePack];
+
+impl Format {
+    /// The media type sent in `Content-Type`.
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Cbor => "application/cbor",
+            Format::MessagePack => "application/msgpack",
+            Format::Html => "text/html; charset=utf-8",
+            Format::Text => "text/plain; charset=utf-8",
+        }
+    }
+
+    /// Whether a media type without parameters names this format.
+    fn is(self, media_type: &str) -> bool {
+        let names: &[&str] = match self {
+            Format::Json => &["application/json"],
+            Format::Cbor => &["application/cbor"],
+            Format::MessagePack => &[
+                "application/msgpack",
+                "application/x-msgpack",
+                "application/vnd.msgpack",
+            ],
+            Format::Html => &["text/html"],
+            Format::Text => &["text/plain"],
+        };
+        names
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(media_type))
+    }
+
+    /// Picks the response format for a request, or `None` if the client
+    /// accepts nothing we can produce. No `Accept` means JSON.
+    pub fn preferred(headers: &HeaderMap) -> Option<Format> {
+        let accept = match headers.get(ACCEPT).and_then(|value| value.to_str().ok()) {
+            Some(accept) if !accept.trim().is_empty() => accept,
+            _ => return Some(Format::Json),
+        };
+        let ranges = accept
+            .split(',')
+            .filter_map(MediaRange::parse)
+            .collect::<Vec<_>>();
+
+        // Highest quality wins, ties go to the earlier format in PREFERRED
+        let mut best: Option<(Format, f32)> = None;
+        for format in PREFERRED.iter().copied() {
+            let quality = quality(format, &ranges);
+            if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+                best = Some((format, quality));
+            }
+        }
+        best.map(|(format, _)| format)
+    }
+
+    /// Finds out how a request body is encoded. Only the machine formats
+    /// can be decoded.
+    pub fn of_body(headers: &HeaderMap) -> Result<Format, (StatusCode, ErrorBody)> {
+        let content_type = match headers.get(CONTENT_TYPE) {
+            Some(value) => value.to_str().unwrap_or(""),
+            None => return Ok(Format::Json),
+        };
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+        DECODABLE
+            .iter()
+            .copied()
+            .find(|format| format.is(media_type))
+            .ok_or_else(|| {
+                let body = ErrorBody::new(format!(
+                    "can't read {}, send application/json, application/cbor or application/msgpack",
+                    content_type
+                ));
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, body)
+            })
+    }
+}
+
+/// One entry of an `Accept` header, such as `text/*;q=0.5`.
+struct MediaRange<'a> {
+    kind: &'a str,
+    subtype: &'a str,
+    quality: f32,
+}
+
+impl<'a> MediaRange<'a> {
+    /// Parses an entry, skipping the ones that make no sense.
+    fn parse(range: &'a str) -> Option<Self> {
+        let mut parts = range.split(';');
+        let (kind, subtype) = parts.next()?.trim().split_once('/')?;
+        let mut quality = 1.0;
+        for param in parts {
+            if let Some((name, value)) = param.split_once('=') {
+                if name.trim().eq_ignore_ascii_case("q") {
+                    quality = value.trim().parse().ok()?;
+                }
+            }
+        }
+        Some(MediaRange {
+            kind: kind.trim(),
+            subtype: subtype.trim(),
+            quality,
+        })
+    }
+
+    /// How closely the range matches `format`: 3 for an exact match,
+    /// 2 for `type/*`, 1 for `*/*` and 0 for no match at all.
+    fn specificity(&self, format: Format) -> u8 {
+        let media_type = format.media_type().split(';').next().unwrap_or("");
+        let (kind, _) = media_type.split_once('/').unwrap_or((media_type, ""));
+        if self.kind == "*" && self.subtype == "*" {
+            1
+        } else if self.subtype == "*" && self.kind.eq_ignore_ascii_case(kind) {
+            2
+        } else if format.is(&format!("{}/{}", self.kind, self.subtype)) {
+            3
+        } else {
+            0
+        }
+    }
+}
+
+/// The quality the client gave a format, taken from the most specific
+/// range that matches it.
+fn quality(format: Format, ranges: &[MediaRange]) -> f32 {
+    ranges
+        .iter()
+        .map(|range| (range.specificity(format), range.quality))
+        .filter(|(specificity, _)| *specificity > 0)
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map_or(0.0, |(_, quality)| quality)
+}
+
+/// Types that can be sent back to clients. The machine formats come from
+/// `Serialize`, text and HTML are up to each type.
+pub trait Render: Serialize {
+    fn text(&self) -> String;
+    /// The inside of the page `<body>`, see `html_page`.
+    fn html(&self) -> String;
+    /// The title of the HTML page.
+    fn title(&self) -> String;
+}
+
+/// Encodes a value in the given format.
+pub fn encode<T: Render>(format: Format, value: &T) -> Vec<u8> {
+    // Our response types only hold strings, numbers and maps, which every
+    // format can represent, so encoding can't fail
+    match format {
+        Format::Json => serde_json::to_vec(value).unwrap(),
+        Format::Cbor => {
+            let mut bytes = Vec::new();
+            ciborium::ser::into_writer(value, &mut bytes).unwrap();
+            bytes
+        }
+        // Named, so structs become maps instead of bare arrays
+        Format::MessagePack => rmp_serde::to_vec_named(value).unwrap(),
+        Format::Html => html_page(&value.title(), &value.html()).into_bytes(),
+        Format::Text => value.text().into_bytes(),
+    }
+}
+
+/// Creates an HTTP response with the value encoded in the given format.
+pub fn respond<T: Render>(format: Format, status_code: StatusCode, value: &T) -> Response<Body> {
+    Response::builder()
+        .status(status_code)
+        .header(CONTENT_TYPE, HeaderValue::from_static(format.media_type()))
+        .header(VARY, HeaderValue::from_static("Accept"))
+        .body(encode(format, value).into())
+        .unwrap()
+}
+
+/// The 406 for clients that accept nothing we produce. It lists what we
+/// do produce, in plain text since the client won't take anything better.
+pub fn not_acceptable() -> Response<Body> {
+    let types = PREFERRED
+        .iter()
+        .map(|format| format.media_type().split(';').next().unwrap_or(""))
+        .collect::<Vec<_>>();
+    Response::builder()
+        .status(StatusCode::NOT_ACCEPTABLE)
+        .header(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; charset=utf-8"),
+        )
+        .header(VARY, HeaderValue::from_static("Accept"))
+        .body(format!("available types: {}\n", types.join(", ")).into())
+        .unwrap()
+}
+
+/// Decodes a request body in the given format. Bytes that aren't valid in
+/// the format get a 400, well formed but unusable data a 422.
+pub fn decode<T: DeserializeOwned>(
+    format: Format,
+    body: &[u8],
+) -> Result<T, (StatusCode, ErrorBody)> {
+    let (is_data_error, message) = match format {
+        Format::Json => match serde_json::from_slice(body) {
+            Ok(value) => return Ok(value),
+            Err(err) => (err.classify() == Category::Data, err.to_string()),
+        },
+        Format::Cbor => match ciborium::de::from_reader(body) {
+            Ok(value) => return Ok(value),
+            Err(err) => (
+                matches!(err, ciborium::de::Error::Semantic(..)),
+                err.to_string(),
+            ),
+        },
+        Format::MessagePack => match rmp_serde::from_slice(body) {
+            Ok(value) => return Ok(value),
+            Err(err) => {
+                use rmp_serde::decode::Error;
+                let broken = matches!(
+                    err,
+                    Error::InvalidMarkerRead(_)
+                        | Error::InvalidDataRead(_)
+                        | Error::DepthLimitExceeded
+                );
+                (!broken, err.to_string())
+            }
+        },
+        Format::Html | Format::Text => {
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                ErrorBody::new("request bodies can't be text"),
+            ))
+        }
+    };
+    let status = if is_data_error {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    Err((status, ErrorBody::new(message)))
+}
+
+/// Wraps the body of a generated page in a complete HTML document.
+pub fn html_page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>{}</title>
+</head>
+<body>
+{}
+</body>
+</html>
+"#,
+        escape(title),
+        body
+    )
+}
+
+/// Escapes text for use in HTML content and attribute values.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
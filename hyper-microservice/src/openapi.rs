@@ -0,0 +1,346 @@
+//! The OpenAPI 3.1 document served at `/openapi.json`.
+//!
+//! Each route is documented by the `Operation` registered right after it
+//! in `handlers::routes`, and the document is built by walking the router.
+//! That way no route can be left out, and path parameters come from the
+//! patterns themselves. Schemas are derived from the request and response
+//! types with `schemars`, so they follow the serde attributes. The tests
+//! in `tests/openapi.rs` check real responses against the document.
+
+use crate::negotiate::{Format, DECODABLE, PREFERRED};
+use crate::router::Router;
+use crate::user::ErrorBody;
+use schemars::generate::SchemaSettings;
+use schemars::{JsonSchema, Schema, SchemaGenerator};
+use serde_json::{json, Map, Value};
+
+pub const OPENAPI_VERSION: &str = "3.1.0";
+
+/// Where the schemas of named types end up in the document.
+const SCHEMAS_PATH: &str = "#/components/schemas";
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+
+fn schema_of<T: JsonSchema>(generator: &mut SchemaGenerator) -> Schema {
+    generator.subschema_for::<T>()
+}
+
+enum RequestBody {
+    /// Any of the formats `negotiate` decodes.
+    Data(SchemaFn),
+    /// A JSON Merge Patch or a JSON Patch.
+    Patch,
+}
+
+enum Content {
+    None,
+    /// Negotiated like every response, see `negotiate`.
+    Negotiated(SchemaFn),
+    /// Always the same media type.
+    Fixed(&'static str),
+}
+
+struct Reply {
+    status: u16,
+    description: &'static str,
+    content: Content,
+}
+
+/// Describes one route.
+pub struct Operation {
+    summary: &'static str,
+    query: Option<SchemaFn>,
+    headers: Vec<(&'static str, &'static str)>,
+    body: Option<RequestBody>,
+    replies: Vec<Reply>,
+    authenticated: bool,
+}
+
+impl Operation {
+    pub fn new(summary: &'static str) -> Self {
+        Operation {
+            summary,
+            query: None,
+            headers: Vec::new(),
+            body: None,
+            replies: Vec::new(),
+            authenticated: false,
+        }
+    }
+
+    /// Documents the query string, one parameter per field of `T`.
+    pub fn query<T: JsonSchema>(mut self) -> Self {
+        self.query = Some(schema_of::<T>);
+        self
+    }
+
+    /// Documents a request header.
+    pub fn header(mut self, name: &'static str, description: &'static str) -> Self {
+        self.headers.push((name, description));
+        self
+    }
+
+    /// Documents the request body, in any format that can be decoded.
+    pub fn body<T: JsonSchema>(mut self) -> Self {
+        self.body = Some(RequestBody::Data(schema_of::<T>));
+        self
+    }
+
+    /// Documents a patch document as the request body.
+    pub fn patch_body(mut self) -> Self {
+        self.body = Some(RequestBody::Patch);
+        self
+    }
+
+    /// Documents a response carrying a `T`.
+    pub fn reply<T: JsonSchema>(mut self, status: u16, description: &'static str) -> Self {
+        self.replies.push(Reply {
+            status,
+            description,
+            content: Content::Negotiated(schema_of::<T>),
+        });
+        self
+    }
+
+    /// Documents a response that is always `media_type`.
+    pub fn fixed_reply(
+        mut self,
+        status: u16,
+        description: &'static str,
+        media_type: &'static str,
+    ) -> Self {
+        self.replies.push(Reply {
+            status,
+            description,
+            content: Content::Fixed(media_type),
+        });
+        self
+    }
+
+    /// Documents a response with an `ErrorBody`.
+    pub fn error(self, status: u16, description: &'static str) -> Self {
+        self.reply::<ErrorBody>(status, description)
+    }
+
+    /// Documents a response without a body.
+    pub fn status(mut self, status: u16, description: &'static str) -> Self {
+        self.replies.push(Reply {
+            status,
+            description,
+            content: Content::None,
+        });
+        self
+    }
+
+    /// Marks the route as needing a bearer token when authentication is on.
+    pub fn authenticated(mut self) -> Self {
+        self.authenticated = true;
+        self.error(401, "No or unknown bearer token")
+            .error(403, "The caller may not do this")
+    }
+
+    fn to_json(&self, requests: &mut SchemaGenerator, responses: &mut SchemaGenerator) -> Value {
+        let mut operation = Map::new();
+        operation.insert("summary".into(), self.summary.into());
+
+        let mut parameters = Vec::new();
+        if let Some(query) = self.query {
+            parameters.extend(query_parameters(requests, query));
+        }
+        for (name, description) in &self.headers {
+            parameters.push(json!({
+                "name": name,
+                "in": "header",
+                "description": description,
+                "schema": {"type": "string"},
+            }));
+        }
+        if !parameters.is_empty() {
+            operation.insert("parameters".into(), parameters.into());
+        }
+
+        match self.body {
+            Some(RequestBody::Data(schema)) => {
+                let schema = Value::from(schema(requests));
+                let content = DECODABLE
+                    .iter()
+                    .map(|format| (media_type(*format), json!({ "schema": schema })))
+                    .collect::<Map<_, _>>();
+                operation.insert(
+                    "requestBody".into(),
+                    json!({"required": true, "content": content}),
+                );
+            }
+            Some(RequestBody::Patch) => {
+                operation.insert(
+                    "requestBody".into(),
+                    json!({"required": true, "content": patch_content()}),
+                );
+            }
+            None => {}
+        }
+
+        let mut replies = Map::new();
+        for reply in &self.replies {
+            let mut response = json!({ "description": reply.description });
+            match reply.content {
+                Content::None => {}
+                Content::Negotiated(schema) => {
+                    let schema = Value::from(schema(responses));
+                    let content = PREFERRED
+                        .iter()
+                        .map(|format| {
+                            let schema = match format {
+                                Format::Html | Format::Text => json!({"type": "string"}),
+                                _ => schema.clone(),
+                            };
+                            (media_type(*format), json!({ "schema": schema }))
+                        })
+                        .collect::<Map<_, _>>();
+                    response["content"] = content.into();
+                }
+                Content::Fixed(media_type) => {
+                    response["content"] = json!({ media_type: {} });
+                }
+            }
+            replies.insert(reply.status.to_string(), response);
+        }
+        operation.insert("responses".into(), replies.into());
+
+        if self.authenticated {
+            operation.insert("security".into(), json!([{"bearer": []}]));
+        }
+        operation.into()
+    }
+}
+
+/// The media type of a format, without parameters.
+fn media_type(format: Format) -> String {
+    let media_type = format.media_type();
+    media_type
+        .split(';')
+        .next()
+        .unwrap_or(media_type)
+        .to_owned()
+}
+
+/// Turns the fields of a query struct into query parameters.
+fn query_parameters(generator: &mut SchemaGenerator, query: SchemaFn) -> Vec<Value> {
+    // Named types come back as a reference, the schema itself is only
+    // needed here so it is taken out of the shared ones
+    let schema = Value::from(query(generator));
+    let schema = match schema.get("$ref").and_then(Value::as_str) {
+        Some(reference) => {
+            let name = reference.rsplit('/').next().unwrap_or_default();
+            generator.definitions_mut().remove(name).unwrap_or_default()
+        }
+        None => schema,
+    };
+    let required = schema["required"].as_array().cloned().unwrap_or_default();
+    let properties = schema["properties"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+    properties
+        .into_iter()
+        .map(|(name, mut schema)| {
+            let description = schema
+                .as_object_mut()
+                .and_then(|schema| schema.remove("description"));
+            let mut parameter = json!({
+                "name": name,
+                "in": "query",
+                "required": required.contains(&Value::from(name.clone())),
+                "schema": schema,
+            });
+            if let Some(description) = description {
+                parameter["description"] = description;
+            }
+            parameter
+        })
+        .collect()
+}
+
+/// The two patch formats `PATCH /user/{id}` takes, see `patch`.
+fn patch_content() -> Value {
+    json!({
+        "application/merge-patch+json": {
+            "schema": {"type": "object"},
+        },
+        "application/json-patch+json": {
+            "schema": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "op": {"enum": ["add", "remove", "replace", "move", "copy", "test"]},
+                        "path": {"type": "string"},
+                        "from": {"type": "string"},
+                        "value": {},
+                    },
+                    "required": ["op", "path"],
+                },
+            },
+        },
+    })
+}
+
+/// Builds the whole document from the routes of a router.
+pub fn spec<S>(router: &Router<S>) -> Value {
+    let settings = SchemaSettings::draft2020_12().with(|settings| {
+        settings.definitions_path = SCHEMAS_PATH.into();
+        settings.meta_schema = None;
+    });
+    // Bodies are read and replies written, which may differ for one type.
+    // Since no type is used both ways, the schemas can share one namespace.
+    let mut requests = settings.clone().for_deserialize().into_generator();
+    let mut responses = settings.for_serialize().into_generator();
+
+    let mut paths = Map::new();
+    for endpoint in router.endpoints() {
+        let mut operation = match endpoint.doc {
+            Some(doc) => doc.to_json(&mut requests, &mut responses),
+            // An empty operation makes the tests complain
+            None => json!({}),
+        };
+        if !endpoint.params.is_empty() {
+            let params = endpoint.params.iter().map(|(name, kind)| {
+                let schema = match *kind {
+                    "u64" => json!({"type": "integer", "format": "uint64", "minimum": 0}),
+                    _ => json!({"type": "string"}),
+                };
+                json!({"name": name, "in": "path", "required": true, "schema": schema})
+            });
+            let mut parameters = params.collect::<Vec<_>>();
+            if let Some(Value::Array(others)) = operation.get("parameters") {
+                parameters.extend(others.iter().cloned());
+            }
+            operation["parameters"] = parameters.into();
+        }
+        let path = paths.entry(endpoint.path).or_insert_with(|| json!({}));
+        path[endpoint.method.as_str().to_lowercase()] = operation;
+    }
+
+    let mut schemas = requests.take_definitions(true);
+    schemas.extend(responses.take_definitions(true));
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": "Rust Microservice Example",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Users with ids, names and emails. Every response can be \
+                            JSON, CBOR, MessagePack, HTML or plain text, see Accept.",
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "Only checked when the service runs with --sessions",
+                },
+            },
+        },
+    })
+}
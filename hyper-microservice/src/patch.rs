@@ -0,0 +1,79 @@
+//! Partial updates of users with `PATCH`.
+//!
+//! Two patch formats are understood, picked by the request's `Content-Type`:
+//! * `application/merge-patch+json`, JSON Merge Patch (RFC 7396),
+//! * `application/json-patch+json`, JSON Patch (RFC 6902).
+//!
+//! Patches apply to the same document `GET /user/{id}` returns. The
+//! read-only `id` and `created_at` fields may be tested but not changed.
+
+use crate::user::ErrorBody;
+use hyper::StatusCode;
+use json_patch::Patch;
+use serde_json::Value;
+
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+pub const JSON_PATCH: &str = "application/json-patch+json";
+
+/// Fields clients can't change.
+const READ_ONLY: &[&str] = &["id", "created_at"];
+
+/// The supported patch formats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PatchFormat {
+    Merge,
+    Json,
+}
+
+impl PatchFormat {
+    /// Picks the format from a `Content-Type`, ignoring any parameters.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        if mime.eq_ignore_ascii_case(MERGE_PATCH) {
+            Some(PatchFormat::Merge)
+        } else if mime.eq_ignore_ascii_case(JSON_PATCH) {
+            Some(PatchFormat::Json)
+        } else {
+            None
+        }
+    }
+}
+
+/// Applies a raw patch body to `doc`, or says what status and error body
+/// the client should get. `doc` is left alone when the patch fails.
+pub fn apply(
+    format: PatchFormat,
+    doc: &mut Value,
+    body: &[u8],
+) -> Result<(), (StatusCode, ErrorBody)> {
+    let bad_request =
+        |err: serde_json::Error| (StatusCode::BAD_REQUEST, ErrorBody::new(err.to_string()));
+    let mut patched = doc.clone();
+    match format {
+        PatchFormat::Merge => {
+            let patch = serde_json::from_slice::<Value>(body).map_err(bad_request)?;
+            json_patch::merge(&mut patched, &patch);
+        }
+        PatchFormat::Json => {
+            let patch = serde_json::from_slice::<Patch>(body).map_err(bad_request)?;
+            json_patch::patch(&mut patched, &patch.0).map_err(|err| {
+                // A failed `test` means the user isn't in the state the
+                // client thought, anything else is a patch that can't apply
+                let status = match err.kind {
+                    json_patch::PatchErrorKind::TestFailed => StatusCode::CONFLICT,
+                    _ => StatusCode::UNPROCESSABLE_ENTITY,
+                };
+                (status, ErrorBody::new(err.to_string()))
+            })?;
+        }
+    }
+
+    for field in READ_ONLY {
+        if patched.get(field) != doc.get(field) {
+            let body = ErrorBody::new(format!("{} can't be changed", field));
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, body));
+        }
+    }
+    *doc = patched;
+    Ok(())
+}
@@ -0,0 +1,331 @@
+//! A tiny router: routes are registered as method + path pattern -> handler.
+//!
+//! Patterns are split on `/`, and each segment is either a literal or a
+//! typed parameter written as `{name:type}`. Supported types are `u64`
+//! (digits only) and `str` (any non-empty segment), `{name}` is short
+//! for `{name:str}`. Trailing slashes are ignored, so `/users` and
+//! `/users/` are the same path.
+//!
+//! On top of dispatching, the router answers for itself:
+//! * unknown paths get a 404,
+//! * known paths with an unregistered method get a 405 with an `Allow` header,
+//! * `HEAD` is served by the `GET` handler,
+//! * `OPTIONS` lists the allowed methods.
+//!
+//! Routes can be documented with `Router::doc`, see `openapi`.
+
+use crate::openapi::Operation;
+use hyper::header::{HeaderValue, ALLOW};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use middleware::MatchedRoute;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+
+/// What every handler eventually produces.
+pub type ResponseFuture = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
+
+/// A request handler with access to shared state `S`.
+type Handler<S> = Box<dyn Fn(Request<Body>, Params, S) -> ResponseFuture + Send + Sync>;
+
+/// The kinds of values a path parameter may hold.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ParamType {
+    U64,
+    Str,
+}
+
+impl ParamType {
+    fn name(self) -> &'static str {
+        match self {
+            ParamType::U64 => "u64",
+            ParamType::Str => "str",
+        }
+    }
+
+    fn accepts(self, value: &str) -> bool {
+        match self {
+            ParamType::U64 => value.parse::<u64>().is_ok(),
+            ParamType::Str => !value.is_empty(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param { name: String, kind: ParamType },
+}
+
+/// A parsed path pattern such as `/user/{id:u64}`.
+#[derive(Clone, Debug, PartialEq)]
+struct Pattern {
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    /// Parses a pattern, panicking on malformed ones since they are
+    /// written by us, not by clients.
+    fn parse(pattern: &str) -> Self {
+        let segments = split_path(pattern)
+            .map(|segment| {
+                if segment.starts_with('{') && segment.ends_with('}') {
+                    let inner = &segment[1..segment.len() - 1];
+                    let (name, kind) = match inner.find(':') {
+                        Some(idx) => (&inner[..idx], &inner[idx + 1..]),
+                        None => (inner, "str"),
+                    };
+                    let kind = match kind {
+                        "u64" => ParamType::U64,
+                        "str" => ParamType::Str,
+                        other => panic!("unknown parameter type {} in {}", other, pattern),
+                    };
+                    Segment::Param {
+                        name: name.to_owned(),
+                        kind,
+                    }
+                } else {
+                    Segment::Literal(segment.to_owned())
+                }
+            })
+            .collect();
+        Pattern { segments }
+    }
+
+    /// The pattern written the OpenAPI way, as in `/user/{id}`.
+    fn template(&self) -> String {
+        let segments = self.segments.iter().map(|segment| match segment {
+            Segment::Literal(literal) => literal.clone(),
+            Segment::Param { name, .. } => format!("{{{}}}", name),
+        });
+        format!("/{}", segments.collect::<Vec<_>>().join("/"))
+    }
+
+    /// Checks a request path against the pattern, collecting parameters.
+    fn matches(&self, path: &str) -> Option<Params> {
+        let mut params = Params::default();
+        let mut parts = split_path(path);
+        for segment in &self.segments {
+            let part = parts.next()?;
+            match segment {
+                Segment::Literal(literal) => {
+                    if literal != part {
+                        return None;
+                    }
+                }
+                Segment::Param { name, kind } => {
+                    if !kind.accepts(part) {
+                        return None;
+                    }
+                    params.values.insert(name.clone(), part.to_owned());
+                }
+            }
+        }
+        // Every part of the path has to be used up
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(params)
+    }
+}
+
+/// Splits a path into its non-empty segments, so `/`, `//` and `` are
+/// all the root and trailing slashes don't matter.
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+/// Parameters captured from the request path.
+#[derive(Debug, Default)]
+pub struct Params {
+    values: HashMap<String, String>,
+}
+
+impl Params {
+    /// Gets a parameter converted to the wanted type.
+    pub fn get<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.values.get(name).and_then(|value| value.parse().ok())
+    }
+}
+
+struct Route<S> {
+    method: Method,
+    pattern: Pattern,
+    /// The pattern as written in docs and metrics, see `Pattern::template`.
+    template: String,
+    handler: Handler<S>,
+    doc: Option<Operation>,
+}
+
+/// A registered route, as far as documentation is concerned.
+pub struct Endpoint<'a> {
+    pub method: &'a Method,
+    /// The path with parameters written as `{name}`.
+    pub path: String,
+    /// Name and type (`u64` or `str`) of every path parameter.
+    pub params: Vec<(&'a str, &'static str)>,
+    pub doc: Option<&'a Operation>,
+}
+
+/// Maps requests to handlers.
+pub struct Router<S> {
+    routes: Vec<Route<S>>,
+}
+
+impl<S> Default for Router<S> {
+    fn default() -> Self {
+        Router { routes: Vec::new() }
+    }
+}
+
+impl<S> Router<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a handler for a method and path pattern.
+    /// Handlers are async functions that get their own copy of the state.
+    pub fn route<F, Fut>(&mut self, method: Method, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(Request<Body>, Params, S) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<Body>> + Send + 'static,
+    {
+        let pattern = Pattern::parse(pattern);
+        self.routes.push(Route {
+            method,
+            template: pattern.template(),
+            pattern,
+            handler: Box::new(move |req, params, state| Box::pin(handler(req, params, state))),
+            doc: None,
+        });
+        self
+    }
+
+    /// Documents the route registered last.
+    pub fn doc(&mut self, operation: Operation) -> &mut Self {
+        let route = self.routes.last_mut().expect("doc() follows a route");
+        route.doc = Some(operation);
+        self
+    }
+
+    /// Lists the registered routes in order.
+    pub fn endpoints(&self) -> impl Iterator<Item = Endpoint<'_>> {
+        self.routes.iter().map(|route| Endpoint {
+            method: &route.method,
+            path: route.template.clone(),
+            params: route
+                .pattern
+                .segments
+                .iter()
+                .filter_map(|segment| match segment {
+                    Segment::Param { name, kind } => Some((name.as_str(), kind.name())),
+                    Segment::Literal(_) => None,
+                })
+                .collect(),
+            doc: route.doc.as_ref(),
+        })
+    }
+
+    pub fn get<F, Fut>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(Request<Body>, Params, S) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<Body>> + Send + 'static,
+    {
+        self.route(Method::GET, pattern, handler)
+    }
+
+    pub fn post<F, Fut>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(Request<Body>, Params, S) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<Body>> + Send + 'static,
+    {
+        self.route(Method::POST, pattern, handler)
+    }
+
+    pub fn put<F, Fut>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(Request<Body>, Params, S) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<Body>> + Send + 'static,
+    {
+        self.route(Method::PUT, pattern, handler)
+    }
+
+    pub fn patch<F, Fut>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(Request<Body>, Params, S) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<Body>> + Send + 'static,
+    {
+        self.route(Method::PATCH, pattern, handler)
+    }
+
+    pub fn delete<F, Fut>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(Request<Body>, Params, S) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<Body>> + Send + 'static,
+    {
+        self.route(Method::DELETE, pattern, handler)
+    }
+
+    /// Finds the handler for a request and runs it. The response is
+    /// tagged with the `MatchedRoute` for metrics.
+    pub fn dispatch(&self, req: Request<Body>, state: S) -> ResponseFuture {
+        let path = req.uri().path().to_owned();
+        let mut allowed = Vec::new();
+
+        for route in &self.routes {
+            if let Some(params) = route.pattern.matches(&path) {
+                // HEAD is answered by GET, hyper leaves out the body for us
+                if route.method == req.method()
+                    || (route.method == Method::GET && req.method() == Method::HEAD)
+                {
+                    let response = (route.handler)(req, params, state);
+                    let matched = MatchedRoute(route.template.clone());
+                    return Box::pin(async move {
+                        let mut response = response.await;
+                        response.extensions_mut().insert(matched);
+                        response
+                    });
+                }
+                allowed.push(route.method.clone());
+            }
+        }
+
+        // Nothing matched the path: 404
+        if allowed.is_empty() {
+            return Box::pin(async { response_with_code(StatusCode::NOT_FOUND) });
+        }
+
+        let allow = allow_header(allowed);
+        let status = if req.method() == Method::OPTIONS {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::METHOD_NOT_ALLOWED
+        };
+        let mut response = response_with_code(status);
+        response.headers_mut().insert(ALLOW, allow);
+        Box::pin(async { response })
+    }
+}
+
+/// Builds the `Allow` header value from the methods registered for a path,
+/// adding the ones the router handles on its own.
+fn allow_header(mut methods: Vec<Method>) -> HeaderValue {
+    if methods.contains(&Method::GET) {
+        methods.push(Method::HEAD);
+    }
+    methods.push(Method::OPTIONS);
+    let mut names = methods.iter().map(Method::as_str).collect::<Vec<&str>>();
+    names.sort_unstable();
+    names.dedup();
+    // Method names are always valid header characters
+    HeaderValue::from_str(&names.join(", ")).unwrap()
+}
+
+/// Creates simple HTTP responses with the given status code.
+pub fn response_with_code(status_code: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status_code)
+        .body(Body::empty())
+        .unwrap()
+}
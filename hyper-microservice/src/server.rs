@@ -0,0 +1,123 @@
+use crate::auth::{self, Auth, Authenticate};
+use crate::handlers::{self, UserDb};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::Server;
+use log::error;
+use middleware::prometheus::{IntGauge, Registry};
+use middleware::{
+    AccessLog, CatchPanic, Cors, Health, Metrics, Pipeline, RateLimit, RequestIds, Timing,
+};
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Optional parts of the service, all off by default.
+#[derive(Default)]
+pub struct Options {
+    /// Checks bearer tokens and who may change what. Without it anyone
+    /// may change any user.
+    pub auth: Option<Auth>,
+    /// Limits how often clients may call. Signed in users are limited by
+    /// who they are, everyone else by IP, whatever key it was given.
+    /// Failed sign ins are limited by IP with the same quotas as well.
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// Request metrics plus the number of stored users, counted when scraped.
+fn metrics(user_db: UserDb) -> Metrics {
+    let registry = Registry::new();
+    let users = IntGauge::new("users_stored", "Users in the store").unwrap();
+    // Both names are unique and valid
+    registry.register(Box::new(users.clone())).unwrap();
+    Metrics::new(registry)
+        .unwrap()
+        .on_scrape(move || match user_db.count() {
+            Ok(count) => users.set(count as i64),
+            Err(err) => error!("Can't count users for metrics: {}", err),
+        })
+}
+
+/// Readiness checks for the store and whatever redis is in use.
+fn health(user_db: &UserDb, auth: Option<&Auth>, rate_limit: Option<&RateLimit>) -> Health {
+    let store = user_db.clone();
+    let mut health = Health::new().check("store", move || {
+        let store = store.clone();
+        blocking(move || store.check())
+    });
+    if let Some(auth) = auth {
+        let sessions = auth.sessions.clone();
+        health = health.check("sessions", move || {
+            let sessions = sessions.clone();
+            blocking(move || sessions.check())
+        });
+    }
+    if let Some(limit) = rate_limit {
+        health = health.check("rate limits", limit.ping());
+    }
+    health
+}
+
+/// Runs a check that blocks off the async workers.
+async fn blocking<F, E>(check: F) -> Result<(), String>
+where
+    F: FnOnce() -> Result<(), E> + Send + 'static,
+    E: Display + Send + 'static,
+{
+    match tokio::task::spawn_blocking(check).await {
+        Ok(result) => result.map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Binds the service to `addr` and returns the address actually used
+/// (handy with port 0) together with the future that runs the server.
+/// Once `shutdown` resolves the server stops accepting connections, and
+/// the returned future completes when the in-flight requests are done.
+/// Must be called from within a tokio runtime.
+pub fn bind<F>(
+    addr: &SocketAddr,
+    user_db: UserDb,
+    options: Options,
+    shutdown: F,
+) -> Result<(SocketAddr, impl Future<Output = Result<(), hyper::Error>>), hyper::Error>
+where
+    F: Future<Output = ()>,
+{
+    // All connections share one routing table behind the usual middlewares
+    let router = Arc::new(handlers::routes());
+    let health = health(&user_db, options.auth.as_ref(), options.rate_limit.as_ref());
+    let mut pipeline = Pipeline::new()
+        .with(metrics(user_db.clone()))
+        // Probes come often, keep them out of the access log
+        .with(health)
+        .with(RequestIds)
+        .with(CatchPanic)
+        .with(AccessLog)
+        .with(Timing)
+        .with(Cors::any());
+    // Checking a token costs a session lookup, so failed ones are limited
+    // by IP ahead of Authenticate, or tokens could be guessed at any speed
+    if let Some(limit) = &options.rate_limit {
+        pipeline = pipeline.with(limit.stage("auth").charge_only(auth::failed));
+    }
+    pipeline = pipeline.with(Authenticate::new(options.auth));
+    // Behind Authenticate so it knows who is calling
+    if let Some(limit) = options.rate_limit {
+        pipeline = pipeline.with(limit.key_by(auth::rate_limit_key));
+    }
+    let stack = pipeline.handler(move |req| router.dispatch(req, user_db.clone()));
+    let builder = Server::try_bind(addr)?;
+
+    // Make a server from the builder
+    let server = builder.serve(make_service_fn(move |conn: &AddrStream| {
+        let stack = stack.clone();
+        let client = conn.remote_addr();
+        async move { Ok::<_, Infallible>(service_fn(move |req| stack.serve(client, req))) }
+    }));
+
+    let addr = server.local_addr();
+    Ok((addr, server.with_graceful_shutdown(shutdown)))
+}
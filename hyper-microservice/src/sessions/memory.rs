@@ -0,0 +1,32 @@
+use super::{SessionError, SessionStore};
+use crate::user::UserId;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Keeps sessions in memory, a stand-in for redis in tests and local runs.
+#[derive(Default)]
+pub struct MemorySessions {
+    sessions: RwLock<HashMap<String, UserId>>,
+}
+
+impl MemorySessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lets `token` act as the user `uid`.
+    pub fn insert(&self, token: impl Into<String>, uid: UserId) {
+        self.sessions.write().unwrap().insert(token.into(), uid);
+    }
+
+    /// Ends the session of `token`.
+    pub fn remove(&self, token: &str) {
+        self.sessions.write().unwrap().remove(token);
+    }
+}
+
+impl SessionStore for MemorySessions {
+    fn lookup(&self, token: &str) -> Result<Option<UserId>, SessionError> {
+        Ok(self.sessions.read().unwrap().get(token).copied())
+    }
+}
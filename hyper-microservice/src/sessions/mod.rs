@@ -0,0 +1,40 @@
+//! Where bearer tokens are looked up.
+//!
+//! Sessions are shared with the `users-nosql` tool in `redis/`, which
+//! keeps them in the `sessions` hash as token -> uid.
+
+mod memory;
+mod redis;
+
+pub use self::memory::MemorySessions;
+pub use self::redis::RedisSessions;
+
+use crate::user::UserId;
+use std::error::Error;
+use std::fmt;
+
+/// The redis hash holding the sessions.
+pub const SESSIONS: &str = "sessions";
+
+/// Resolves session tokens to the users they belong to.
+pub trait SessionStore: Send + Sync {
+    /// Finds the user a token belongs to, `None` for unknown tokens.
+    fn lookup(&self, token: &str) -> Result<Option<UserId>, SessionError>;
+
+    /// Checks the backend can be reached, for readiness probes.
+    fn check(&self) -> Result<(), SessionError> {
+        Ok(())
+    }
+}
+
+/// The session backend couldn't be asked.
+#[derive(Debug)]
+pub struct SessionError(pub String);
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "session store error: {}", self.0)
+    }
+}
+
+impl Error for SessionError {}
@@ -0,0 +1,55 @@
+use super::{SessionError, SessionStore, SESSIONS};
+use crate::user::UserId;
+use log::warn;
+use redis::Commands;
+use std::time::Duration;
+
+/// Reads sessions from the redis `sessions` hash through a connection pool.
+pub struct RedisSessions {
+    pool: r2d2::Pool<redis::Client>,
+}
+
+impl RedisSessions {
+    /// Sets up a pool for the redis server at `url`, such as
+    /// `redis://127.0.0.1/`. Only the url is checked here, connections
+    /// are made when needed, so the service can start before redis does.
+    pub fn connect(url: &str) -> Result<Self, SessionError> {
+        let client = redis::Client::open(url).map_err(|err| SessionError(err.to_string()))?;
+        let pool = r2d2::Pool::builder()
+            // Don't keep requests waiting long when redis is down
+            .connection_timeout(Duration::from_secs(2))
+            .build_unchecked(client);
+        Ok(RedisSessions { pool })
+    }
+}
+
+impl SessionStore for RedisSessions {
+    fn lookup(&self, token: &str) -> Result<Option<UserId>, SessionError> {
+        let mut conn = self
+            .pool
+            .get()
+            .map_err(|err| SessionError(err.to_string()))?;
+        let uid: Option<String> = conn
+            .hget(SESSIONS, token)
+            .map_err(|err| SessionError(err.to_string()))?;
+        // The hash is written by hand with users-nosql, so don't trust it
+        Ok(uid.and_then(|uid| match uid.parse() {
+            Ok(uid) => Some(uid),
+            Err(_) => {
+                warn!("Ignoring session with bad uid {:?}", uid);
+                None
+            }
+        }))
+    }
+
+    fn check(&self) -> Result<(), SessionError> {
+        let mut conn = self
+            .pool
+            .get()
+            .map_err(|err| SessionError(err.to_string()))?;
+        redis::cmd("PING")
+            .query::<String>(&mut *conn)
+            .map(drop)
+            .map_err(|err| SessionError(err.to_string()))
+    }
+}
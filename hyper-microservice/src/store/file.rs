@@ -0,0 +1,219 @@
+use super::table::UserTable;
+use super::{Applied, BatchOutcome, Change, StoreError, StoredUser, UserStore, Version};
+use crate::user::{UserData, UserId};
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// One line of the append-only log.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum LogEntry {
+    Insert { id: UserId, user: UserData },
+    Update { id: UserId, user: UserData },
+    Delete { id: UserId },
+}
+
+/// Persists users in an append-only file with one JSON operation per line.
+/// The log is replayed into memory on startup. Because the table hands
+/// out ids deterministically, replaying the same operations gives back
+/// the same ids, and deleted ids stay retired across restarts.
+pub struct FileStore {
+    path: PathBuf,
+    state: RwLock<FileState>,
+}
+
+/// Everything guarded by the lock. The log lives next to the table so a
+/// write holds one lock while it appends and applies, keeping both in the
+/// same order. Reads only ever touch the table. Versions aren't logged,
+/// replaying the updates counts them up again.
+struct FileState {
+    users: UserTable,
+    log: File,
+}
+
+impl FileStore {
+    /// Opens (or creates) the log at `path` and replays it. A last line
+    /// torn by a crash is cut off, a bad line anywhere else is `Corrupt`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        let path = path.as_ref().to_owned();
+        let log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut bytes = Vec::new();
+        (&log).read_to_end(&mut bytes)?;
+        let mut users = UserTable::default();
+        let mut offset = 0;
+        for (index, line) in bytes.split_inclusive(|byte| *byte == b'\n').enumerate() {
+            let start = offset;
+            offset += line.len();
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let corrupt = |err: &dyn std::fmt::Display| {
+                StoreError::Corrupt(format!("line {}: {}", index + 1, err))
+            };
+            let entry = match serde_json::from_slice::<LogEntry>(line) {
+                Ok(entry) => entry,
+                // Only the last write can be cut short by a crash, and
+                // whoever made it never heard back that it went through
+                Err(err) if !line.ends_with(b"\n") => {
+                    warn!(
+                        "Dropping torn last line {} of {}: {}",
+                        index + 1,
+                        path.display(),
+                        err
+                    );
+                    log.set_len(start as u64)?;
+                    break;
+                }
+                Err(err) => return Err(corrupt(&err)),
+            };
+            replay(&mut users, entry).map_err(|err| corrupt(&err))?;
+            if !line.ends_with(b"\n") {
+                // Whole but for its newline, the next append needs a line of its own
+                (&log).write_all(b"\n")?;
+            }
+        }
+
+        Ok(FileStore {
+            path,
+            state: RwLock::new(FileState { users, log }),
+        })
+    }
+}
+
+impl FileState {
+    /// Writes an entry to the end of the log.
+    fn append(&mut self, entry: &LogEntry) -> Result<(), StoreError> {
+        self.append_all(std::slice::from_ref(entry))
+    }
+
+    /// Writes several entries to the end of the log with a single write.
+    fn append_all(&mut self, entries: &[LogEntry]) -> Result<(), StoreError> {
+        let mut lines = String::new();
+        for entry in entries {
+            let line =
+                serde_json::to_string(entry).map_err(|err| StoreError::Corrupt(err.to_string()))?;
+            lines.push_str(&line);
+            lines.push('\n');
+        }
+        self.log.write_all(lines.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Applies a logged operation to the in-memory copy.
+fn replay(users: &mut UserTable, entry: LogEntry) -> Result<(), StoreError> {
+    match entry {
+        LogEntry::Insert { id, user } => {
+            let (assigned, _) = users.insert(user);
+            if assigned != id {
+                return Err(StoreError::Corrupt(format!(
+                    "insert expected id {} but got {}",
+                    id, assigned
+                )));
+            }
+            Ok(())
+        }
+        LogEntry::Update { id, user } => users.update(id, user, None).map(drop),
+        LogEntry::Delete { id } => users.delete(id, None),
+    }
+}
+
+impl UserStore for FileStore {
+    fn insert(&self, user: UserData) -> Result<(UserId, Version), StoreError> {
+        let mut state = self.state.write().unwrap();
+        // Log first so memory never holds something that isn't on disk
+        let id = state.users.next_id();
+        state.append(&LogEntry::Insert {
+            id,
+            user: user.clone(),
+        })?;
+        Ok(state.users.insert(user))
+    }
+
+    fn get(&self, id: UserId) -> Result<StoredUser, StoreError> {
+        self.state.read().unwrap().users.get(id)
+    }
+
+    fn update(
+        &self,
+        id: UserId,
+        user: UserData,
+        expected: Option<&[Version]>,
+    ) -> Result<Version, StoreError> {
+        let mut state = self.state.write().unwrap();
+        state.users.check(id, expected)?;
+        state.append(&LogEntry::Update {
+            id,
+            user: user.clone(),
+        })?;
+        state.users.update(id, user, None)
+    }
+
+    fn delete(&self, id: UserId, expected: Option<&[Version]>) -> Result<(), StoreError> {
+        let mut state = self.state.write().unwrap();
+        state.users.check(id, expected)?;
+        state.append(&LogEntry::Delete { id })?;
+        state.users.delete(id, None)
+    }
+
+    fn apply(&self, changes: Vec<Change>, atomic: bool) -> Result<BatchOutcome, StoreError> {
+        let mut state = self.state.write().unwrap();
+        // Unlike single writes, the batch runs in memory first and is
+        // logged in one go, then taken back if the log can't be written
+        let mut entries = Vec::new();
+        let (outcome, undo) = state.users.apply_batch(changes, atomic, |users, applied| {
+            // The change just went through, so the user is there
+            let user = |id| users.get(id).map(|stored| stored.user).unwrap();
+            entries.push(match *applied {
+                Applied::Inserted { id, .. } => LogEntry::Insert { id, user: user(id) },
+                Applied::Updated { id, .. } => LogEntry::Update { id, user: user(id) },
+                Applied::Deleted { id } => LogEntry::Delete { id },
+            })
+        });
+        if outcome.committed && !entries.is_empty() {
+            if let Err(err) = state.append_all(&entries) {
+                state.users.rollback(undo);
+                return Err(err);
+            }
+        }
+        Ok(outcome)
+    }
+
+    fn list(&self) -> Result<Vec<(UserId, UserData)>, StoreError> {
+        Ok(self.state.read().unwrap().users.list())
+    }
+
+    fn count(&self) -> Result<usize, StoreError> {
+        Ok(self.state.read().unwrap().users.len())
+    }
+
+    fn check(&self) -> Result<(), StoreError> {
+        // The open handle keeps working after the file is deleted or its
+        // disk is unmounted, so look at the path instead
+        let metadata = std::fs::metadata(&self.path)?;
+        if metadata.permissions().readonly() {
+            return Err(StoreError::Io(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is read-only", self.path.display()),
+            )));
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), StoreError> {
+        // Appends go straight to the file, so only the OS buffers are left
+        let mut state = self.state.write().unwrap();
+        state.log.flush()?;
+        state.log.sync_all()?;
+        Ok(())
+    }
+}
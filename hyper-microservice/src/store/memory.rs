@@ -0,0 +1,54 @@
+use super::table::UserTable;
+use super::{BatchOutcome, Change, StoreError, StoredUser, UserStore, Version};
+use crate::user::{UserData, UserId};
+use std::sync::RwLock;
+
+/// Keeps users in memory only, everything is lost on restart.
+/// Reads share the lock, so only writes wait for each other.
+#[derive(Default)]
+pub struct MemoryStore {
+    users: RwLock<UserTable>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl UserStore for MemoryStore {
+    fn insert(&self, user: UserData) -> Result<(UserId, Version), StoreError> {
+        Ok(self.users.write().unwrap().insert(user))
+    }
+
+    fn get(&self, id: UserId) -> Result<StoredUser, StoreError> {
+        self.users.read().unwrap().get(id)
+    }
+
+    fn update(
+        &self,
+        id: UserId,
+        user: UserData,
+        expected: Option<&[Version]>,
+    ) -> Result<Version, StoreError> {
+        self.users.write().unwrap().update(id, user, expected)
+    }
+
+    fn delete(&self, id: UserId, expected: Option<&[Version]>) -> Result<(), StoreError> {
+        self.users.write().unwrap().delete(id, expected)
+    }
+
+    fn apply(&self, changes: Vec<Change>, atomic: bool) -> Result<BatchOutcome, StoreError> {
+        let mut users = self.users.write().unwrap();
+        let (outcome, _) = users.apply_batch(changes, atomic, |_, _| {});
+        Ok(outcome)
+    }
+
+    fn list(&self) -> Result<Vec<(UserId, UserData)>, StoreError> {
+        Ok(self.users.read().unwrap().list())
+    }
+
+    fn count(&self) -> Result<usize, StoreError> {
+        Ok(self.users.read().unwrap().len())
+    }
+}
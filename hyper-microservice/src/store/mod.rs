@@ -0,0 +1,141 @@
+mod file;
+mod memory;
+mod table;
+
+pub use file::FileStore;
+pub use memory::MemoryStore;
+
+use crate::user::{UserData, UserId};
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Counts the changes made to a user, starting at 1 when it's created.
+pub type Version = u64;
+
+/// A user together with its current version.
+#[derive(Clone, Debug)]
+pub struct StoredUser {
+    pub user: UserData,
+    pub version: Version,
+}
+
+/// One write out of a batch, see `UserStore::apply`.
+#[derive(Clone, Debug)]
+pub enum Change {
+    Insert(UserData),
+    /// Replaces a user. Its `created_at` is kept from the stored user.
+    Update {
+        id: UserId,
+        user: UserData,
+        expected: Option<Vec<Version>>,
+    },
+    Delete {
+        id: UserId,
+        expected: Option<Vec<Version>>,
+    },
+}
+
+/// What a change that went through did.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Applied {
+    Inserted { id: UserId, version: Version },
+    Updated { id: UserId, version: Version },
+    Deleted { id: UserId },
+}
+
+/// The result of a whole batch, one entry per change in the same order.
+#[derive(Debug)]
+pub struct BatchOutcome {
+    pub results: Vec<Result<Applied, StoreError>>,
+    /// Whether the successful changes were kept. Only an atomic batch
+    /// with a failed change leaves the store untouched.
+    pub committed: bool,
+}
+
+/// Operations every user storage backend has to support.
+/// Ids are handed out by the store itself. Stores are shared between
+/// requests, so each backend does its own locking and can let reads
+/// run side by side.
+pub trait UserStore: Send + Sync {
+    /// Adds a new user and returns the id it was stored under and its version.
+    fn insert(&self, user: UserData) -> Result<(UserId, Version), StoreError>;
+    /// Fetches a copy of the user with the given id.
+    fn get(&self, id: UserId) -> Result<StoredUser, StoreError>;
+    /// Replaces the user with the given id and returns its new version.
+    /// When `expected` is given, the current version has to be one of them.
+    fn update(
+        &self,
+        id: UserId,
+        user: UserData,
+        expected: Option<&[Version]>,
+    ) -> Result<Version, StoreError>;
+    /// Removes the user with the given id.
+    /// When `expected` is given, the current version has to be one of them.
+    fn delete(&self, id: UserId, expected: Option<&[Version]>) -> Result<(), StoreError>;
+    /// Applies several changes in order under a single lock. An atomic
+    /// batch keeps all of them or, if any fails, none. Otherwise every
+    /// change that works is kept. Only backend failures fail the call.
+    fn apply(&self, changes: Vec<Change>, atomic: bool) -> Result<BatchOutcome, StoreError>;
+    /// Returns every stored user, ordered by id.
+    fn list(&self) -> Result<Vec<(UserId, UserData)>, StoreError>;
+    /// Counts the stored users.
+    fn count(&self) -> Result<usize, StoreError> {
+        self.list().map(|users| users.len())
+    }
+    /// Checks the backend can still be used, for readiness probes.
+    /// Stores that don't persist are always fine.
+    fn check(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+    /// Makes sure everything written so far is durable.
+    /// Nothing to do for stores that don't persist.
+    fn flush(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+/// Ways a store operation can fail.
+#[derive(Debug)]
+pub enum StoreError {
+    /// No user has ever had the requested id.
+    NotFound,
+    /// The user with the requested id was deleted. Ids aren't reused,
+    /// so it will never come back.
+    Gone,
+    /// The user has changed since the version the client expected.
+    VersionMismatch { current: Version },
+    /// The backend couldn't read or write its data.
+    Io(io::Error),
+    /// Persisted data couldn't be understood.
+    Corrupt(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::NotFound => f.write_str("user not found"),
+            StoreError::Gone => f.write_str("user was deleted"),
+            StoreError::VersionMismatch { current } => {
+                write!(f, "user is at version {}", current)
+            }
+            StoreError::Io(err) => write!(f, "storage io error: {}", err),
+            StoreError::Corrupt(msg) => write!(f, "corrupt storage: {}", msg),
+        }
+    }
+}
+
+impl Error for StoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StoreError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        StoreError::Io(err)
+    }
+}
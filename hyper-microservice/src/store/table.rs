@@ -0,0 +1,163 @@
+use super::{Applied, BatchOutcome, Change, StoreError, StoredUser, Version};
+use crate::user::{UserData, UserId};
+use std::collections::BTreeMap;
+
+/// The plain, unsynchronized table of users the backends build on.
+/// Ids only ever go up, so an id is never given to a second user and
+/// any id below `next_id` that isn't in the map has been deleted.
+#[derive(Default)]
+pub(super) struct UserTable {
+    users: BTreeMap<UserId, StoredUser>,
+    next_id: UserId,
+}
+
+impl UserTable {
+    /// The id the next inserted user will get.
+    pub fn next_id(&self) -> UserId {
+        self.next_id
+    }
+
+    /// The error for an id that isn't in the table: gone if it was used
+    /// before, not found if it never was.
+    fn missing(&self, id: UserId) -> StoreError {
+        if id < self.next_id {
+            StoreError::Gone
+        } else {
+            StoreError::NotFound
+        }
+    }
+
+    /// Checks that a user exists and, if asked to, that it is at one of
+    /// the expected versions.
+    pub fn check(&self, id: UserId, expected: Option<&[Version]>) -> Result<(), StoreError> {
+        let stored = self.users.get(&id).ok_or_else(|| self.missing(id))?;
+        match expected {
+            Some(versions) if !versions.contains(&stored.version) => {
+                Err(StoreError::VersionMismatch {
+                    current: stored.version,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn insert(&mut self, user: UserData) -> (UserId, Version) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.users.insert(id, StoredUser { user, version: 1 });
+        (id, 1)
+    }
+
+    pub fn get(&self, id: UserId) -> Result<StoredUser, StoreError> {
+        self.users.get(&id).cloned().ok_or_else(|| self.missing(id))
+    }
+
+    pub fn update(
+        &mut self,
+        id: UserId,
+        user: UserData,
+        expected: Option<&[Version]>,
+    ) -> Result<Version, StoreError> {
+        self.check(id, expected)?;
+        let stored = self.users.get_mut(&id).ok_or(StoreError::NotFound)?;
+        stored.user = user;
+        stored.version += 1;
+        Ok(stored.version)
+    }
+
+    pub fn delete(&mut self, id: UserId, expected: Option<&[Version]>) -> Result<(), StoreError> {
+        self.check(id, expected)?;
+        self.users.remove(&id);
+        Ok(())
+    }
+
+    /// Applies one change, recording how to take it back in `undo`.
+    pub fn apply(&mut self, change: Change, undo: &mut Vec<Undo>) -> Result<Applied, StoreError> {
+        match change {
+            Change::Insert(user) => {
+                let (id, version) = self.insert(user);
+                undo.push(Undo::Insert(id));
+                Ok(Applied::Inserted { id, version })
+            }
+            Change::Update {
+                id,
+                mut user,
+                expected,
+            } => {
+                let previous = self.get(id)?;
+                user.created_at = previous.user.created_at;
+                let version = self.update(id, user, expected.as_deref())?;
+                undo.push(Undo::Restore(id, previous));
+                Ok(Applied::Updated { id, version })
+            }
+            Change::Delete { id, expected } => {
+                let previous = self.get(id)?;
+                self.delete(id, expected.as_deref())?;
+                undo.push(Undo::Restore(id, previous));
+                Ok(Applied::Deleted { id })
+            }
+        }
+    }
+
+    /// Takes back changes made through `apply`, newest first.
+    pub fn rollback(&mut self, undo: Vec<Undo>) {
+        for step in undo.into_iter().rev() {
+            match step {
+                Undo::Insert(id) => {
+                    self.users.remove(&id);
+                    self.next_id = id;
+                }
+                Undo::Restore(id, stored) => {
+                    self.users.insert(id, stored);
+                }
+            }
+        }
+    }
+
+    /// Applies a batch of changes, see `UserStore::apply`. `applied` is
+    /// called right after each change that goes through, before the next
+    /// one runs. The returned undo steps take back whatever was kept.
+    pub fn apply_batch(
+        &mut self,
+        changes: Vec<Change>,
+        atomic: bool,
+        mut applied: impl FnMut(&Self, &Applied),
+    ) -> (BatchOutcome, Vec<Undo>) {
+        let mut undo = Vec::new();
+        let results = changes
+            .into_iter()
+            .map(|change| {
+                let result = self.apply(change, &mut undo);
+                if let Ok(done) = &result {
+                    applied(self, done);
+                }
+                result
+            })
+            .collect::<Vec<_>>();
+
+        let committed = !atomic || results.iter().all(Result::is_ok);
+        if !committed {
+            self.rollback(std::mem::take(&mut undo));
+        }
+        (BatchOutcome { results, committed }, undo)
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn list(&self) -> Vec<(UserId, UserData)> {
+        self.users
+            .iter()
+            .map(|(id, stored)| (*id, stored.user.clone()))
+            .collect()
+    }
+}
+
+/// How to take back one applied change.
+pub(super) enum Undo {
+    /// Forget an inserted user and hand its id out again.
+    Insert(UserId),
+    /// Put back the user as it was before an update or delete.
+    Restore(UserId, StoredUser),
+}
@@ -0,0 +1,178 @@
+use crate::negotiate::{escape, Render};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+
+// Types used for a makeshift database of users
+pub type UserId = u64;
+
+/// Longest name a user is allowed to have.
+const MAX_NAME_LEN: usize = 100;
+
+/// A user as it is kept in the database.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UserData {
+    pub name: String,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A stored user together with its id, used for REST responses.
+#[derive(Serialize, JsonSchema)]
+pub struct UserEntry<'a> {
+    pub id: UserId,
+    #[serde(flatten)]
+    pub user: &'a UserData,
+}
+
+/// The fields a client sends to create or replace a user.
+/// `created_at` is managed by the server, so it is ignored if present.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct UserInput {
+    pub name: String,
+    pub email: String,
+}
+
+/// Describes why a single field of a request was rejected.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+/// Body sent back to clients whenever a request fails.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ErrorBody {
+    pub error: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+impl ErrorBody {
+    pub fn new(error: impl Into<String>) -> Self {
+        ErrorBody {
+            error: error.into(),
+            fields: Vec::new(),
+        }
+    }
+}
+
+impl Render for UserEntry<'_> {
+    fn text(&self) -> String {
+        format!(
+            "id: {}\nname: {}\nemail: {}\ncreated_at: {}",
+            self.id,
+            self.user.name,
+            self.user.email,
+            self.user.created_at.to_rfc3339()
+        )
+    }
+
+    fn html(&self) -> String {
+        format!(
+            "<h3>{}</h3>\n<dl>\n<dt>Id</dt><dd>{}</dd>\n<dt>Email</dt><dd>{}</dd>\n\
+             <dt>Created</dt><dd>{}</dd>\n</dl>\n<p><a href=\"/users\">All users</a></p>",
+            escape(&self.user.name),
+            self.id,
+            escape(&self.user.email),
+            self.user.created_at.to_rfc3339()
+        )
+    }
+
+    fn title(&self) -> String {
+        self.user.name.clone()
+    }
+}
+
+impl Render for ErrorBody {
+    fn text(&self) -> String {
+        let mut text = self.error.clone();
+        for field in &self.fields {
+            text.push_str(&format!("\n{}: {}", field.field, field.message));
+        }
+        text
+    }
+
+    fn html(&self) -> String {
+        let mut html = format!("<h3>{}</h3>", escape(&self.error));
+        if !self.fields.is_empty() {
+            html.push_str("\n<ul>");
+            for field in &self.fields {
+                html.push_str(&format!(
+                    "\n<li>{}: {}</li>",
+                    field.field,
+                    escape(&field.message)
+                ));
+            }
+            html.push_str("\n</ul>");
+        }
+        html
+    }
+
+    fn title(&self) -> String {
+        "Error".into()
+    }
+}
+
+impl UserInput {
+    /// Checks the fields, collecting every problem instead of stopping at the first.
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        let name = self.name.trim();
+        if name.is_empty() {
+            errors.push(FieldError {
+                field: "name",
+                message: "must not be empty".into(),
+            });
+        } else if name.chars().count() > MAX_NAME_LEN {
+            errors.push(FieldError {
+                field: "name",
+                message: format!("must be at most {} characters", MAX_NAME_LEN),
+            });
+        }
+
+        if !is_valid_email(self.email.trim()) {
+            errors.push(FieldError {
+                field: "email",
+                message: "must be a valid email address".into(),
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Builds a brand new user from the input.
+    pub fn into_user(self) -> UserData {
+        self.into_user_created_at(Utc::now())
+    }
+
+    /// Builds a user that keeps an existing creation time, used for replacements.
+    pub fn into_user_created_at(self, created_at: DateTime<Utc>) -> UserData {
+        UserData {
+            name: self.name.trim().to_owned(),
+            email: self.email.trim().to_owned(),
+            created_at,
+        }
+    }
+}
+
+/// A deliberately loose check: one `@` with something on both sides
+/// and a dot somewhere in the domain.
+fn is_valid_email(email: &str) -> bool {
+    let mut parts = email.split('@');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(local), Some(domain), None) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.contains(char::is_whitespace)
+        }
+        _ => false,
+    }
+}
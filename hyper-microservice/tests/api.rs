@@ -0,0 +1,678 @@
+//! Exercises every route and method of the service over HTTP.
+
+mod common;
+
+use common::{create, send, send_bytes, send_with, start};
+use futures::future::join_all;
+use hyper::{Method, StatusCode};
+use std::collections::HashSet;
+
+const ALICE: &str = r#"{"name": "Alice", "email": "alice@example.com"}"#;
+
+/* Index */
+
+#[tokio::test]
+async fn index_is_served_on_every_alias() {
+    let addr = start().await;
+    for path in &["/", "/index.htm", "/index.html"] {
+        let reply = send(addr, Method::GET, path, "").await;
+        assert_eq!(reply.status, StatusCode::OK, "{}", path);
+        assert!(reply.body.contains("Rust Microservice Example"));
+    }
+}
+
+#[tokio::test]
+async fn index_answers_head_without_a_body() {
+    let addr = start().await;
+    let reply = send(addr, Method::HEAD, "/", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert!(reply.body.is_empty());
+}
+
+#[tokio::test]
+async fn index_rejects_other_methods() {
+    let addr = start().await;
+    for method in &[Method::POST, Method::PUT, Method::DELETE] {
+        let reply = send(addr, method.clone(), "/", "").await;
+        assert_eq!(reply.status, StatusCode::METHOD_NOT_ALLOWED, "{}", method);
+        assert_eq!(reply.header("allow"), Some("GET, HEAD, OPTIONS"));
+    }
+}
+
+/* Routing */
+
+#[tokio::test]
+async fn unknown_paths_are_not_found() {
+    let addr = start().await;
+    for path in &["/nope", "/user/abc", "/users/1", "/user/1/extra"] {
+        let reply = send(addr, Method::GET, path, "").await;
+        assert_eq!(reply.status, StatusCode::NOT_FOUND, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn options_lists_allowed_methods() {
+    let addr = start().await;
+    let reply = send(addr, Method::OPTIONS, "/user/0", "").await;
+    assert_eq!(reply.status, StatusCode::NO_CONTENT);
+    assert_eq!(
+        reply.header("allow"),
+        Some("DELETE, GET, HEAD, OPTIONS, PATCH, POST, PUT")
+    );
+}
+
+/* Users collection */
+
+#[tokio::test]
+async fn users_starts_empty() {
+    let addr = start().await;
+    let reply = send(addr, Method::GET, "/users", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.header("content-type"), Some("application/json"));
+    let page = reply.json();
+    assert_eq!(page["total"], 0);
+    assert_eq!(page["items"].as_array().unwrap().len(), 0);
+    assert!(page["links"]["next"].is_null());
+}
+
+#[tokio::test]
+async fn users_pages_filters_and_sorts() {
+    let addr = start().await;
+    for name in &["Carol", "alice", "Bob", "Dave"] {
+        create(addr, name, &format!("{}@example.com", name)).await;
+    }
+
+    let reply = send(addr, Method::GET, "/users?limit=2&sort=-id", "").await;
+    let page = reply.json();
+    assert_eq!(page["total"], 4);
+    assert_eq!(page["items"][0]["name"], "Dave");
+    assert_eq!(page["items"][1]["name"], "Bob");
+    let next = page["links"]["next"].as_str().unwrap().to_owned();
+
+    let page = send(addr, Method::GET, &next, "").await.json();
+    assert_eq!(page["offset"], 2);
+    assert_eq!(page["items"][0]["name"], "alice");
+    assert!(page["links"]["next"].is_null());
+
+    let page = send(addr, Method::GET, "/users?name=A&sort=name", "")
+        .await
+        .json();
+    assert_eq!(page["total"], 3);
+    let names = page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|user| user["name"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["Carol", "Dave", "alice"]);
+}
+
+#[tokio::test]
+async fn users_pages_past_the_end_are_empty() {
+    let addr = start().await;
+    create(addr, "Alice", "alice@example.com").await;
+
+    let path = format!("/users?offset={}", usize::MAX);
+    let reply = send(addr, Method::GET, &path, "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let page = reply.json();
+    assert_eq!(page["total"], 1);
+    assert_eq!(page["items"].as_array().unwrap().len(), 0);
+    assert!(page["links"]["next"].is_null());
+    assert!(page["links"]["prev"].is_string());
+}
+
+#[tokio::test]
+async fn users_rejects_bad_queries() {
+    let addr = start().await;
+    for query in &[
+        "limit=0",
+        "limit=1000",
+        "offset=-1",
+        "sort=age",
+        "colour=red",
+    ] {
+        let reply = send(addr, Method::GET, &format!("/users?{}", query), "").await;
+        assert_eq!(reply.status, StatusCode::BAD_REQUEST, "{}", query);
+        assert!(reply.json()["error"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn users_rejects_other_methods() {
+    let addr = start().await;
+    for method in &[Method::POST, Method::PUT, Method::DELETE] {
+        let reply = send(addr, method.clone(), "/users", "").await;
+        assert_eq!(reply.status, StatusCode::METHOD_NOT_ALLOWED, "{}", method);
+    }
+}
+
+/* Single users */
+
+#[tokio::test]
+async fn user_lifecycle() {
+    let addr = start().await;
+
+    let reply = send(addr, Method::POST, "/user/", ALICE).await;
+    assert_eq!(reply.status, StatusCode::CREATED);
+    let created = reply.json();
+    assert_eq!(created["id"], 0);
+    assert_eq!(created["name"], "Alice");
+    assert!(created["created_at"].is_string());
+
+    let reply = send(addr, Method::GET, "/user/0", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.header("content-type"), Some("application/json"));
+    assert_eq!(reply.json(), created);
+
+    let update = r#"{"name": "Alicia", "email": "alicia@example.com"}"#;
+    let reply = send(addr, Method::PUT, "/user/0/", update).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let updated = reply.json();
+    assert_eq!(updated["name"], "Alicia");
+    assert_eq!(updated["created_at"], created["created_at"]);
+
+    let reply = send(addr, Method::DELETE, "/user/0", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let reply = send(addr, Method::GET, "/user/0", "").await;
+    assert_eq!(reply.status, StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn missing_users_are_not_found() {
+    let addr = start().await;
+    let reply = send(addr, Method::GET, "/user/7", "").await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+    let reply = send(addr, Method::PUT, "/user/7", ALICE).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+    let reply = send(addr, Method::DELETE, "/user/7", "").await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn clients_cannot_choose_ids() {
+    let addr = start().await;
+    let reply = send(addr, Method::POST, "/user/3", ALICE).await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn user_rejects_other_methods() {
+    let addr = start().await;
+    let reply = send(addr, Method::GET, "/user/", "").await;
+    assert_eq!(reply.status, StatusCode::METHOD_NOT_ALLOWED);
+    let reply = send(addr, Method::TRACE, "/user/0", "").await;
+    assert_eq!(reply.status, StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[tokio::test]
+async fn malformed_json_is_a_bad_request() {
+    let addr = start().await;
+    for body in &["", "not json", r#"{"name": "Alice""#] {
+        let reply = send(addr, Method::POST, "/user/", body).await;
+        assert_eq!(reply.status, StatusCode::BAD_REQUEST, "{:?}", body);
+        assert!(reply.json()["error"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn incomplete_or_invalid_users_are_unprocessable() {
+    let addr = start().await;
+    create(addr, "Alice", "alice@example.com").await;
+
+    let reply = send(addr, Method::POST, "/user/", r#"{"name": "Alice"}"#).await;
+    assert_eq!(reply.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(reply.json()["error"].as_str().unwrap().contains("email"));
+
+    let reply = send(
+        addr,
+        Method::PUT,
+        "/user/0",
+        r#"{"name": "", "email": "nope"}"#,
+    )
+    .await;
+    assert_eq!(reply.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let fields = reply.json()["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| field["field"].as_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(fields, vec!["name", "email"]);
+
+    // The failed update left the user alone
+    let reply = send(addr, Method::GET, "/user/0", "").await;
+    assert_eq!(reply.json()["name"], "Alice");
+}
+
+#[tokio::test]
+async fn deleted_ids_are_never_reused() {
+    let addr = start().await;
+    for name in &["Alice", "Bob", "Carol"] {
+        create(addr, name, "someone@example.com").await;
+    }
+    let reply = send(addr, Method::DELETE, "/user/2", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+
+    let page = send(addr, Method::GET, "/users", "").await.json();
+    assert_eq!(page["total"], 2);
+
+    // Even the highest id isn't handed out again
+    let id = create(addr, "Dave", "dave@example.com").await;
+    assert_eq!(id, 3);
+}
+
+#[tokio::test]
+async fn deleted_users_are_gone() {
+    let addr = start().await;
+    let id = create(addr, "Alice", "alice@example.com").await;
+    let path = format!("/user/{}", id);
+    send(addr, Method::DELETE, &path, "").await;
+
+    let reply = send(addr, Method::GET, &path, "").await;
+    assert_eq!(reply.status, StatusCode::GONE);
+    let reply = send(addr, Method::PUT, &path, ALICE).await;
+    assert_eq!(reply.status, StatusCode::GONE);
+    let reply = send(addr, Method::DELETE, &path, "").await;
+    assert_eq!(reply.status, StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn concurrent_creates_get_distinct_ids() {
+    let addr = start().await;
+    let creates = (0..50).map(|n| async move {
+        let name = format!("user {}", n);
+        create(addr, &name, "someone@example.com").await
+    });
+    let ids = join_all(creates).await;
+
+    let unique = ids.iter().collect::<HashSet<_>>();
+    assert_eq!(unique.len(), ids.len());
+    let page = send(addr, Method::GET, "/users?limit=100", "").await.json();
+    assert_eq!(page["total"], 50);
+}
+
+/* Conditional requests */
+
+#[tokio::test]
+async fn versions_are_sent_as_etags() {
+    let addr = start().await;
+    let reply = send(addr, Method::POST, "/user/", ALICE).await;
+    assert_eq!(reply.header("etag"), Some("\"1\""));
+
+    let reply = send(addr, Method::GET, "/user/0", "").await;
+    assert_eq!(reply.header("etag"), Some("\"1\""));
+
+    let reply = send(addr, Method::PUT, "/user/0", ALICE).await;
+    assert_eq!(reply.header("etag"), Some("\"2\""));
+}
+
+#[tokio::test]
+async fn get_honors_if_none_match() {
+    let addr = start().await;
+    create(addr, "Alice", "alice@example.com").await;
+
+    for tag in &["\"1\"", "W/\"1\"", "\"7\", \"1\"", "*"] {
+        let headers = [("if-none-match", *tag)];
+        let reply = send_with(addr, Method::GET, "/user/0", &headers, "").await;
+        assert_eq!(reply.status, StatusCode::NOT_MODIFIED, "{}", tag);
+        assert_eq!(reply.header("etag"), Some("\"1\""));
+        assert!(reply.body.is_empty());
+    }
+
+    let headers = [("if-none-match", "\"0\"")];
+    let reply = send_with(addr, Method::GET, "/user/0", &headers, "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn put_honors_if_match() {
+    let addr = start().await;
+    create(addr, "Alice", "alice@example.com").await;
+    let update = r#"{"name": "Alicia", "email": "alice@example.com"}"#;
+
+    // Someone else's change made our copy stale
+    let headers = [("if-match", "\"1\"")];
+    let reply = send_with(addr, Method::PUT, "/user/0", &headers, update).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let reply = send_with(addr, Method::PUT, "/user/0", &headers, ALICE).await;
+    assert_eq!(reply.status, StatusCode::PRECONDITION_FAILED);
+    assert_eq!(reply.header("etag"), Some("\"2\""));
+
+    // Weak tags never match for writes
+    let headers = [("if-match", "W/\"2\"")];
+    let reply = send_with(addr, Method::PUT, "/user/0", &headers, ALICE).await;
+    assert_eq!(reply.status, StatusCode::PRECONDITION_FAILED);
+
+    let reply = send(addr, Method::GET, "/user/0", "").await;
+    assert_eq!(reply.json()["name"], "Alicia");
+}
+
+#[tokio::test]
+async fn delete_honors_if_match() {
+    let addr = start().await;
+    create(addr, "Alice", "alice@example.com").await;
+
+    let headers = [("if-match", "\"5\"")];
+    let reply = send_with(addr, Method::DELETE, "/user/0", &headers, "").await;
+    assert_eq!(reply.status, StatusCode::PRECONDITION_FAILED);
+
+    let headers = [("if-match", "\"1\"")];
+    let reply = send_with(addr, Method::DELETE, "/user/0", &headers, "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+}
+
+/* Patches */
+
+const MERGE_PATCH: &str = "application/merge-patch+json";
+const JSON_PATCH: &str = "application/json-patch+json";
+
+async fn patch(addr: std::net::SocketAddr, content_type: &str, body: &str) -> common::Reply {
+    let headers = [("content-type", content_type)];
+    send_with(addr, Method::PATCH, "/user/0", &headers, body).await
+}
+
+#[tokio::test]
+async fn merge_patch_changes_one_field() {
+    let addr = start().await;
+    create(addr, "Alice", "alice@example.com").await;
+
+    let reply = patch(addr, MERGE_PATCH, r#"{"email": "alicia@example.com"}"#).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.header("etag"), Some("\"2\""));
+    let user = reply.json();
+    assert_eq!(user["name"], "Alice");
+    assert_eq!(user["email"], "alicia@example.com");
+}
+
+#[tokio::test]
+async fn json_patch_applies_operations() {
+    let addr = start().await;
+    create(addr, "Alice", "alice@example.com").await;
+
+    let ops = r#"[
+        {"op": "test", "path": "/name", "value": "Alice"},
+        {"op": "replace", "path": "/name", "value": "Alicia"}
+    ]"#;
+    let reply = patch(addr, JSON_PATCH, ops).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.json()["name"], "Alicia");
+
+    // The test now fails, so nothing changes
+    let reply = patch(addr, JSON_PATCH, ops).await;
+    assert_eq!(reply.status, StatusCode::CONFLICT);
+    let reply = send(addr, Method::GET, "/user/0", "").await;
+    assert_eq!(reply.json()["name"], "Alicia");
+}
+
+#[tokio::test]
+async fn patches_producing_invalid_users_are_unprocessable() {
+    let addr = start().await;
+    create(addr, "Alice", "alice@example.com").await;
+
+    let reply = patch(addr, MERGE_PATCH, r#"{"email": "nope"}"#).await;
+    assert_eq!(reply.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(reply.json()["fields"][0]["field"], "email");
+
+    let reply = patch(addr, MERGE_PATCH, r#"{"name": null}"#).await;
+    assert_eq!(reply.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let reply = patch(addr, MERGE_PATCH, r#"{"id": 5}"#).await;
+    assert_eq!(reply.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let ops = r#"[{"op": "remove", "path": "/nickname"}]"#;
+    let reply = patch(addr, JSON_PATCH, ops).await;
+    assert_eq!(reply.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let reply = send(addr, Method::GET, "/user/0", "").await;
+    assert_eq!(reply.header("etag"), Some("\"1\""));
+}
+
+#[tokio::test]
+async fn patch_rejects_bad_requests() {
+    let addr = start().await;
+    create(addr, "Alice", "alice@example.com").await;
+
+    let reply = patch(addr, "application/json", r#"{"name": "Bob"}"#).await;
+    assert_eq!(reply.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(reply.header("accept-patch").is_some());
+
+    let reply = patch(addr, MERGE_PATCH, "{").await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+    let reply = patch(addr, JSON_PATCH, r#"{"op": "add"}"#).await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+
+    let headers = [("content-type", MERGE_PATCH), ("if-match", "\"9\"")];
+    let reply = send_with(addr, Method::PATCH, "/user/0", &headers, "{}").await;
+    assert_eq!(reply.status, StatusCode::PRECONDITION_FAILED);
+
+    let headers = [("content-type", MERGE_PATCH)];
+    let reply = send_with(addr, Method::PATCH, "/user/9", &headers, "{}").await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+}
+
+/* Batches */
+
+#[tokio::test]
+async fn best_effort_batch_keeps_what_works() {
+    let addr = start().await;
+    create(addr, "Alice", "alice@example.com").await;
+
+    let batch = r#"{"mode": "best_effort", "operations": [
+        {"op": "create", "user": {"name": "Bob", "email": "bob@example.com"}},
+        {"op": "create", "user": {"name": "", "email": "nope"}},
+        {"op": "update", "id": 0, "version": 1, "user": {"name": "Alicia", "email": "alice@example.com"}},
+        {"op": "delete", "id": 99},
+        {"op": "rename", "id": 0}
+    ]}"#;
+    let reply = send(addr, Method::POST, "/users/batch", batch).await;
+    assert_eq!(reply.status, StatusCode::MULTI_STATUS);
+    let body = reply.json();
+    assert_eq!(body["committed"], true);
+    let results = body["results"].as_array().unwrap();
+    let statuses = results
+        .iter()
+        .map(|r| r["status"].as_u64().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(statuses, vec![201, 422, 200, 404, 422]);
+    assert_eq!(results[0]["id"], 1);
+    assert_eq!(results[1]["fields"].as_array().unwrap().len(), 2);
+    assert_eq!(results[2]["version"], 2);
+    assert_eq!(results[3]["id"], 99);
+
+    let reply = send(addr, Method::GET, "/user/0", "").await;
+    assert_eq!(reply.json()["name"], "Alicia");
+    let reply = send(addr, Method::GET, "/users", "").await;
+    assert_eq!(reply.json()["total"], 2);
+}
+
+#[tokio::test]
+async fn atomic_batch_is_all_or_nothing() {
+    let addr = start().await;
+    create(addr, "Alice", "alice@example.com").await;
+
+    let batch = r#"{"operations": [
+        {"op": "create", "user": {"name": "Bob", "email": "bob@example.com"}},
+        {"op": "update", "id": 0, "user": {"name": "Alicia", "email": "alice@example.com"}},
+        {"op": "delete", "id": 0, "version": 1}
+    ]}"#;
+    let reply = send(addr, Method::POST, "/users/batch", batch).await;
+    assert_eq!(reply.status, StatusCode::MULTI_STATUS);
+    let body = reply.json();
+    assert_eq!(body["committed"], false);
+    let statuses = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["status"].as_u64().unwrap())
+        .collect::<Vec<_>>();
+    // The update bumped the version, so the delete no longer matches
+    assert_eq!(statuses, vec![424, 424, 412]);
+
+    let reply = send(addr, Method::GET, "/user/0", "").await;
+    assert_eq!(reply.header("etag"), Some("\"1\""));
+    assert_eq!(reply.json()["name"], "Alice");
+    // Ids handed out by the rolled back create are free again
+    assert_eq!(create(addr, "Carol", "carol@example.com").await, 1);
+
+    let batch = r#"{"mode": "atomic", "operations": [
+        {"op": "create", "user": {"name": "Dave", "email": "dave@example.com"}},
+        {"op": "delete", "id": 0}
+    ]}"#;
+    let reply = send(addr, Method::POST, "/users/batch", batch).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let body = reply.json();
+    assert_eq!(body["committed"], true);
+    assert_eq!(body["results"][0]["id"], 2);
+    assert_eq!(body["results"][1]["status"], 204);
+    let reply = send(addr, Method::GET, "/user/0", "").await;
+    assert_eq!(reply.status, StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn atomic_batch_with_invalid_operations_changes_nothing() {
+    let addr = start().await;
+    let batch = r#"{"operations": [
+        {"op": "create", "user": {"name": "Bob", "email": "bob@example.com"}},
+        {"op": "create", "user": {"name": "Bob"}}
+    ]}"#;
+    let reply = send(addr, Method::POST, "/users/batch", batch).await;
+    assert_eq!(reply.status, StatusCode::MULTI_STATUS);
+    assert_eq!(reply.json()["results"][0]["status"], 424);
+    assert_eq!(reply.json()["results"][1]["status"], 422);
+    let reply = send(addr, Method::GET, "/users", "").await;
+    assert_eq!(reply.json()["total"], 0);
+}
+
+#[tokio::test]
+async fn batch_rejects_bad_requests() {
+    let addr = start().await;
+    for body in &["", "[]", r#"{"mode": "eventually", "operations": []}"#] {
+        let reply = send(addr, Method::POST, "/users/batch", body).await;
+        assert_eq!(reply.status, StatusCode::BAD_REQUEST, "{}", body);
+    }
+
+    let operation = r#"{"op": "delete", "id": 0}"#;
+    let operations = vec![operation; hyper_microservice::batch::MAX_OPERATIONS + 1];
+    let body = format!(r#"{{"operations": [{}]}}"#, operations.join(","));
+    let reply = send(addr, Method::POST, "/users/batch", &body).await;
+    assert_eq!(reply.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    let reply = send(addr, Method::GET, "/users/batch", "").await;
+    assert_eq!(reply.status, StatusCode::METHOD_NOT_ALLOWED);
+}
+
+/* Content negotiation */
+
+#[tokio::test]
+async fn index_follows_accept() {
+    let addr = start().await;
+
+    let headers = [("accept", "text/html,application/xhtml+xml,*/*;q=0.8")];
+    let reply = send_with(addr, Method::GET, "/", &headers, "").await;
+    assert_eq!(
+        reply.header("content-type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert!(reply.body.contains("<h3>Rust Microservice Example</h3>"));
+
+    let reply = send(addr, Method::GET, "/", "").await;
+    assert_eq!(reply.header("content-type"), Some("application/json"));
+    assert_eq!(reply.json()["links"]["users"], "/users");
+    assert_eq!(reply.header("vary"), Some("Accept"));
+}
+
+#[tokio::test]
+async fn users_can_be_listed_as_text_and_html() {
+    let addr = start().await;
+    create(addr, "Alice", "alice@example.com").await;
+    create(addr, "<b>Bob</b>", "bob@example.com").await;
+
+    // Plain text keeps the old comma separated ids
+    let headers = [("accept", "application/json;q=0.5, text/plain")];
+    let reply = send_with(addr, Method::GET, "/users", &headers, "").await;
+    assert_eq!(
+        reply.header("content-type"),
+        Some("text/plain; charset=utf-8")
+    );
+    assert_eq!(reply.body, "0,1");
+
+    let headers = [("accept", "text/*")];
+    let reply = send_with(addr, Method::GET, "/users?limit=1", &headers, "").await;
+    assert_eq!(
+        reply.header("content-type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert!(reply.body.contains("<a href=\"/user/0\">0</a>"));
+    assert!(reply.body.contains("/users?limit=1&amp;offset=1"));
+
+    let reply = send_with(addr, Method::GET, "/users?offset=1", &headers, "").await;
+    assert!(reply.body.contains("&lt;b&gt;Bob&lt;/b&gt;"));
+}
+
+#[tokio::test]
+async fn users_can_be_sent_as_cbor_and_messagepack() {
+    let addr = start().await;
+
+    let user = serde_json::json!({ "name": "Alice", "email": "alice@example.com" });
+    let body = rmp_serde::to_vec_named(&user).unwrap();
+    let headers = [
+        ("content-type", "application/msgpack"),
+        ("accept", "application/cbor"),
+    ];
+    let reply = send_bytes(addr, Method::POST, "/user/", &headers, body).await;
+    assert_eq!(reply.status, StatusCode::CREATED, "{}", reply.body);
+    assert_eq!(reply.header("content-type"), Some("application/cbor"));
+    let created: serde_json::Value = ciborium::de::from_reader(&reply.bytes[..]).unwrap();
+    assert_eq!(created["name"], "Alice");
+
+    let mut body = Vec::new();
+    let user = serde_json::json!({ "name": "Alicia", "email": "alice@example.com" });
+    ciborium::ser::into_writer(&user, &mut body).unwrap();
+    let headers = [
+        ("content-type", "application/cbor"),
+        ("accept", "application/x-msgpack"),
+    ];
+    let reply = send_bytes(addr, Method::PUT, "/user/0", &headers, body).await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    assert_eq!(reply.header("content-type"), Some("application/msgpack"));
+    let replaced: serde_json::Value = rmp_serde::from_slice(&reply.bytes).unwrap();
+    assert_eq!(replaced["name"], "Alicia");
+    assert_eq!(replaced["id"], 0);
+
+    // Data that decodes but isn't a user is still unprocessable
+    let body = rmp_serde::to_vec_named(&serde_json::json!({ "name": "Bob" })).unwrap();
+    let headers = [("content-type", "application/msgpack")];
+    let reply = send_bytes(addr, Method::POST, "/user/", &headers, body).await;
+    assert_eq!(reply.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let headers = [("content-type", "application/cbor")];
+    let reply = send_bytes(addr, Method::POST, "/user/", &headers, vec![0xa1]).await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn unacceptable_requests_change_nothing() {
+    let addr = start().await;
+
+    let headers = [("accept", "image/png, application/json;q=0")];
+    let reply = send_with(addr, Method::POST, "/user/", &headers, ALICE).await;
+    assert_eq!(reply.status, StatusCode::NOT_ACCEPTABLE);
+    assert!(reply.body.contains("application/cbor"));
+
+    let headers = [("content-type", "text/plain")];
+    let reply = send_with(addr, Method::POST, "/user/", &headers, ALICE).await;
+    assert_eq!(reply.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let reply = send(addr, Method::GET, "/users", "").await;
+    assert_eq!(reply.json()["total"], 0);
+}
+
+#[tokio::test]
+async fn errors_follow_accept() {
+    let addr = start().await;
+    let headers = [("accept", "text/plain")];
+    let body = r#"{"name": "", "email": "alice@example.com"}"#;
+    let reply = send_with(addr, Method::POST, "/user/", &headers, body).await;
+    assert_eq!(reply.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(reply.body, "invalid user\nname: must not be empty");
+}
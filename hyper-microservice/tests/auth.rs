@@ -0,0 +1,210 @@
+//! Bearer token authentication against an in-memory session store.
+
+mod common;
+
+use common::{send_with, start_with_options, Reply};
+use hyper::{Method, StatusCode};
+use hyper_microservice::auth::Auth;
+use hyper_microservice::server::Options;
+use hyper_microservice::sessions::{MemorySessions, SessionError, SessionStore};
+use hyper_microservice::store::MemoryStore;
+use hyper_microservice::user::UserId;
+use middleware::rate_limit::MemoryBuckets;
+use middleware::{Quota, RateLimit};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+const ALICE: &str = r#"{"name": "Alice", "email": "alice@example.com"}"#;
+const BOB: &str = r#"{"name": "Bob", "email": "bob@example.com"}"#;
+
+/// Starts the service where `admin-token` belongs to admin 0,
+/// `alice-token` to user 1 and `bob-token` to user 2.
+async fn start() -> SocketAddr {
+    let sessions = MemorySessions::new();
+    sessions.insert("admin-token", 0);
+    sessions.insert("alice-token", 1);
+    sessions.insert("bob-token", 2);
+    start_with(Arc::new(sessions)).await
+}
+
+async fn start_with(sessions: Arc<dyn SessionStore>) -> SocketAddr {
+    let auth = Auth {
+        sessions,
+        admins: vec![0].into_iter().collect(),
+    };
+    let options = Options {
+        auth: Some(auth),
+        ..Options::default()
+    };
+    start_with_options(Arc::new(MemoryStore::new()), options).await
+}
+
+async fn send_as(addr: SocketAddr, token: &str, method: Method, path: &str, body: &str) -> Reply {
+    let authorization = format!("Bearer {}", token);
+    let headers = [("authorization", authorization.as_str())];
+    send_with(addr, method, path, &headers, body).await
+}
+
+/// Creates the admin, Alice and Bob as users 0, 1 and 2.
+async fn create_users(addr: SocketAddr) {
+    let admin = r#"{"name": "Admin", "email": "admin@example.com"}"#;
+    for body in &[admin, ALICE, BOB] {
+        let reply = send_as(addr, "admin-token", Method::POST, "/user/", body).await;
+        assert_eq!(reply.status, StatusCode::CREATED, "{}", reply.body);
+    }
+}
+
+#[tokio::test]
+async fn reading_needs_no_token() {
+    let addr = start().await;
+    create_users(addr).await;
+    let reply = send_with(addr, Method::GET, "/user/1", &[], "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let reply = send_with(addr, Method::GET, "/users", &[], "").await;
+    assert_eq!(reply.json()["total"], 3);
+}
+
+#[tokio::test]
+async fn changes_need_a_token() {
+    let addr = start().await;
+    create_users(addr).await;
+
+    let reply = send_with(addr, Method::POST, "/user/", &[], ALICE).await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(reply.header("www-authenticate"), Some("Bearer"));
+    let reply = send_with(addr, Method::DELETE, "/user/1", &[], "").await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+
+    let reply = send_as(addr, "stolen-token", Method::DELETE, "/user/1", "").await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        reply.header("www-authenticate"),
+        Some("Bearer error=\"invalid_token\"")
+    );
+
+    let headers = [("authorization", "Basic YWxpY2U6c2VjcmV0")];
+    let reply = send_with(addr, Method::DELETE, "/user/1", &headers, "").await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn users_may_only_change_themselves() {
+    let addr = start().await;
+    create_users(addr).await;
+
+    let alicia = r#"{"name": "Alicia", "email": "alice@example.com"}"#;
+    let reply = send_as(addr, "alice-token", Method::PUT, "/user/1", alicia).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let reply = send_as(addr, "alice-token", Method::PUT, "/user/2", alicia).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+    let reply = send_as(addr, "alice-token", Method::DELETE, "/user/2", "").await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+
+    let headers = [
+        ("authorization", "Bearer bob-token"),
+        ("content-type", "application/merge-patch+json"),
+    ];
+    let patch = r#"{"name": "Robert"}"#;
+    let reply = send_with(addr, Method::PATCH, "/user/1", &headers, patch).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+    let reply = send_with(addr, Method::PATCH, "/user/2", &headers, patch).await;
+    assert_eq!(reply.status, StatusCode::OK);
+
+    // Only admins create users and run batches
+    let reply = send_as(addr, "alice-token", Method::POST, "/user/", BOB).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+    let batch = r#"{"operations": [{"op": "delete", "id": 2}]}"#;
+    let reply = send_as(addr, "alice-token", Method::POST, "/users/batch", batch).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+
+    let reply = send_as(addr, "bob-token", Method::DELETE, "/user/2", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn admins_may_change_anyone() {
+    let addr = start().await;
+    create_users(addr).await;
+
+    let reply = send_as(addr, "admin-token", Method::PUT, "/user/1", BOB).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let batch = r#"{"operations": [{"op": "delete", "id": 2}]}"#;
+    let reply = send_as(addr, "admin-token", Method::POST, "/users/batch", batch).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let reply = send_as(addr, "admin-token", Method::DELETE, "/user/1", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+}
+
+/// A session store that is never reachable.
+struct Down;
+
+impl SessionStore for Down {
+    fn lookup(&self, _token: &str) -> Result<Option<UserId>, SessionError> {
+        Err(SessionError("connection refused".into()))
+    }
+}
+
+#[tokio::test]
+async fn unreachable_sessions_are_unavailable() {
+    let addr = start_with(Arc::new(Down)).await;
+    let reply = send_as(addr, "admin-token", Method::POST, "/user/", ALICE).await;
+    assert_eq!(reply.status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn signed_in_users_are_rate_limited_by_id() {
+    let sessions = MemorySessions::new();
+    sessions.insert("alice-token", 1);
+    sessions.insert("alice-phone", 1);
+    sessions.insert("bob-token", 2);
+    let auth = Auth {
+        sessions: Arc::new(sessions),
+        admins: Default::default(),
+    };
+    let options = Options {
+        auth: Some(auth),
+        rate_limit: Some(RateLimit::new(MemoryBuckets::new()).default_quota(Quota::per_minute(2))),
+    };
+    let addr = start_with_options(Arc::new(MemoryStore::new()), options).await;
+
+    // Both of Alice's sessions share her limit
+    let reply = send_as(addr, "alice-token", Method::GET, "/users", "").await;
+    assert_eq!(reply.header("ratelimit-remaining"), Some("1"));
+    let reply = send_as(addr, "alice-phone", Method::GET, "/users", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let reply = send_as(addr, "alice-phone", Method::GET, "/users", "").await;
+    assert_eq!(reply.status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Bob and anonymous callers from the same IP don't
+    let reply = send_as(addr, "bob-token", Method::GET, "/users", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let reply = send_with(addr, Method::GET, "/users", &[], "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn guessing_tokens_is_rate_limited() {
+    let sessions = MemorySessions::new();
+    sessions.insert("alice-token", 1);
+    let auth = Auth {
+        sessions: Arc::new(sessions),
+        admins: Default::default(),
+    };
+    let options = Options {
+        auth: Some(auth),
+        rate_limit: Some(RateLimit::new(MemoryBuckets::new()).default_quota(Quota::per_minute(3))),
+    };
+    let addr = start_with_options(Arc::new(MemoryStore::new()), options).await;
+
+    for guess in &["guess-1", "guess-2", "guess-3"] {
+        let reply = send_as(addr, guess, Method::GET, "/users", "").await;
+        assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+    }
+    let reply = send_as(addr, "guess-4", Method::GET, "/users", "").await;
+    assert_eq!(reply.status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(reply.header("retry-after").is_some());
+
+    // Even a right guess is turned away now
+    let reply = send_as(addr, "alice-token", Method::GET, "/users", "").await;
+    assert_eq!(reply.status, StatusCode::TOO_MANY_REQUESTS);
+}
@@ -0,0 +1,102 @@
+// Shared by several test binaries, each of which only uses some helpers
+#![allow(dead_code)]
+
+use hyper::header::HeaderMap;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use hyper_microservice::handlers::UserDb;
+use hyper_microservice::server::{self, Options};
+use hyper_microservice::store::MemoryStore;
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// What came back for a request.
+pub struct Reply {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// The body as text, binary formats are in `bytes`.
+    pub body: String,
+    pub bytes: Vec<u8>,
+}
+
+impl Reply {
+    /// Parses the body as JSON, failing the test if it isn't.
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body)
+            .unwrap_or_else(|err| panic!("body is not JSON ({}): {}", err, self.body))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|value| value.to_str().unwrap())
+    }
+}
+
+/// Starts the service with an in-memory store on an ephemeral port.
+pub async fn start() -> SocketAddr {
+    start_with(Arc::new(MemoryStore::new())).await
+}
+
+/// Starts the service with the given store on an ephemeral port.
+pub async fn start_with(user_db: UserDb) -> SocketAddr {
+    start_with_options(user_db, Options::default()).await
+}
+
+/// Starts the service with the given store and options on an ephemeral port.
+pub async fn start_with_options(user_db: UserDb, options: Options) -> SocketAddr {
+    let shutdown = std::future::pending();
+    let addr = ([127, 0, 0, 1], 0).into();
+    let (addr, server) = server::bind(&addr, user_db, options, shutdown).unwrap();
+    tokio::spawn(server);
+    addr
+}
+
+/// Sends a request to the service.
+pub async fn send(addr: SocketAddr, method: Method, path: &str, body: &str) -> Reply {
+    send_with(addr, method, path, &[], body).await
+}
+
+/// Sends a request with extra headers to the service.
+pub async fn send_with(
+    addr: SocketAddr,
+    method: Method,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> Reply {
+    send_bytes(addr, method, path, headers, body.as_bytes().to_vec()).await
+}
+
+/// Sends a request with a binary body to the service.
+pub async fn send_bytes(
+    addr: SocketAddr,
+    method: Method,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Vec<u8>,
+) -> Reply {
+    let mut req = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path));
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+    let req = req.body(Body::from(body)).unwrap();
+    let resp = Client::new().request(req).await.unwrap();
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    Reply {
+        status,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+        bytes: body.to_vec(),
+    }
+}
+
+/// Creates a user and returns its id.
+pub async fn create(addr: SocketAddr, name: &str, email: &str) -> u64 {
+    let body = serde_json::json!({ "name": name, "email": email }).to_string();
+    let reply = send(addr, Method::POST, "/user/", &body).await;
+    assert_eq!(reply.status, StatusCode::CREATED, "{}", reply.body);
+    reply.json()["id"].as_u64().unwrap()
+}
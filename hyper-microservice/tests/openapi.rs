@@ -0,0 +1,225 @@
+//! Keeps `/openapi.json` honest: every documented operation is called,
+//! and each reply must have a documented status and a body matching the
+//! documented schema. Adding a route or changing a type without the docs
+//! following makes this fail.
+
+mod common;
+
+use common::{send_with, Reply};
+use hyper::{Method, StatusCode};
+use hyper_microservice::handlers;
+use hyper_microservice::openapi;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+
+const ALICE: &str = r#"{"name": "Alice", "email": "alice@example.com"}"#;
+
+/// Sends requests and checks them against the document.
+struct Checker {
+    addr: SocketAddr,
+    spec: Value,
+    called: BTreeSet<(String, String)>,
+}
+
+impl Checker {
+    /// Sends a request to the route documented as `template`.
+    async fn call(
+        &mut self,
+        method: Method,
+        template: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> Reply {
+        let name = method.as_str().to_lowercase();
+        let operation = self.spec["paths"][template][&name].clone();
+        assert!(
+            operation["summary"].is_string(),
+            "{} {} is not documented",
+            method,
+            template
+        );
+        self.called.insert((template.to_owned(), name));
+
+        let reply = send_with(self.addr, method.clone(), path, headers, body).await;
+        let status = reply.status.as_u16().to_string();
+        let documented = &operation["responses"][&status];
+        assert!(
+            documented.is_object(),
+            "{} {} answered an undocumented {}: {}",
+            method,
+            path,
+            status,
+            reply.body
+        );
+
+        // Bodies of requests that worked must match the request schema
+        let content_type = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .map_or("application/json", |(_, value)| *value);
+        if reply.status.is_success() && !body.is_empty() {
+            let schema = &operation["requestBody"]["content"][content_type]["schema"];
+            self.validate(schema, &serde_json::from_str(body).unwrap(), "request");
+        }
+        let is_json = reply
+            .header("content-type")
+            .is_some_and(|value| value.starts_with("application/json"));
+        if is_json && template != "/openapi.json" {
+            let schema = &documented["content"]["application/json"]["schema"];
+            self.validate(schema, &reply.json(), &format!("{} {}", method, path));
+        } else if !is_json {
+            assert!(
+                reply.body.is_empty() || documented.get("content").is_none(),
+                "{} {} sent a body that isn't JSON",
+                method,
+                path
+            );
+        }
+        reply
+    }
+
+    fn validate(&self, schema: &Value, instance: &Value, what: &str) {
+        assert!(schema.is_object(), "no schema for {}", what);
+        // References point into the components of the whole document
+        let mut root = schema.clone();
+        root["components"] = self.spec["components"].clone();
+        let validator = jsonschema::draft202012::new(&root).unwrap();
+        if let Err(err) = validator.validate(instance) {
+            panic!("{} doesn't match the document: {}\n{}", what, err, instance);
+        }
+    }
+
+    /// Fails unless every documented operation has been called.
+    fn assert_all_called(&self) {
+        let mut missing = Vec::new();
+        for (path, operations) in self.spec["paths"].as_object().unwrap() {
+            for method in operations.as_object().unwrap().keys() {
+                if !self.called.contains(&(path.clone(), method.clone())) {
+                    missing.push(format!("{} {}", method, path));
+                }
+            }
+        }
+        assert!(missing.is_empty(), "never called: {:?}", missing);
+    }
+}
+
+#[tokio::test]
+async fn served_document_is_the_generated_one() {
+    let addr = common::start().await;
+    let reply = send_with(addr, Method::GET, "/openapi.json", &[], "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.header("content-type"), Some("application/json"));
+    let spec = reply.json();
+    assert_eq!(spec, openapi::spec(&handlers::routes()));
+    assert_eq!(spec["openapi"], openapi::OPENAPI_VERSION);
+
+    // The tagged batch operations are spelled out
+    let operation = &spec["components"]["schemas"]["Operation"]["oneOf"];
+    let ops = operation
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|variant| variant["properties"]["op"]["const"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(ops, vec!["create", "update", "delete"]);
+    let parameters = &spec["paths"]["/user/{id}"]["get"]["parameters"];
+    assert_eq!(parameters[0]["name"], "id");
+    assert_eq!(parameters[0]["in"], "path");
+}
+
+#[tokio::test]
+async fn handlers_do_what_the_document_says() {
+    let addr = common::start().await;
+    let reply = send_with(addr, Method::GET, "/openapi.json", &[], "").await;
+    let mut api = Checker {
+        addr,
+        spec: reply.json(),
+        called: BTreeSet::new(),
+    };
+    let json_patch = [("content-type", "application/json-patch+json")];
+    let merge_patch = [("content-type", "application/merge-patch+json")];
+
+    for path in &["/", "/index.htm", "/index.html"] {
+        api.call(Method::GET, path, path, &[], "").await;
+    }
+    let png = [("accept", "image/png")];
+    api.call(Method::GET, "/", "/", &png, "").await;
+    api.call(Method::GET, "/openapi.json", "/openapi.json", &[], "")
+        .await;
+
+    // Users 0 and 1
+    let reply = api.call(Method::POST, "/user", "/user", &[], ALICE).await;
+    assert_eq!(reply.status, StatusCode::CREATED);
+    let bob = r#"{"name": "Bob", "email": "bob@example.com"}"#;
+    api.call(Method::POST, "/user", "/user", &[], bob).await;
+    let bad = r#"{"name": "", "email": "nope"}"#;
+    let reply = api.call(Method::POST, "/user", "/user", &[], bad).await;
+    assert_eq!(reply.status, StatusCode::UNPROCESSABLE_ENTITY);
+    api.call(Method::POST, "/user", "/user", &[], "{").await;
+    api.call(Method::POST, "/user/{id}", "/user/5", &[], ALICE)
+        .await;
+
+    let reply = api.call(Method::GET, "/users", "/users", &[], "").await;
+    assert_eq!(reply.json()["total"], 2);
+    api.call(Method::GET, "/users", "/users?limit=0", &[], "")
+        .await;
+
+    let reply = api
+        .call(Method::GET, "/user/{id}", "/user/0", &[], "")
+        .await;
+    let etag = reply.header("etag").unwrap().to_owned();
+    let current = [("if-none-match", etag.as_str())];
+    let reply = api
+        .call(Method::GET, "/user/{id}", "/user/0", &current, "")
+        .await;
+    assert_eq!(reply.status, StatusCode::NOT_MODIFIED);
+    api.call(Method::GET, "/user/{id}", "/user/99", &[], "")
+        .await;
+
+    let alicia = r#"{"name": "Alicia", "email": "alice@example.com"}"#;
+    api.call(Method::PUT, "/user/{id}", "/user/0", &[], alicia)
+        .await;
+    let stale = [("if-match", "\"99\"")];
+    let reply = api
+        .call(Method::PUT, "/user/{id}", "/user/0", &stale, alicia)
+        .await;
+    assert_eq!(reply.status, StatusCode::PRECONDITION_FAILED);
+
+    let patch = r#"{"name": "Ali"}"#;
+    api.call(Method::PATCH, "/user/{id}", "/user/0", &merge_patch, patch)
+        .await;
+    let failing = r#"[{"op": "test", "path": "/name", "value": "Zed"}]"#;
+    let reply = api
+        .call(Method::PATCH, "/user/{id}", "/user/0", &json_patch, failing)
+        .await;
+    assert_eq!(reply.status, StatusCode::CONFLICT);
+    api.call(Method::PATCH, "/user/{id}", "/user/0", &[], patch)
+        .await;
+
+    let batch = r#"{"operations": [{"op": "update", "id": 1, "user": {"name": "Robert", "email": "bob@example.com"}}]}"#;
+    let reply = api
+        .call(Method::POST, "/users/batch", "/users/batch", &[], batch)
+        .await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let batch = r#"{"mode": "best_effort", "operations": [
+        {"op": "create", "user": {"name": "Carol", "email": "carol@example.com"}},
+        {"op": "delete", "id": 99}
+    ]}"#;
+    let reply = api
+        .call(Method::POST, "/users/batch", "/users/batch", &[], batch)
+        .await;
+    assert_eq!(reply.status, StatusCode::MULTI_STATUS);
+
+    api.call(Method::DELETE, "/user/{id}", "/user/1", &[], "")
+        .await;
+    api.call(Method::GET, "/user/{id}", "/user/1", &[], "")
+        .await;
+    let reply = api
+        .call(Method::DELETE, "/user/{id}", "/user/1", &[], "")
+        .await;
+    assert_eq!(reply.status, StatusCode::GONE);
+
+    api.assert_all_called();
+}
@@ -0,0 +1,248 @@
+mod common;
+
+use common::send;
+use hyper::{Method, StatusCode};
+use hyper_microservice::auth::Auth;
+use hyper_microservice::handlers::UserDb;
+use hyper_microservice::server::{self, Options};
+use hyper_microservice::sessions::RedisSessions;
+use hyper_microservice::store::{FileStore, MemoryStore, StoreError};
+use middleware::rate_limit::MemoryBuckets;
+use middleware::{Quota, RateLimit};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn stops_after_shutdown_signal() {
+    let user_db: UserDb = Arc::new(MemoryStore::new());
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let shutdown = async {
+        rx.await.ok();
+    };
+    let addr = ([127, 0, 0, 1], 0).into();
+    let (addr, server) = server::bind(&addr, user_db, Options::default(), shutdown).unwrap();
+    let server = tokio::spawn(server);
+
+    let reply = send(addr, Method::GET, "/", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+
+    tx.send(()).unwrap();
+    let result = tokio::time::timeout(Duration::from_secs(5), server).await;
+    assert!(matches!(result, Ok(Ok(Ok(())))));
+}
+
+#[tokio::test]
+async fn file_store_survives_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("users.log");
+
+    let addr = common::start_with(Arc::new(FileStore::open(&path).unwrap())).await;
+    let alice = common::create(addr, "Alice", "alice@example.com").await;
+    let bob = common::create(addr, "Bob", "bob@example.com").await;
+    let reply = send(addr, Method::DELETE, &format!("/user/{}", alice), "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+
+    // A second server replaying the same log sees the same users
+    let addr = common::start_with(Arc::new(FileStore::open(&path).unwrap())).await;
+    let reply = send(addr, Method::GET, "/users", "").await;
+    let ids = reply.json()["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|user| user["id"].as_u64().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec![bob]);
+
+    // Deleted ids stay retired after the restart
+    let reply = send(addr, Method::GET, &format!("/user/{}", alice), "").await;
+    assert_eq!(reply.status, StatusCode::GONE);
+    let carol = common::create(addr, "Carol", "carol@example.com").await;
+    assert!(carol > bob);
+}
+
+#[tokio::test]
+async fn file_store_drops_a_torn_last_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("users.log");
+
+    let addr = common::start_with(Arc::new(FileStore::open(&path).unwrap())).await;
+    let alice = common::create(addr, "Alice", "alice@example.com").await;
+    // A crash halfway through the next append
+    let mut log = OpenOptions::new().append(true).open(&path).unwrap();
+    log.write_all(br#"{"op":"insert","id":1,"user":{"na"#)
+        .unwrap();
+
+    let addr = common::start_with(Arc::new(FileStore::open(&path).unwrap())).await;
+    let bob = common::create(addr, "Bob", "bob@example.com").await;
+    let addr = common::start_with(Arc::new(FileStore::open(&path).unwrap())).await;
+    let reply = send(addr, Method::GET, "/users", "").await;
+    let names = reply.json()["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|user| user["name"].as_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["Alice", "Bob"]);
+    assert_eq!(bob, alice + 1);
+
+    // Anywhere else a bad line is a real problem
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::write(&path, format!("{{\"op\":\n{}", contents)).unwrap();
+    match FileStore::open(&path) {
+        Err(StoreError::Corrupt(msg)) => assert!(msg.starts_with("line 1:"), "{}", msg),
+        other => panic!("expected a corrupt log, got {:?}", other.map(drop)),
+    }
+}
+
+#[tokio::test]
+async fn file_store_keeps_batches() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("users.log");
+
+    let addr = common::start_with(Arc::new(FileStore::open(&path).unwrap())).await;
+    let batch = r#"{"operations": [
+        {"op": "create", "user": {"name": "Alice", "email": "alice@example.com"}},
+        {"op": "create", "user": {"name": "Bob", "email": "bob@example.com"}},
+        {"op": "update", "id": 1, "user": {"name": "Robert", "email": "bob@example.com"}},
+        {"op": "delete", "id": 0}
+    ]}"#;
+    let reply = send(addr, Method::POST, "/users/batch", batch).await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+
+    // A failed atomic batch must leave nothing in the log
+    let batch = r#"{"operations": [
+        {"op": "create", "user": {"name": "Carol", "email": "carol@example.com"}},
+        {"op": "delete", "id": 0}
+    ]}"#;
+    let reply = send(addr, Method::POST, "/users/batch", batch).await;
+    assert_eq!(reply.json()["committed"], false);
+
+    let addr = common::start_with(Arc::new(FileStore::open(&path).unwrap())).await;
+    let reply = send(addr, Method::GET, "/users", "").await;
+    let items = reply.json()["items"].clone();
+    assert_eq!(items.as_array().unwrap().len(), 1);
+    assert_eq!(items[0]["name"], "Robert");
+    let reply = send(addr, Method::GET, "/user/1", "").await;
+    assert_eq!(reply.header("etag"), Some("\"2\""));
+    let reply = send(addr, Method::GET, "/user/0", "").await;
+    assert_eq!(reply.status, StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn responses_go_through_the_middlewares() {
+    let addr = common::start().await;
+
+    let headers = [("x-request-id", "trace-me")];
+    let reply = common::send_with(addr, Method::GET, "/users", &headers, "").await;
+    assert_eq!(reply.header("x-request-id"), Some("trace-me"));
+    assert!(reply.header("server-timing").is_some());
+
+    // Browsers ask before cross-origin writes
+    let headers = [
+        ("origin", "https://app.example.com"),
+        ("access-control-request-method", "PUT"),
+    ];
+    let reply = common::send_with(addr, Method::OPTIONS, "/user/0", &headers, "").await;
+    assert_eq!(reply.status, StatusCode::NO_CONTENT);
+    assert_eq!(reply.header("access-control-allow-origin"), Some("*"));
+}
+
+#[tokio::test]
+async fn rate_limits_apply_per_route() {
+    let limit = RateLimit::new(MemoryBuckets::new())
+        .default_quota(Quota::per_minute(100))
+        .route("POST /users/batch=1/min".parse().unwrap());
+    let options = Options {
+        rate_limit: Some(limit),
+        ..Options::default()
+    };
+    let addr = common::start_with_options(Arc::new(MemoryStore::new()), options).await;
+
+    let batch = r#"{"operations": []}"#;
+    let reply = send(addr, Method::POST, "/users/batch", batch).await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    assert_eq!(reply.header("ratelimit-policy"), Some("1;w=60"));
+    let reply = send(addr, Method::POST, "/users/batch", batch).await;
+    assert_eq!(reply.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(reply.header("retry-after"), Some("60"));
+    // The rest of the API is still open
+    let reply = send(addr, Method::GET, "/users", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.header("ratelimit-remaining"), Some("99"));
+}
+
+#[tokio::test]
+async fn metrics_are_scrapeable() {
+    let addr = common::start().await;
+    common::create(addr, "Alice", "alice@example.com").await;
+    send(addr, Method::GET, "/user/0", "").await;
+    send(addr, Method::GET, "/user/7", "").await;
+    send(addr, Method::GET, "/nowhere", "").await;
+
+    let reply = send(addr, Method::GET, "/metrics", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert!(reply
+        .header("content-type")
+        .unwrap()
+        .starts_with("text/plain"));
+    let lines = reply.body.lines().collect::<Vec<_>>();
+    // Requests are counted by route, not by path
+    for line in &[
+        r#"http_requests_total{method="POST",route="/user",status="201"} 1"#,
+        r#"http_requests_total{method="GET",route="/user/{id}",status="200"} 1"#,
+        r#"http_requests_total{method="GET",route="/user/{id}",status="404"} 1"#,
+        r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#,
+        "users_stored 1",
+    ] {
+        assert!(lines.contains(line), "no {} in\n{}", line, reply.body);
+    }
+    assert!(reply.body.contains("http_request_duration_seconds_bucket{"));
+    assert!(reply.body.contains("http_requests_in_flight"));
+}
+
+#[tokio::test]
+async fn readiness_follows_the_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("users.log");
+    let addr = common::start_with(Arc::new(FileStore::open(&path).unwrap())).await;
+
+    let reply = send(addr, Method::GET, "/healthz", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let reply = send(addr, Method::GET, "/readyz", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.body, "[+]store ok\nready\n");
+
+    // Writes would go nowhere once the log is gone
+    std::fs::remove_file(&path).unwrap();
+    let reply = send(addr, Method::GET, "/readyz", "").await;
+    assert_eq!(reply.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(
+        reply.body.starts_with("[-]store failed: "),
+        "{}",
+        reply.body
+    );
+    let reply = send(addr, Method::GET, "/healthz", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn readiness_checks_redis() {
+    // Nothing listens on port 1
+    let auth = Auth {
+        sessions: Arc::new(RedisSessions::connect("redis://127.0.0.1:1/").unwrap()),
+        admins: Default::default(),
+    };
+    let options = Options {
+        auth: Some(auth),
+        ..Options::default()
+    };
+    let addr = common::start_with_options(Arc::new(MemoryStore::new()), options).await;
+
+    // Probes don't need a token
+    let reply = send(addr, Method::GET, "/readyz", "").await;
+    assert_eq!(reply.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(reply.body.contains("[+]store ok\n"), "{}", reply.body);
+    assert!(reply.body.contains("[-]sessions "), "{}", reply.body);
+}
@@ -0,0 +1,42 @@
+use crate::{BoxFuture, ClientAddr, Middleware, Next, RequestId};
+use hyper::{Body, Request};
+use log::info;
+use std::time::Instant;
+
+/// Logs one line per request once its response is ready: client, method,
+/// path, status, time taken and request id. Lines go to the `access`
+/// target, so they can be filtered with `RUST_LOG=access=info`.
+/// Add it after `RequestIds` to get the ids.
+pub struct AccessLog;
+
+impl Middleware for AccessLog {
+    fn handle(&self, req: Request<Body>, next: Next) -> BoxFuture {
+        let start = Instant::now();
+        let method = req.method().clone();
+        let path = req.uri().path().to_owned();
+        let client = req
+            .extensions()
+            .get::<ClientAddr>()
+            .map_or_else(|| "-".to_owned(), |client| client.0.to_string());
+        let id = req
+            .extensions()
+            .get::<RequestId>()
+            .map_or_else(|| "-".to_owned(), |id| id.0.clone());
+
+        let response = next.run(req);
+        Box::pin(async move {
+            let response = response.await;
+            info!(
+                target: "access",
+                "{} {} {} {} {:.1}ms id={}",
+                client,
+                method,
+                path,
+                response.status().as_u16(),
+                start.elapsed().as_secs_f64() * 1000.0,
+                id
+            );
+            response
+        })
+    }
+}
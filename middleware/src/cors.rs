@@ -0,0 +1,189 @@
+use crate::{BoxFuture, Middleware, Next};
+use hyper::header::{
+    HeaderName, HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE,
+    ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::time::Duration;
+
+/// Which origins may call the service from a browser.
+enum Origins {
+    Any,
+    List(Vec<String>),
+}
+
+/// Cross-origin resource sharing. Answers preflight requests on its own
+/// and tags the responses to allowed origins. Requests from origins that
+/// aren't allowed still go through, they just get no CORS headers, so
+/// browsers keep the response from the calling page. With a list of
+/// origins every response says `Vary: Origin`.
+///
+/// Credentials (cookies) are never allowed, the services don't use them.
+pub struct Cors {
+    origins: Origins,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+    expose: Vec<HeaderName>,
+    max_age: Duration,
+}
+
+impl Cors {
+    /// Allows every origin.
+    pub fn any() -> Self {
+        Self::with_origins(Origins::Any)
+    }
+
+    /// Allows only the given origins, such as `https://example.com`.
+    pub fn origins<I, S>(origins: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::with_origins(Origins::List(origins.into_iter().map(Into::into).collect()))
+    }
+
+    fn with_origins(origins: Origins) -> Self {
+        Cors {
+            origins,
+            methods: vec![
+                Method::GET,
+                Method::HEAD,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ],
+            headers: vec![
+                HeaderName::from_static("accept"),
+                HeaderName::from_static("authorization"),
+                HeaderName::from_static("content-type"),
+                HeaderName::from_static("if-match"),
+                HeaderName::from_static("if-none-match"),
+                HeaderName::from_static("x-request-id"),
+            ],
+            expose: vec![
+                HeaderName::from_static("etag"),
+                HeaderName::from_static("x-request-id"),
+                HeaderName::from_static("ratelimit-limit"),
+                HeaderName::from_static("ratelimit-remaining"),
+                HeaderName::from_static("ratelimit-reset"),
+                HeaderName::from_static("ratelimit-policy"),
+                HeaderName::from_static("retry-after"),
+            ],
+            max_age: Duration::from_secs(600),
+        }
+    }
+
+    /// Replaces the methods allowed in cross-origin requests.
+    pub fn allow_methods(mut self, methods: Vec<Method>) -> Self {
+        self.methods = methods;
+        self
+    }
+
+    /// Replaces the request headers cross-origin requests may send.
+    pub fn allow_headers(mut self, headers: Vec<HeaderName>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Replaces the response headers pages may read.
+    pub fn expose_headers(mut self, headers: Vec<HeaderName>) -> Self {
+        self.expose = headers;
+        self
+    }
+
+    /// Lets pages read one more response header besides the others.
+    pub fn expose_header(mut self, header: HeaderName) -> Self {
+        self.expose.push(header);
+        self
+    }
+
+    /// Sets how long browsers may cache a preflight answer.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// The `Access-Control-Allow-Origin` value for a request's origin,
+    /// if it is allowed.
+    fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        match &self.origins {
+            Origins::Any => Some(HeaderValue::from_static("*")),
+            Origins::List(origins) => {
+                let origin_str = origin.to_str().ok()?;
+                if origins.iter().any(|allowed| allowed == origin_str) {
+                    Some(origin.clone())
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Joins names into a comma separated header value.
+fn list<'a>(names: impl Iterator<Item = &'a str>) -> HeaderValue {
+    // Method and header names are always valid header characters
+    HeaderValue::from_str(&names.collect::<Vec<_>>().join(", ")).unwrap()
+}
+
+impl Middleware for Cors {
+    fn handle(&self, req: Request<Body>, next: Next) -> BoxFuture {
+        // With a list the answer depends on the origin, even when there is
+        // none or it isn't allowed, so caches have to keep them apart
+        let vary = matches!(self.origins, Origins::List(_));
+        let allow_origin = req
+            .headers()
+            .get(ORIGIN)
+            .and_then(|origin| self.allow_origin(origin));
+        let allow_origin = match allow_origin {
+            Some(allow_origin) => allow_origin,
+            None if vary => {
+                let response = next.run(req);
+                return Box::pin(async move {
+                    let mut response = response.await;
+                    let origin = HeaderValue::from_static("Origin");
+                    response.headers_mut().append(VARY, origin);
+                    response
+                });
+            }
+            None => return next.run(req),
+        };
+
+        let preflight = req.method() == Method::OPTIONS
+            && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+        if preflight {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NO_CONTENT;
+            let headers = response.headers_mut();
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_METHODS,
+                list(self.methods.iter().map(Method::as_str)),
+            );
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                list(self.headers.iter().map(HeaderName::as_str)),
+            );
+            headers.insert(ACCESS_CONTROL_MAX_AGE, self.max_age.as_secs().into());
+            if vary {
+                headers.append(VARY, HeaderValue::from_static("Origin"));
+            }
+            return Box::pin(async { response });
+        }
+
+        let expose = list(self.expose.iter().map(HeaderName::as_str));
+        let response = next.run(req);
+        Box::pin(async move {
+            let mut response = response.await;
+            let headers = response.headers_mut();
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+            headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, expose);
+            if vary {
+                headers.append(VARY, HeaderValue::from_static("Origin"));
+            }
+            response
+        })
+    }
+}
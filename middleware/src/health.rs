@@ -0,0 +1,162 @@
+//! Liveness and readiness probes.
+//!
+//! `Health` answers `GET /healthz` with 200 as long as the process can
+//! answer at all, and `GET /readyz` by running every check it was given.
+//! The service is ready when all of them pass within the timeout, else
+//! the probe gets a 503. Either way the body lists each check:
+//!
+//! ```text
+//! [+]store ok
+//! [-]sessions failed: connection refused
+//! not ready
+//! ```
+//!
+//! Checks run side by side, so a slow dependency costs one timeout, not
+//! one per check.
+
+use crate::{BoxFuture, MatchedRoute, Middleware, Next};
+use futures::future::join_all;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::warn;
+use std::fmt::{self, Display, Write};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Where `Health` answers liveness probes.
+pub const LIVE_PATH: &str = "/healthz";
+/// Where `Health` answers readiness probes.
+pub const READY_PATH: &str = "/readyz";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+type CheckFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+type CheckFn = dyn Fn() -> CheckFuture + Send + Sync;
+
+/// The probe middleware, see the module docs. Put it in front of
+/// anything that could turn probes away, like authentication.
+pub struct Health {
+    checks: Vec<(&'static str, Box<CheckFn>)>,
+    timeout: Duration,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health::new()
+    }
+}
+
+impl Health {
+    /// Without checks the service is always ready.
+    pub fn new() -> Self {
+        Health {
+            checks: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// How long each check may take before it counts as failed.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Adds a dependency that has to be reachable to be ready. `check`
+    /// is called for every readiness probe.
+    pub fn check<F, Fut, E>(mut self, name: &'static str, check: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        let check = move || -> CheckFuture {
+            let result = check();
+            Box::pin(async move { result.await.map_err(|err| err.to_string()) })
+        };
+        self.checks.push((name, Box::new(check)));
+        self
+    }
+}
+
+/// How one check went.
+enum Status {
+    Ok,
+    Failed(String),
+    TimedOut(Duration),
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Ok => f.write_str("ok"),
+            Status::Failed(reason) => write!(f, "failed: {}", reason),
+            Status::TimedOut(timeout) => write!(f, "timed out after {:?}", timeout),
+        }
+    }
+}
+
+fn text(status: StatusCode, body: String, path: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response
+        .extensions_mut()
+        .insert(MatchedRoute(path.to_owned()));
+    response
+}
+
+impl Middleware for Health {
+    fn handle(&self, req: Request<Body>, next: Next) -> BoxFuture {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return next.run(req);
+        }
+        match req.uri().path() {
+            LIVE_PATH => {
+                let response = text(StatusCode::OK, "ok\n".into(), LIVE_PATH);
+                Box::pin(async { response })
+            }
+            READY_PATH => {
+                let timeout = self.timeout;
+                let runs = self.checks.iter().map(|(name, check)| {
+                    let run = check();
+                    let name = *name;
+                    async move {
+                        let status = match tokio::time::timeout(timeout, run).await {
+                            Ok(Ok(())) => Status::Ok,
+                            Ok(Err(reason)) => Status::Failed(reason),
+                            Err(_) => Status::TimedOut(timeout),
+                        };
+                        (name, status)
+                    }
+                });
+                let runs = join_all(runs);
+                Box::pin(async move {
+                    let mut ready = true;
+                    let mut body = String::new();
+                    for (name, status) in runs.await {
+                        let passed = matches!(status, Status::Ok);
+                        if !passed {
+                            warn!("Readiness check {} {}", name, status);
+                            ready = false;
+                        }
+                        let sign = if passed { '+' } else { '-' };
+                        // Writing to a String can't fail
+                        writeln!(body, "[{}]{} {}", sign, name, status).unwrap();
+                    }
+                    if ready {
+                        body.push_str("ready\n");
+                        text(StatusCode::OK, body, READY_PATH)
+                    } else {
+                        body.push_str("not ready\n");
+                        text(StatusCode::SERVICE_UNAVAILABLE, body, READY_PATH)
+                    }
+                })
+            }
+            _ => next.run(req),
+        }
+    }
+}
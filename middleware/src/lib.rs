@@ -0,0 +1,177 @@
+//! Composable middleware for the hyper services in this repo.
+//!
+//! A handler is anything that turns a request into a future response. A
+//! middleware sits in front of it, gets the request first and decides
+//! what to do with the rest of the stack, handed to it as `Next`. It can
+//! change the request, answer on its own, or run `Next` and then change
+//! the response.
+//!
+//! ```ignore
+//! let stack = Pipeline::new()
+//!     .with(RequestIds)
+//!     .with(CatchPanic)
+//!     .with(AccessLog)
+//!     .handler(my_handler);
+//! ```
+//!
+//! Middlewares run in the order they were added, so the first one sees
+//! the request first and the response last. A `Stack` is cheap to clone
+//! and is meant to be shared by every connection, see `Stack::serve`.
+
+mod access_log;
+mod cors;
+pub mod health;
+pub mod metrics;
+mod panic;
+pub mod rate_limit;
+mod request_id;
+mod timing;
+
+pub use crate::access_log::AccessLog;
+pub use crate::cors::Cors;
+pub use crate::health::Health;
+pub use crate::metrics::{MatchedRoute, Metrics};
+pub use crate::panic::CatchPanic;
+pub use crate::rate_limit::{Quota, RateLimit, Rule};
+pub use crate::request_id::{RequestId, RequestIds, REQUEST_ID_HEADER};
+pub use crate::timing::Timing;
+// Services register their own metrics with the same version
+pub use prometheus;
+
+use hyper::{Body, Request, Response};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// What handlers and middlewares eventually produce.
+pub type BoxFuture = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
+
+/// The end of a stack, the code that actually answers requests.
+/// Async functions and closures taking a request work as handlers.
+pub trait Handler: Send + Sync + 'static {
+    fn call(&self, req: Request<Body>) -> BoxFuture;
+}
+
+impl<F, Fut> Handler for F
+where
+    F: Fn(Request<Body>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response<Body>> + Send + 'static,
+{
+    fn call(&self, req: Request<Body>) -> BoxFuture {
+        Box::pin(self(req))
+    }
+}
+
+/// Something that wraps the rest of the stack.
+/// Closures taking a request and `Next` work as middlewares too.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, req: Request<Body>, next: Next) -> BoxFuture;
+}
+
+impl<F, Fut> Middleware for F
+where
+    F: Fn(Request<Body>, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response<Body>> + Send + 'static,
+{
+    fn handle(&self, req: Request<Body>, next: Next) -> BoxFuture {
+        Box::pin(self(req, next))
+    }
+}
+
+/// The rest of the stack behind a middleware.
+pub struct Next {
+    rest: Arc<dyn Handler>,
+}
+
+impl Next {
+    /// Passes the request on to the rest of the stack.
+    pub fn run(self, req: Request<Body>) -> BoxFuture {
+        self.rest.call(req)
+    }
+}
+
+/// A middleware bound to whatever comes after it.
+struct Layer {
+    middleware: Arc<dyn Middleware>,
+    next: Arc<dyn Handler>,
+}
+
+impl Handler for Layer {
+    fn call(&self, req: Request<Body>) -> BoxFuture {
+        let next = Next {
+            rest: self.next.clone(),
+        };
+        self.middleware.handle(req, next)
+    }
+}
+
+/// Collects middlewares until the handler is known.
+#[derive(Default)]
+pub struct Pipeline {
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a middleware behind the ones added so far.
+    pub fn with<M: Middleware>(mut self, middleware: M) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Puts the handler at the end of the pipeline.
+    pub fn handler<H: Handler>(self, handler: H) -> Stack {
+        let mut stack: Arc<dyn Handler> = Arc::new(handler);
+        for middleware in self.middlewares.into_iter().rev() {
+            stack = Arc::new(Layer {
+                middleware,
+                next: stack,
+            });
+        }
+        Stack { stack }
+    }
+}
+
+/// The address of the client that sent a request, put into the request
+/// extensions by `Stack::serve`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClientAddr(pub SocketAddr);
+
+/// A finished pipeline, ready to answer requests.
+#[derive(Clone)]
+pub struct Stack {
+    stack: Arc<dyn Handler>,
+}
+
+impl Stack {
+    /// Runs a request through every middleware and the handler.
+    pub fn handle(&self, req: Request<Body>) -> BoxFuture {
+        self.stack.call(req)
+    }
+
+    /// Answers a request from a connection with `client`, in the shape
+    /// `hyper::service::service_fn` wants:
+    ///
+    /// ```ignore
+    /// make_service_fn(move |conn: &AddrStream| {
+    ///     let (stack, client) = (stack.clone(), conn.remote_addr());
+    ///     async move {
+    ///         Ok::<_, Infallible>(service_fn(move |req| stack.serve(client, req)))
+    ///     }
+    /// })
+    /// ```
+    pub fn serve(
+        &self,
+        client: SocketAddr,
+        mut req: Request<Body>,
+    ) -> impl Future<Output = Result<Response<Body>, Infallible>> {
+        req.extensions_mut().insert(ClientAddr(client));
+        let response = self.handle(req);
+        async move { Ok(response.await) }
+    }
+}
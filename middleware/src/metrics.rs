@@ -0,0 +1,155 @@
+//! Prometheus metrics.
+//!
+//! `Metrics` counts and times every request by method, route and status,
+//! keeps track of the requests in flight, and answers `GET /metrics` with
+//! everything in its registry in the Prometheus text format. Services put
+//! their own metrics into the same registry.
+//!
+//! Requests are labeled by the route that matched rather than the path,
+//! so `/user/1` and `/user/2` count as one. The handler says which route
+//! matched by putting a `MatchedRoute` into the response extensions,
+//! anything without one is counted as `unmatched`.
+
+use crate::{BoxFuture, Middleware, Next};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::error;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::time::Instant;
+
+/// Where `Metrics` answers scrapes.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// The route pattern a response was produced by, like `/user/{id}`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchedRoute(pub String);
+
+type Hook = Box<dyn Fn() + Send + Sync>;
+
+/// The metrics middleware, see the module docs. Put it first, so it also
+/// sees the requests other middlewares answer on their own.
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    durations: HistogramVec,
+    in_flight: IntGauge,
+    on_scrape: Vec<Hook>,
+}
+
+impl Metrics {
+    /// Registers the request metrics in `registry`.
+    pub fn new(registry: Registry) -> Result<Self, prometheus::Error> {
+        let labels = &["method", "route", "status"];
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Requests answered"),
+            labels,
+        )?;
+        let durations = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to answer requests",
+            ),
+            labels,
+        )?;
+        let in_flight = IntGauge::new("http_requests_in_flight", "Requests being answered")?;
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(durations.clone()))?;
+        registry.register(Box::new(in_flight.clone()))?;
+        Ok(Metrics {
+            registry,
+            requests,
+            durations,
+            in_flight,
+            on_scrape: Vec::new(),
+        })
+    }
+
+    /// Runs `hook` before every scrape, to update gauges that are cheaper
+    /// to read when asked than to keep current.
+    pub fn on_scrape<F: Fn() + Send + Sync + 'static>(mut self, hook: F) -> Self {
+        self.on_scrape.push(Box::new(hook));
+        self
+    }
+
+    fn scrape(&self) -> Response<Body> {
+        for hook in &self.on_scrape {
+            hook();
+        }
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        if let Err(err) = encoder.encode(&self.registry.gather(), &mut buffer) {
+            error!("Can't encode metrics: {}", err);
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            return response;
+        }
+        let mut response = Response::new(Body::from(buffer));
+        // The text format's content type is a fixed ASCII string
+        let content_type = HeaderValue::from_str(encoder.format_type()).unwrap();
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+        response
+    }
+}
+
+/// Takes a request out of the in-flight count when it is done, even if
+/// its handler panics.
+struct InFlight(IntGauge);
+
+impl InFlight {
+    fn start(gauge: &IntGauge) -> Self {
+        gauge.inc();
+        InFlight(gauge.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// The method label, made up methods would give endless labels.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::PATCH => "PATCH",
+        Method::DELETE => "DELETE",
+        Method::OPTIONS => "OPTIONS",
+        _ => "other",
+    }
+}
+
+impl Middleware for Metrics {
+    fn handle(&self, req: Request<Body>, next: Next) -> BoxFuture {
+        if req.method() == Method::GET && req.uri().path() == METRICS_PATH {
+            let response = self.scrape();
+            return Box::pin(async { response });
+        }
+
+        let method = method_label(req.method());
+        let requests = self.requests.clone();
+        let durations = self.durations.clone();
+        let in_flight = InFlight::start(&self.in_flight);
+        let started = Instant::now();
+        Box::pin(async move {
+            let response = next.run(req).await;
+            let route = match response.extensions().get::<MatchedRoute>() {
+                Some(MatchedRoute(route)) => route.as_str(),
+                None => "unmatched",
+            };
+            let status = response.status();
+            let labels = [method, route, status.as_str()];
+            requests.with_label_values(&labels).inc();
+            durations
+                .with_label_values(&labels)
+                .observe(started.elapsed().as_secs_f64());
+            drop(in_flight);
+            response
+        })
+    }
+}
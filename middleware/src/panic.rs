@@ -0,0 +1,47 @@
+use crate::{BoxFuture, Middleware, Next, RequestId};
+use futures::FutureExt;
+use hyper::{Body, Request, Response, StatusCode};
+use log::error;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Turns a panic anywhere behind it into a 500 instead of a dropped
+/// connection. Add it right after `RequestIds`, so it covers every other
+/// middleware and the 500 still carries the request id.
+pub struct CatchPanic;
+
+impl Middleware for CatchPanic {
+    fn handle(&self, req: Request<Body>, next: Next) -> BoxFuture {
+        let id = req.extensions().get::<RequestId>().cloned();
+        // Both building the response future and polling it may panic.
+        // Nothing is shared with the panicking code afterwards, so
+        // asserting unwind safety is fine.
+        let response = match panic::catch_unwind(AssertUnwindSafe(|| next.run(req))) {
+            Ok(response) => response,
+            Err(payload) => return Box::pin(async move { internal_error(payload, id) }),
+        };
+        Box::pin(async move {
+            match AssertUnwindSafe(response).catch_unwind().await {
+                Ok(response) => response,
+                Err(payload) => internal_error(payload, id),
+            }
+        })
+    }
+}
+
+/// Logs a caught panic and builds the response for it.
+fn internal_error(payload: Box<dyn Any + Send>, id: Option<RequestId>) -> Response<Body> {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown cause".to_owned());
+    match id {
+        Some(id) => error!("Request {} panicked: {}", id, message),
+        None => error!("Request panicked: {}", message),
+    }
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(Body::from("Internal Server Error"))
+        .unwrap()
+}
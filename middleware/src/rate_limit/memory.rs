@@ -0,0 +1,73 @@
+use super::{Buckets, Outcome, Quota, TakeFuture};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// How many buckets are kept before full ones are dropped.
+const PRUNE_AT: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    quota: Quota,
+}
+
+impl Bucket {
+    /// Tokens in the bucket at `now`.
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let refilled = now.duration_since(self.updated).as_secs_f64() * self.quota.rate();
+        (self.tokens + refilled).min(f64::from(self.quota.burst))
+    }
+}
+
+/// Keeps buckets in this process only, each instance limits on its own.
+#[derive(Default)]
+pub struct MemoryBuckets {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl MemoryBuckets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MemoryBuckets {
+    /// Refills the bucket under `key` and takes `cost` tokens if there is
+    /// at least one. A negative cost always gives tokens back.
+    fn spend(&self, key: &str, quota: Quota, cost: f64) -> TakeFuture {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_AT {
+            // A full bucket is the same as no bucket, forget those
+            buckets.retain(|_, bucket| bucket.tokens_at(now) < f64::from(bucket.quota.burst));
+        }
+
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: f64::from(quota.burst),
+            updated: now,
+            quota,
+        });
+        bucket.tokens = bucket.tokens_at(now);
+        bucket.updated = now;
+        let allowed = cost < 0.0 || bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens = (bucket.tokens - cost).min(f64::from(quota.burst));
+        }
+        let outcome = Outcome {
+            allowed,
+            tokens: bucket.tokens,
+        };
+        Box::pin(async move { Ok(outcome) })
+    }
+}
+
+impl Buckets for MemoryBuckets {
+    fn take(&self, key: &str, quota: Quota) -> TakeFuture {
+        self.spend(key, quota, 1.0)
+    }
+
+    fn refund(&self, key: &str, quota: Quota) -> TakeFuture {
+        self.spend(key, quota, -1.0)
+    }
+}
@@ -0,0 +1,363 @@
+//! Token bucket rate limiting.
+//!
+//! Every client gets a bucket per rule holding up to `burst` tokens,
+//! refilled evenly over `period`. Each request takes a token, and a
+//! request finding the bucket empty gets a 429 with `Retry-After`. All
+//! responses carry the `RateLimit-Limit`, `RateLimit-Remaining`,
+//! `RateLimit-Reset` and `RateLimit-Policy` headers.
+//!
+//! Clients are told apart by IP unless another key is set with
+//! `RateLimit::key_by`, for example to use an authenticated identity.
+//! A limiter can also charge only some responses, like failed logins,
+//! see `RateLimit::charge_only`.
+//! Buckets live in memory by default. With the `redis` feature they can
+//! live in redis instead, so several instances enforce one limit.
+
+mod memory;
+#[cfg(feature = "redis")]
+mod redis;
+
+pub use self::memory::MemoryBuckets;
+#[cfg(feature = "redis")]
+pub use self::redis::RedisBuckets;
+
+use crate::{BoxFuture, ClientAddr, Middleware, Next};
+use hyper::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::warn;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How many requests a client may send: `burst` at once, refilled evenly
+/// over `period`. Written as `<burst>/<period>`, like `60/min` or `5/s`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quota {
+    pub burst: u32,
+    pub period: Duration,
+}
+
+impl Quota {
+    pub fn per_second(burst: u32) -> Self {
+        Quota {
+            burst,
+            period: Duration::from_secs(1),
+        }
+    }
+
+    pub fn per_minute(burst: u32) -> Self {
+        Quota {
+            burst,
+            period: Duration::from_secs(60),
+        }
+    }
+
+    pub fn per_hour(burst: u32) -> Self {
+        Quota {
+            burst,
+            period: Duration::from_secs(3600),
+        }
+    }
+
+    /// Tokens added back per second.
+    pub fn rate(&self) -> f64 {
+        f64::from(self.burst) / self.period.as_secs_f64()
+    }
+
+    /// Seconds until a bucket holding `tokens` has `wanted` again.
+    fn secs_until(&self, tokens: f64, wanted: f64) -> u64 {
+        ((wanted - tokens).max(0.0) / self.rate()).ceil() as u64
+    }
+}
+
+impl FromStr for Quota {
+    type Err = String;
+
+    fn from_str(quota: &str) -> Result<Self, Self::Err> {
+        let err = || format!("{:?} is not a quota like 60/min", quota);
+        let (burst, period) = quota.trim().split_once('/').ok_or_else(err)?;
+        let burst = burst.trim().parse::<u32>().map_err(|_| err())?;
+        let secs = match period.trim() {
+            "s" | "sec" | "second" => 1,
+            "m" | "min" | "minute" => 60,
+            "h" | "hour" => 3600,
+            "d" | "day" => 86400,
+            _ => return Err(err()),
+        };
+        if burst == 0 {
+            return Err(format!("{:?} allows no requests at all", quota));
+        }
+        Ok(Quota {
+            burst,
+            period: Duration::from_secs(secs),
+        })
+    }
+}
+
+impl fmt::Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}s", self.burst, self.period.as_secs())
+    }
+}
+
+/// A quota for some of the routes, written as `[METHOD] /path=<quota>`,
+/// like `POST /users/batch=10/min`. The path matches itself and
+/// everything below it, `/user` covers `/user/5` but not `/users`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub method: Option<Method>,
+    pub path: String,
+    pub quota: Quota,
+}
+
+impl Rule {
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        if self.method.as_ref().is_some_and(|wanted| wanted != method) {
+            return false;
+        }
+        let mut parts = path.split('/').filter(|part| !part.is_empty());
+        self.path
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .all(|wanted| parts.next() == Some(wanted))
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let (route, quota) = rule
+            .rsplit_once('=')
+            .ok_or_else(|| format!("{:?} is not a rule like POST /users=10/min", rule))?;
+        let quota = quota.parse()?;
+        let route = route.trim();
+        let (method, path) = match route.split_once(' ') {
+            Some((method, path)) => {
+                let method = method
+                    .parse::<Method>()
+                    .map_err(|_| format!("{:?} is not a method", method))?;
+                (Some(method), path.trim())
+            }
+            None => (None, route),
+        };
+        if !path.starts_with('/') {
+            return Err(format!("{:?} is not a path", path));
+        }
+        Ok(Rule {
+            method,
+            path: path.to_owned(),
+            quota,
+        })
+    }
+}
+
+/// What taking a token left behind.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Outcome {
+    /// Whether there was a token to take.
+    pub allowed: bool,
+    /// Tokens left in the bucket, partly refilled ones included.
+    pub tokens: f64,
+}
+
+/// The bucket backend couldn't be reached.
+#[derive(Debug)]
+pub struct BucketError(pub String);
+
+impl fmt::Display for BucketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rate limit buckets: {}", self.0)
+    }
+}
+
+impl Error for BucketError {}
+
+pub type TakeFuture = Pin<Box<dyn Future<Output = Result<Outcome, BucketError>> + Send>>;
+pub type PingFuture = Pin<Box<dyn Future<Output = Result<(), BucketError>> + Send>>;
+
+/// Where buckets are kept.
+pub trait Buckets: Send + Sync + 'static {
+    /// Refills the bucket under `key` for the time passed, then takes a
+    /// token if there is one. Unknown buckets start out full.
+    fn take(&self, key: &str, quota: Quota) -> TakeFuture;
+
+    /// Puts back a token `take` took, never filling the bucket past
+    /// `burst`.
+    fn refund(&self, key: &str, quota: Quota) -> TakeFuture;
+
+    /// Checks the backend can be reached, for readiness probes.
+    fn ping(&self) -> PingFuture {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+type KeyFn = dyn Fn(&Request<Body>) -> String + Send + Sync;
+type ChargeFn = dyn Fn(&Response<Body>) -> bool + Send + Sync;
+
+/// The rate limiting middleware, see the module docs.
+pub struct RateLimit {
+    buckets: Arc<dyn Buckets>,
+    default: Option<Quota>,
+    rules: Vec<Rule>,
+    key: Arc<KeyFn>,
+    /// Kept apart from other limiters on the same buckets by this.
+    stage: Option<String>,
+    charge: Option<Arc<ChargeFn>>,
+}
+
+impl RateLimit {
+    /// Limits nothing until quotas are added.
+    pub fn new<B: Buckets>(buckets: B) -> Self {
+        RateLimit {
+            buckets: Arc::new(buckets),
+            default: None,
+            rules: Vec::new(),
+            key: Arc::new(client_ip),
+            stage: None,
+            charge: None,
+        }
+    }
+
+    /// Another limiter on the same buckets with the same quotas, for a
+    /// different place in the stack. Its buckets are kept apart by `name`.
+    /// Like a new one it tells clients apart by IP and charges everything.
+    pub fn stage(&self, name: &str) -> Self {
+        RateLimit {
+            buckets: self.buckets.clone(),
+            default: self.default,
+            rules: self.rules.clone(),
+            key: Arc::new(client_ip),
+            stage: Some(name.to_owned()),
+            charge: None,
+        }
+    }
+
+    /// Sets the quota for routes no rule covers. Without one, they
+    /// aren't limited.
+    pub fn default_quota(mut self, quota: Quota) -> Self {
+        self.default = Some(quota);
+        self
+    }
+
+    /// Adds a rule, rules are tried in the order they were added.
+    pub fn route(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Sets how clients are told apart.
+    pub fn key_by<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&Request<Body>) -> String + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    /// Only charges for responses `charge` picks. Every request takes a
+    /// token up front, so concurrent ones can't slip past an empty bucket,
+    /// and gets it back if its response isn't picked. This limits things
+    /// like failed logins without slowing down the rest.
+    pub fn charge_only<F>(mut self, charge: F) -> Self
+    where
+        F: Fn(&Response<Body>) -> bool + Send + Sync + 'static,
+    {
+        self.charge = Some(Arc::new(charge));
+        self
+    }
+
+    /// Checks the buckets can be reached, see `Health::check`.
+    pub fn ping(&self) -> impl Fn() -> PingFuture + Send + Sync + 'static {
+        let buckets = self.buckets.clone();
+        move || buckets.ping()
+    }
+
+    /// The bucket name and quota that apply to a request, if any.
+    fn quota_for(&self, req: &Request<Body>) -> Option<(String, Quota)> {
+        let path = req.uri().path();
+        self.rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.matches(req.method(), path))
+            .map(|(index, rule)| (format!("rule{}", index), rule.quota))
+            .or_else(|| self.default.map(|quota| ("default".to_owned(), quota)))
+    }
+}
+
+/// Tells clients apart by IP address, the default key.
+pub fn client_ip(req: &Request<Body>) -> String {
+    match req.extensions().get::<ClientAddr>() {
+        Some(ClientAddr(addr)) => format!("ip:{}", addr.ip()),
+        None => "ip:unknown".to_owned(),
+    }
+}
+
+impl Middleware for RateLimit {
+    fn handle(&self, req: Request<Body>, next: Next) -> BoxFuture {
+        let (bucket, quota) = match self.quota_for(&req) {
+            Some(found) => found,
+            None => return next.run(req),
+        };
+        let key = match &self.stage {
+            Some(stage) => format!("{}:{}:{}", stage, bucket, (self.key)(&req)),
+            None => format!("{}:{}", bucket, (self.key)(&req)),
+        };
+        let buckets = self.buckets.clone();
+        let charge = self.charge.clone();
+        let take = buckets.take(&key, quota);
+
+        Box::pin(async move {
+            let outcome = match take.await {
+                Ok(outcome) => outcome,
+                Err(err) => {
+                    // Better to let everyone in than to lock everyone out
+                    warn!("Not rate limiting {}: {}", key, err);
+                    return next.run(req).await;
+                }
+            };
+            if !outcome.allowed {
+                return too_many_requests(quota, outcome);
+            }
+            let mut response = next.run(req).await;
+            match charge {
+                Some(charge) if !charge(&response) => {
+                    if let Err(err) = buckets.refund(&key, quota).await {
+                        warn!("Can't give back a token of {}: {}", key, err);
+                    }
+                    // Uncharged responses leave the headers to other limiters
+                }
+                _ => add_headers(response.headers_mut(), quota, outcome),
+            }
+            response
+        })
+    }
+}
+
+/// The answer when a bucket is empty.
+fn too_many_requests(quota: Quota, outcome: Outcome) -> Response<Body> {
+    let mut response = Response::new(Body::from("Too Many Requests"));
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    add_headers(response.headers_mut(), quota, outcome);
+    let retry_after = quota.secs_until(outcome.tokens, 1.0).max(1);
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
+
+/// Adds the `RateLimit-*` headers describing a bucket.
+fn add_headers(headers: &mut HeaderMap, quota: Quota, outcome: Outcome) {
+    let remaining = outcome.tokens.max(0.0).floor() as u64;
+    let reset = quota.secs_until(outcome.tokens, f64::from(quota.burst));
+    let policy = format!("{};w={}", quota.burst, quota.period.as_secs());
+    headers.insert("ratelimit-limit", HeaderValue::from(quota.burst));
+    headers.insert("ratelimit-remaining", HeaderValue::from(remaining));
+    headers.insert("ratelimit-reset", HeaderValue::from(reset));
+    // Numbers and a semicolon are always a valid header value
+    headers.insert("ratelimit-policy", HeaderValue::from_str(&policy).unwrap());
+}
@@ -0,0 +1,102 @@
+use super::{BucketError, Buckets, Outcome, PingFuture, Quota, TakeFuture};
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{Client, Script};
+use std::time::Duration;
+
+const PREFIX: &str = "ratelimit:";
+
+// Refills and takes `cost` tokens in one go, so instances racing on a
+// bucket can't both spend its last token. A negative cost gives tokens
+// back. Redis' clock is used so instances don't need to agree on the
+// time. Lua truncates numbers it returns, hence tostring.
+const TAKE: &str = r"
+local burst = tonumber(ARGV[1])
+local per_ms = tonumber(ARGV[2])
+local period_ms = tonumber(ARGV[3])
+local cost = tonumber(ARGV[4])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local state = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(state[1]) or burst
+local updated = tonumber(state[2]) or now
+tokens = math.min(burst, tokens + math.max(0, now - updated) * per_ms)
+local allowed = 0
+if cost < 0 or tokens >= 1 then
+    tokens = math.min(burst, tokens - cost)
+    allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', now)
+redis.call('PEXPIRE', KEYS[1], period_ms)
+return {allowed, tostring(tokens)}
+";
+
+/// Keeps buckets in redis, under `ratelimit:<bucket>`, so every instance
+/// pointed at the same redis enforces one limit together. Buckets expire
+/// once they would be full again.
+pub struct RedisBuckets {
+    connection: ConnectionManager,
+    script: Script,
+}
+
+impl RedisBuckets {
+    pub async fn connect(url: &str) -> Result<Self, BucketError> {
+        let client = Client::open(url).map_err(|err| BucketError(err.to_string()))?;
+        // Give up quickly, requests are let through while redis is away
+        let config = ConnectionManagerConfig::new()
+            .set_number_of_retries(2)
+            .set_max_delay(1000)
+            .set_connection_timeout(Duration::from_secs(2))
+            .set_response_timeout(Duration::from_secs(1));
+        let connection = ConnectionManager::new_with_config(client, config)
+            .await
+            .map_err(|err| BucketError(err.to_string()))?;
+        Ok(RedisBuckets {
+            connection,
+            script: Script::new(TAKE),
+        })
+    }
+}
+
+impl RedisBuckets {
+    fn spend(&self, key: &str, quota: Quota, cost: i32) -> TakeFuture {
+        let mut connection = self.connection.clone();
+        let script = self.script.clone();
+        let key = format!("{}{}", PREFIX, key);
+        Box::pin(async move {
+            let (allowed, tokens): (i64, String) = script
+                .key(key)
+                .arg(quota.burst)
+                .arg(quota.rate() / 1000.0)
+                .arg(quota.period.as_millis() as u64)
+                .arg(cost)
+                .invoke_async(&mut connection)
+                .await
+                .map_err(|err| BucketError(err.to_string()))?;
+            let tokens = tokens
+                .parse()
+                .map_err(|_| BucketError(format!("bad token count {:?}", tokens)))?;
+            Ok(Outcome {
+                allowed: allowed == 1,
+                tokens,
+            })
+        })
+    }
+}
+
+impl Buckets for RedisBuckets {
+    fn take(&self, key: &str, quota: Quota) -> TakeFuture {
+        self.spend(key, quota, 1)
+    }
+
+    fn refund(&self, key: &str, quota: Quota) -> TakeFuture {
+        self.spend(key, quota, -1)
+    }
+
+    fn ping(&self) -> PingFuture {
+        let mut connection = self.connection.clone();
+        Box::pin(async move {
+            let pong: Result<String, _> = redis::cmd("PING").query_async(&mut connection).await;
+            pong.map(drop).map_err(|err| BucketError(err.to_string()))
+        })
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{BoxFuture, Middleware, Next};
+use hyper::header::HeaderValue;
+use hyper::{Body, Request};
+use std::fmt;
+
+/// The header request ids travel in, both ways.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request id taken from a client, longer ones are replaced.
+const MAX_LEN: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// The id of the request being handled, put into the request extensions
+/// by `RequestIds`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Makes up a fresh random id.
+    pub fn generate() -> Self {
+        RequestId(format!("{:032x}", rand::random::<u128>()))
+    }
+
+    /// The id of the request the calling task is working on, so loggers
+    /// can put it on every line. `None` outside of `RequestIds`, and in
+    /// tasks spawned by a handler.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|id| id.clone()).ok()
+    }
+
+    /// Takes the id a client sent, if it is short and printable enough to
+    /// end up in logs and headers.
+    fn from_client(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?.trim();
+        let usable = !value.is_empty()
+            && value.len() <= MAX_LEN
+            && value.chars().all(|c| c.is_ascii_graphic());
+        if usable {
+            Some(RequestId(value.to_owned()))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Gives every request an id, keeping the one the client sent in
+/// `X-Request-Id` if there is one, and echoes it in the response. The
+/// rest of the stack runs with it as `RequestId::current`.
+pub struct RequestIds;
+
+impl Middleware for RequestIds {
+    fn handle(&self, mut req: Request<Body>, next: Next) -> BoxFuture {
+        let id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(RequestId::from_client)
+            .unwrap_or_else(RequestId::generate);
+        // Only graphic ASCII gets this far, so it's a valid header value
+        let value = HeaderValue::from_str(&id.0).unwrap();
+        req.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+        req.extensions_mut().insert(id.clone());
+
+        let response = CURRENT.sync_scope(id.clone(), || next.run(req));
+        let response = CURRENT.scope(id, response);
+        Box::pin(async move {
+            let mut response = response.await;
+            response.headers_mut().insert(REQUEST_ID_HEADER, value);
+            response
+        })
+    }
+}
@@ -0,0 +1,23 @@
+use crate::{BoxFuture, Middleware, Next};
+use hyper::header::HeaderValue;
+use hyper::{Body, Request};
+use std::time::Instant;
+
+/// Tells clients how long the rest of the stack took, in milliseconds,
+/// with a `Server-Timing: app;dur=<ms>` header.
+pub struct Timing;
+
+impl Middleware for Timing {
+    fn handle(&self, req: Request<Body>, next: Next) -> BoxFuture {
+        let start = Instant::now();
+        let response = next.run(req);
+        Box::pin(async move {
+            let mut response = response.await;
+            let millis = start.elapsed().as_secs_f64() * 1000.0;
+            // A number is always a valid header value
+            let value = HeaderValue::from_str(&format!("app;dur={:.3}", millis)).unwrap();
+            response.headers_mut().append("server-timing", value);
+            response
+        })
+    }
+}
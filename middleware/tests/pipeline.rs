@@ -0,0 +1,382 @@
+//! Runs requests through stacks directly, no server needed.
+
+use hyper::header::HeaderValue;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use middleware::{
+    AccessLog, CatchPanic, ClientAddr, Cors, Health, MatchedRoute, Metrics, Next, Pipeline,
+    RequestId, RequestIds, Timing,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+async fn hello(_req: Request<Body>) -> Response<Body> {
+    Response::new(Body::from("hello"))
+}
+
+/// Answers with the request id the handler got.
+async fn echo_id(req: Request<Body>) -> Response<Body> {
+    let id = req.extensions().get::<RequestId>().unwrap().0.clone();
+    Response::new(Body::from(id))
+}
+
+fn get(path: &str) -> Request<Body> {
+    Request::get(path).body(Body::empty()).unwrap()
+}
+
+async fn body(response: Response<Body>) -> String {
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn middlewares_run_in_the_order_they_were_added() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let tracer = |name: &'static str| {
+        let seen = seen.clone();
+        move |req: Request<Body>, next: Next| {
+            seen.lock().unwrap().push(format!("{} in", name));
+            let seen = seen.clone();
+            async move {
+                let response = next.run(req).await;
+                seen.lock().unwrap().push(format!("{} out", name));
+                response
+            }
+        }
+    };
+    let stack = Pipeline::new()
+        .with(tracer("outer"))
+        .with(tracer("inner"))
+        .handler(hello);
+
+    let response = stack.handle(get("/")).await;
+    assert_eq!(body(response).await, "hello");
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec!["outer in", "inner in", "inner out", "outer out"]
+    );
+}
+
+#[tokio::test]
+async fn middlewares_can_answer_on_their_own() {
+    let stack = Pipeline::new()
+        .with(|_req: Request<Body>, _next: Next| async {
+            Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::empty())
+                .unwrap()
+        })
+        .handler(|_req: Request<Body>| async { panic!("never reached") });
+    let response = stack.handle(get("/")).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn request_ids_are_generated_and_echoed() {
+    let stack = Pipeline::new().with(RequestIds).handler(echo_id);
+
+    let response = stack.handle(get("/")).await;
+    let header = response.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    assert_eq!(header.len(), 32);
+    assert_eq!(body(response).await, header);
+
+    // Every request gets its own
+    let other = stack.handle(get("/")).await;
+    assert_ne!(other.headers()["x-request-id"], header.as_str());
+}
+
+#[tokio::test]
+async fn request_ids_from_clients_are_kept_if_sane() {
+    let stack = Pipeline::new().with(RequestIds).handler(echo_id);
+
+    let req = Request::get("/")
+        .header("x-request-id", "abc-123")
+        .body(Body::empty())
+        .unwrap();
+    let response = stack.handle(req).await;
+    assert_eq!(response.headers()["x-request-id"], "abc-123");
+    assert_eq!(body(response).await, "abc-123");
+
+    let long = "a".repeat(500);
+    for bad in &["", "has space", long.as_str()] {
+        let req = Request::get("/")
+            .header("x-request-id", HeaderValue::from_str(bad).unwrap())
+            .body(Body::empty())
+            .unwrap();
+        let response = stack.handle(req).await;
+        assert_ne!(response.headers()["x-request-id"], *bad);
+    }
+}
+
+#[tokio::test]
+async fn request_ids_are_current_while_handling() {
+    let stack = Pipeline::new()
+        .with(RequestIds)
+        .handler(|_req: Request<Body>| async {
+            tokio::task::yield_now().await;
+            let id = RequestId::current().map_or_else(|| "none".to_owned(), |id| id.0);
+            Response::new(Body::from(id))
+        });
+
+    let req = Request::get("/")
+        .header("x-request-id", "abc-123")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(body(stack.handle(req).await).await, "abc-123");
+    assert_eq!(RequestId::current(), None);
+}
+
+#[tokio::test]
+async fn timing_adds_server_timing() {
+    let stack = Pipeline::new().with(Timing).handler(hello);
+    let response = stack.handle(get("/")).await;
+    let timing = response.headers()["server-timing"].to_str().unwrap();
+    assert!(timing.starts_with("app;dur="), "{}", timing);
+}
+
+#[tokio::test]
+async fn access_log_passes_responses_through() {
+    let stack = Pipeline::new()
+        .with(RequestIds)
+        .with(AccessLog)
+        .handler(hello);
+    let response = stack
+        .serve(([127, 0, 0, 1], 4000).into(), get("/"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body(response).await, "hello");
+}
+
+#[tokio::test]
+async fn serve_records_the_client_address() {
+    let stack = Pipeline::new().handler(|req: Request<Body>| async move {
+        let client = req.extensions().get::<ClientAddr>().unwrap().0;
+        Response::new(Body::from(client.to_string()))
+    });
+    let response = stack
+        .serve(([10, 0, 0, 1], 4000).into(), get("/"))
+        .await
+        .unwrap();
+    assert_eq!(body(response).await, "10.0.0.1:4000");
+}
+
+#[tokio::test]
+async fn panics_become_internal_errors() {
+    let stack = Pipeline::new()
+        .with(CatchPanic)
+        .handler(|req: Request<Body>| async move {
+            if req.uri().path() == "/boom" {
+                panic!("boom");
+            }
+            Response::new(Body::from("fine"))
+        });
+
+    let response = stack.handle(get("/boom")).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    // The stack keeps working afterwards
+    let response = stack.handle(get("/")).await;
+    assert_eq!(body(response).await, "fine");
+
+    // Panics while building the response future are caught too
+    let stack = Pipeline::new()
+        .with(CatchPanic)
+        .with(
+            |_req: Request<Body>, _next: Next| -> std::future::Ready<Response<Body>> {
+                panic!("eager boom")
+            },
+        )
+        .handler(hello);
+    let response = stack.handle(get("/")).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn internal_errors_keep_the_request_id() {
+    let stack = Pipeline::new()
+        .with(RequestIds)
+        .with(CatchPanic)
+        .handler(|_req: Request<Body>| async { panic!("boom") });
+
+    let req = Request::get("/")
+        .header("x-request-id", "abc-123")
+        .body(Body::empty())
+        .unwrap();
+    let response = stack.handle(req).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.headers()["x-request-id"], "abc-123");
+}
+
+fn cross_origin(method: Method, origin: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri("/")
+        .header("origin", origin)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn cors_answers_preflights() {
+    let stack = Pipeline::new()
+        .with(Cors::origins(vec!["https://app.example.com"]))
+        .handler(hello);
+
+    let mut req = cross_origin(Method::OPTIONS, "https://app.example.com");
+    req.headers_mut().insert(
+        "access-control-request-method",
+        HeaderValue::from_static("PUT"),
+    );
+    let response = stack.handle(req).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let headers = response.headers();
+    assert_eq!(
+        headers["access-control-allow-origin"],
+        "https://app.example.com"
+    );
+    assert!(headers["access-control-allow-methods"]
+        .to_str()
+        .unwrap()
+        .contains("PUT"));
+    assert_eq!(headers["access-control-max-age"], "600");
+    assert_eq!(headers["vary"], "Origin");
+}
+
+#[tokio::test]
+async fn cors_tags_allowed_origins_only() {
+    let stack = Pipeline::new()
+        .with(Cors::origins(vec!["https://app.example.com"]))
+        .handler(hello);
+
+    let response = stack
+        .handle(cross_origin(Method::GET, "https://app.example.com"))
+        .await;
+    assert_eq!(
+        response.headers()["access-control-allow-origin"],
+        "https://app.example.com"
+    );
+    let expose = response.headers()["access-control-expose-headers"]
+        .to_str()
+        .unwrap();
+    assert!(expose.contains("ratelimit-remaining"), "{}", expose);
+    assert!(expose.contains("retry-after"), "{}", expose);
+    assert_eq!(body(response).await, "hello");
+
+    let response = stack
+        .handle(cross_origin(Method::GET, "https://evil.example.com"))
+        .await;
+    assert!(!response
+        .headers()
+        .contains_key("access-control-allow-origin"));
+    assert_eq!(response.headers()["vary"], "Origin");
+
+    let response = stack.handle(get("/")).await;
+    assert!(!response
+        .headers()
+        .contains_key("access-control-allow-origin"));
+    assert_eq!(response.headers()["vary"], "Origin");
+
+    let stack = Pipeline::new().with(Cors::any()).handler(hello);
+    let response = stack
+        .handle(cross_origin(Method::GET, "https://anywhere.example.com"))
+        .await;
+    assert_eq!(response.headers()["access-control-allow-origin"], "*");
+}
+
+#[tokio::test]
+async fn metrics_count_requests_by_route() {
+    let registry = middleware::prometheus::Registry::new();
+    let stack = Pipeline::new()
+        .with(Metrics::new(registry).unwrap())
+        .handler(|req: Request<Body>| async move {
+            let mut response = Response::new(Body::from("hello"));
+            if req.uri().path().starts_with("/user/") {
+                let route = MatchedRoute("/user/{id}".into());
+                response.extensions_mut().insert(route);
+            } else {
+                *response.status_mut() = StatusCode::NOT_FOUND;
+            }
+            response
+        });
+
+    for path in &["/user/1", "/user/2", "/nope"] {
+        stack.handle(get(path)).await;
+    }
+    let response = stack.handle(get("/metrics")).await;
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let text = body(response).await;
+    assert!(text.contains(r#"http_requests_total{method="GET",route="/user/{id}",status="200"} 2"#));
+    assert!(text.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#));
+    assert!(text.contains(
+        r#"http_request_duration_seconds_count{method="GET",route="/user/{id}",status="200"} 2"#
+    ));
+    assert!(text.contains("http_requests_in_flight 0"));
+}
+
+#[tokio::test]
+async fn readiness_runs_every_check() {
+    let store_up = Arc::new(AtomicBool::new(true));
+    let up = store_up.clone();
+    let stack = Pipeline::new()
+        .with(
+            Health::new()
+                .timeout(Duration::from_millis(50))
+                .check("store", move || {
+                    let up = up.load(Ordering::SeqCst);
+                    async move {
+                        if up {
+                            Ok(())
+                        } else {
+                            Err("disk is gone")
+                        }
+                    }
+                })
+                .check("cache", || async {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    Ok::<_, String>(())
+                }),
+        )
+        .handler(hello);
+
+    let response = stack.handle(get("/healthz")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = stack.handle(get("/readyz")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body(response).await, "[+]store ok\n[+]cache ok\nready\n");
+
+    store_up.store(false, Ordering::SeqCst);
+    let response = stack.handle(get("/readyz")).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let text = body(response).await;
+    assert!(text.contains("[-]store failed: disk is gone\n"), "{}", text);
+    assert!(text.ends_with("not ready\n"));
+    // Still alive though, and everything else goes through
+    let response = stack.handle(get("/healthz")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body(stack.handle(get("/")).await).await, "hello");
+}
+
+#[tokio::test]
+async fn slow_checks_time_out() {
+    let stack = Pipeline::new()
+        .with(
+            Health::new()
+                .timeout(Duration::from_millis(20))
+                .check("redis", || async {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    Ok::<_, String>(())
+                }),
+        )
+        .handler(hello);
+    let response = stack.handle(get("/readyz")).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body(response)
+        .await
+        .contains("[-]redis timed out after 20ms"));
+}
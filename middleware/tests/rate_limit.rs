@@ -0,0 +1,201 @@
+use hyper::{Body, Method, Request, Response, StatusCode};
+use middleware::rate_limit::MemoryBuckets;
+use middleware::{Pipeline, Quota, RateLimit, Rule, Stack};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+async fn hello(_req: Request<Body>) -> Response<Body> {
+    Response::new(Body::from("hello"))
+}
+
+fn client(last: u8) -> SocketAddr {
+    ([10, 0, 0, last], 4000).into()
+}
+
+async fn send(stack: &Stack, from: SocketAddr, method: Method, path: &str) -> Response<Body> {
+    let req = Request::builder()
+        .method(method)
+        .uri(path)
+        .body(Body::empty())
+        .unwrap();
+    stack.serve(from, req).await.unwrap()
+}
+
+fn header<'a>(response: &'a Response<Body>, name: &str) -> Option<&'a str> {
+    response
+        .headers()
+        .get(name)
+        .map(|value| value.to_str().unwrap())
+}
+
+#[test]
+fn quotas_and_rules_parse() {
+    assert_eq!("60/min".parse(), Ok(Quota::per_minute(60)));
+    assert_eq!("5/s".parse(), Ok(Quota::per_second(5)));
+    assert_eq!(" 100 / hour ".parse(), Ok(Quota::per_hour(100)));
+    for bad in &["60", "0/min", "ten/min", "10/fortnight"] {
+        assert!(bad.parse::<Quota>().is_err(), "{}", bad);
+    }
+
+    let rule: Rule = "POST /users/batch=10/min".parse().unwrap();
+    assert_eq!(rule.method, Some(Method::POST));
+    assert_eq!(rule.path, "/users/batch");
+    assert_eq!(rule.quota, Quota::per_minute(10));
+    let rule: Rule = "/user=1/s".parse().unwrap();
+    assert_eq!(rule.method, None);
+    assert!("POST users=1/s".parse::<Rule>().is_err());
+}
+
+#[tokio::test]
+async fn empty_buckets_get_429() {
+    let limit = RateLimit::new(MemoryBuckets::new()).default_quota(Quota::per_minute(2));
+    let stack = Pipeline::new().with(limit).handler(hello);
+
+    let response = send(&stack, client(1), Method::GET, "/").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "ratelimit-limit"), Some("2"));
+    assert_eq!(header(&response, "ratelimit-remaining"), Some("1"));
+    assert_eq!(header(&response, "ratelimit-policy"), Some("2;w=60"));
+    assert_eq!(header(&response, "ratelimit-reset"), Some("30"));
+
+    let response = send(&stack, client(1), Method::GET, "/").await;
+    assert_eq!(header(&response, "ratelimit-remaining"), Some("0"));
+
+    let response = send(&stack, client(1), Method::GET, "/").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(header(&response, "retry-after"), Some("30"));
+    assert_eq!(header(&response, "ratelimit-remaining"), Some("0"));
+
+    // Other clients have buckets of their own
+    let response = send(&stack, client(2), Method::GET, "/").await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn buckets_refill_over_time() {
+    let limit = RateLimit::new(MemoryBuckets::new()).default_quota(Quota::per_second(10));
+    let stack = Pipeline::new().with(limit).handler(hello);
+
+    for _ in 0..10 {
+        send(&stack, client(1), Method::GET, "/").await;
+    }
+    let response = send(&stack, client(1), Method::GET, "/").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(header(&response, "retry-after"), Some("1"));
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    let response = send(&stack, client(1), Method::GET, "/").await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn rules_pick_the_quota() {
+    let limit = RateLimit::new(MemoryBuckets::new())
+        .route("POST /users/batch=1/min".parse().unwrap())
+        .route("/user=2/min".parse().unwrap());
+    let stack = Pipeline::new().with(limit).handler(hello);
+
+    let response = send(&stack, client(1), Method::POST, "/users/batch").await;
+    assert_eq!(header(&response, "ratelimit-limit"), Some("1"));
+    let response = send(&stack, client(1), Method::POST, "/users/batch").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Rules have their own buckets
+    let response = send(&stack, client(1), Method::GET, "/user/5").await;
+    assert_eq!(header(&response, "ratelimit-limit"), Some("2"));
+
+    // No rule and no default quota, no limit
+    for path in &["/users/batch", "/users", "/"] {
+        let response = send(&stack, client(1), Method::GET, path).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(header(&response, "ratelimit-limit").is_none(), "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn keys_can_come_from_the_request() {
+    let limit = RateLimit::new(MemoryBuckets::new())
+        .default_quota(Quota::per_minute(1))
+        .key_by(|req| match req.headers().get("x-user") {
+            Some(user) => format!("user:{}", user.to_str().unwrap()),
+            None => middleware::rate_limit::client_ip(req),
+        });
+    let stack = Pipeline::new().with(limit).handler(hello);
+
+    let as_user = |user: &str| {
+        Request::get("/")
+            .header("x-user", user)
+            .body(Body::empty())
+            .unwrap()
+    };
+    // The same user is limited from anywhere
+    let response = stack.serve(client(1), as_user("alice")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = stack.serve(client(2), as_user("alice")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let response = stack.serve(client(2), as_user("bob")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn stages_can_charge_only_some_responses() {
+    let limit = RateLimit::new(MemoryBuckets::new()).default_quota(Quota::per_minute(2));
+    let failures = limit
+        .stage("failures")
+        .charge_only(|response| response.status() == StatusCode::UNAUTHORIZED);
+    let stack =
+        Pipeline::new()
+            .with(failures)
+            .with(limit)
+            .handler(|req: Request<Body>| async move {
+                let status = match req.uri().path() {
+                    "/denied" => StatusCode::UNAUTHORIZED,
+                    _ => StatusCode::OK,
+                };
+                let mut response = Response::new(Body::empty());
+                *response.status_mut() = status;
+                response
+            });
+
+    // Fine requests only count against the inner limiter
+    let response = send(&stack, client(1), Method::GET, "/").await;
+    assert_eq!(header(&response, "ratelimit-remaining"), Some("1"));
+
+    let client = client(2);
+    for remaining in &["1", "0"] {
+        let response = send(&stack, client, Method::GET, "/denied").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(header(&response, "ratelimit-remaining"), Some(*remaining));
+    }
+    // Once failures run out nothing gets through, not even fine requests
+    for path in &["/denied", "/"] {
+        let response = send(&stack, client, Method::GET, path).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
+
+#[tokio::test]
+async fn concurrent_requests_cant_slip_past_a_charge_only_limit() {
+    let limit = RateLimit::new(MemoryBuckets::new())
+        .default_quota(Quota::per_minute(2))
+        .charge_only(|response| response.status() == StatusCode::UNAUTHORIZED);
+    let stack = Pipeline::new()
+        .with(limit)
+        .handler(|_req: Request<Body>| async {
+            // All of them are in here before the first one is charged
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::UNAUTHORIZED;
+            response
+        });
+
+    let requests = (0..5).map(|_| send(&stack, client(1), Method::GET, "/"));
+    let statuses = futures::future::join_all(requests)
+        .await
+        .iter()
+        .map(Response::status)
+        .collect::<Vec<_>>();
+    let denied = |status| statuses.iter().filter(|s| **s == status).count();
+    assert_eq!(denied(StatusCode::UNAUTHORIZED), 2);
+    assert_eq!(denied(StatusCode::TOO_MANY_REQUESTS), 3);
+}
@@ -0,0 +1,24 @@
+//! Shared parts of the `users` tool.
+
+use postgres::NoTls;
+use r2d2_postgres::PostgresConnectionManager;
+use std::time::Duration;
+
+/// Connections to the users database.
+pub type Pool = r2d2::Pool<PostgresConnectionManager<NoTls>>;
+
+/// Check the database answers a trivial query. Getting a connection
+/// from `pool` and running the query may take `timeout` each, the
+/// query's timeout is enforced by the server.
+pub fn health_check(pool: &Pool, timeout: Duration) -> Result<(), failure::Error> {
+    let mut conn = pool.get_timeout(timeout)?;
+    let mut tx = conn.transaction()?;
+    // SET LOCAL only lasts until the end of this transaction
+    tx.batch_execute(&format!(
+        "SET LOCAL statement_timeout = {}",
+        // 0 would mean no timeout at all
+        timeout.as_millis().max(1)
+    ))?;
+    tx.query_one("SELECT 1", &[])?;
+    Ok(())
+}
@@ -0,0 +1,589 @@
+//! Every setting of the server and where it came from.
+//!
+//! Settings are merged from layers, each overriding the ones before it:
+//!
+//! 1. the defaults in `KEYS`
+//! 2. the config file, `microservice.toml` unless `--config` names another
+//! 3. environment variables, `RAND_VALUE_` and the key in capitals with
+//!    `_` for `.`, like `RAND_VALUE_LOG_FORMAT` for `log.format`
+//! 4. the flags of `run`, see `Key::flag`
+//!
+//! `RUST_LOG` still works for `log.level`, below `RAND_VALUE_LOG_LEVEL`,
+//! and so does `ADDRESS` for `address` with a warning, it's deprecated.
+//! Other `RAND_VALUE_` variables that aren't settings are warned about
+//! and ignored, see `Layers::warnings`.
+//! Durations are written like `30s`, `500ms` or `2m`. Lists are TOML
+//! arrays in the file and comma separated everywhere else. An empty
+//! value unsets a setting. A whole file looks like this:
+//!
+//! ```toml
+//! address = "0.0.0.0:9876"
+//! worker_threads = 4
+//! request_timeout = "30s"
+//! header_timeout = "10s"
+//! secret_file = "secret"
+//!
+//! [log]
+//! level = "info,access=warn"
+//! format = "json"
+//!
+//! [rng]
+//! source = "os"
+//! seed = 42
+//! max_string_length = 1024
+//! max_bytes = 4096
+//!
+//! [rate_limit]
+//! quota = "100/min"
+//! routes = ["GET /bytes=10/s"]
+//! redis = "redis://127.0.0.1/"
+//! ```
+
+use crate::logging::LogFormat;
+use crate::values::{Limits, RngSource};
+use crate::Live;
+use middleware::{Quota, Rule};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Write};
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Where the config file is looked for without `--config`.
+pub const DEFAULT_PATH: &str = "microservice.toml";
+/// What every environment variable for a setting starts with.
+pub const ENV_PREFIX: &str = "RAND_VALUE_";
+
+/// What a setting holds, so it can be written back as TOML.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Type {
+    Text,
+    Number,
+    List,
+}
+
+/// A setting the server knows.
+#[derive(Debug)]
+pub struct Key {
+    /// The name in the file, `table.name` for the ones in a table.
+    pub name: &'static str,
+    pub kind: Type,
+    pub default: Option<&'static str>,
+    /// The flag of `run` that sets it, without the dashes.
+    pub flag: &'static str,
+}
+
+impl Key {
+    /// The environment variable that sets it.
+    pub fn env_var(&self) -> String {
+        format!(
+            "{}{}",
+            ENV_PREFIX,
+            self.name.replace('.', "_").to_uppercase()
+        )
+    }
+}
+
+const fn key(
+    name: &'static str,
+    kind: Type,
+    default: Option<&'static str>,
+    flag: &'static str,
+) -> Key {
+    Key {
+        name,
+        kind,
+        default,
+        flag,
+    }
+}
+
+/// Every setting, in the order they are printed.
+pub const KEYS: [Key; 14] = [
+    key("address", Type::Text, Some("127.0.0.1:8080"), "address"),
+    key("worker_threads", Type::Number, None, "worker-threads"),
+    key(
+        "request_timeout",
+        Type::Text,
+        Some("30s"),
+        "request-timeout",
+    ),
+    key("header_timeout", Type::Text, Some("10s"), "header-timeout"),
+    key("secret_file", Type::Text, None, "secret-file"),
+    key("log.level", Type::Text, Some("error"), "log-level"),
+    key("log.format", Type::Text, Some("text"), "log-format"),
+    key("rng.source", Type::Text, Some("thread"), "rng"),
+    key("rng.seed", Type::Number, None, "seed"),
+    key(
+        "rng.max_string_length",
+        Type::Number,
+        Some("1024"),
+        "max-string-length",
+    ),
+    key("rng.max_bytes", Type::Number, Some("4096"), "max-bytes"),
+    key("rate_limit.quota", Type::Text, None, "rate-limit"),
+    key("rate_limit.routes", Type::List, None, "route-limit"),
+    key("rate_limit.redis", Type::Text, None, "rate-limit-redis"),
+];
+
+fn find(name: &str) -> Option<&'static Key> {
+    KEYS.iter().find(|key| key.name == name)
+}
+
+/// Where a value came from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(String),
+    Flag(&'static str),
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => f.write_str("default"),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Flag(flag) => write!(f, "flag --{}", flag),
+        }
+    }
+}
+
+/// Why the settings can't be used.
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// A setting that doesn't exist, most likely a typo.
+    Unknown(String, Source),
+    Invalid {
+        key: &'static str,
+        value: String,
+        source: Source,
+        reason: String,
+    },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "can't read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => write!(f, "{} is not valid: {}", path.display(), err),
+            ConfigError::Unknown(var, Source::Env(_)) => {
+                write!(f, "unknown setting in the environment: {}", var)
+            }
+            ConfigError::Unknown(name, source) => {
+                write!(f, "unknown setting {} in {}", name, source)
+            }
+            ConfigError::Invalid {
+                key,
+                value,
+                source,
+                reason,
+            } => write!(
+                f,
+                "{} = {:?} from {} is invalid: {}",
+                key, value, source, reason
+            ),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Read(_, err) => Some(err),
+            ConfigError::Parse(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// The settings as they are merged, still as text.
+#[derive(Clone, Debug)]
+pub struct Layers {
+    values: HashMap<&'static str, (String, Source)>,
+    warnings: Vec<String>,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        let values = KEYS
+            .iter()
+            .filter_map(|key| Some((key.name, (key.default?.to_owned(), Source::Default))))
+            .collect();
+        Layers {
+            values,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+impl Layers {
+    /// Starts out with the defaults.
+    pub fn new() -> Self {
+        Layers::default()
+    }
+
+    /// Sets `name` unless `value` is empty, which unsets it.
+    pub fn set(&mut self, name: &str, value: &str, source: Source) -> Result<(), ConfigError> {
+        let key =
+            find(name).ok_or_else(|| ConfigError::Unknown(name.to_owned(), source.clone()))?;
+        if value.trim().is_empty() {
+            self.values.remove(key.name);
+        } else {
+            self.values
+                .insert(key.name, (value.trim().to_owned(), source));
+        }
+        Ok(())
+    }
+
+    /// The value of `name` and where it came from, if it is set.
+    pub fn get(&self, name: &str) -> Option<(&str, &Source)> {
+        self.values
+            .get(name)
+            .map(|(value, source)| (value.as_str(), source))
+    }
+
+    /// Merges in the config file at `path`. Returns false if there is no
+    /// such file, which is only an error when `required`.
+    pub fn file<P: AsRef<Path>>(&mut self, path: P, required: bool) -> Result<bool, ConfigError> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => return Ok(false),
+            Err(err) => return Err(ConfigError::Read(path.to_owned(), err)),
+        };
+        let table = toml::from_str::<toml::value::Table>(&text)
+            .map_err(|err| ConfigError::Parse(path.to_owned(), err))?;
+        let source = Source::File(path.to_owned());
+        for (name, value) in table {
+            match value {
+                toml::Value::Table(table) => {
+                    for (inner, value) in table {
+                        let name = format!("{}.{}", name, inner);
+                        self.set(&name, &text_of(&name, value, &source)?, source.clone())?;
+                    }
+                }
+                value => self.set(&name, &text_of(&name, value, &source)?, source.clone())?,
+            }
+        }
+        Ok(true)
+    }
+
+    /// Merges in the variables starting with `ENV_PREFIX`, and the older
+    /// `RUST_LOG` and `ADDRESS`.
+    pub fn env<I>(&mut self, vars: I) -> Result<(), ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut prefixed = Vec::new();
+        for (var, value) in vars {
+            if var == "RUST_LOG" {
+                self.set("log.level", &value, Source::Env(var))?;
+            } else if var == "ADDRESS" {
+                self.warnings.push(format!(
+                    "ADDRESS is deprecated, use {}ADDRESS instead",
+                    ENV_PREFIX
+                ));
+                self.set("address", &value, Source::Env(var))?;
+            } else if var.starts_with(ENV_PREFIX) {
+                prefixed.push((var, value));
+            }
+        }
+        // After the older ones, so the prefixed ones win
+        for (var, value) in prefixed {
+            match KEYS.iter().find(|key| key.env_var() == var) {
+                Some(key) => self.set(key.name, &value, Source::Env(var))?,
+                // Could belong to something else, so it's not worth failing over
+                None => self
+                    .warnings
+                    .push(format!("Ignoring {}, there is no such setting", var)),
+            }
+        }
+        Ok(())
+    }
+
+    /// What was odd about the layers but didn't stop them from merging,
+    /// for whoever can tell the user.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Parses the merged values.
+    pub fn resolve(&self) -> Result<Config, ConfigError> {
+        Ok(Config {
+            address: self.required("address")?,
+            worker_threads: self.parse_with("worker_threads", positive)?,
+            request_timeout: self.parse_with("request_timeout", parse_duration)?,
+            header_timeout: self.parse_with("header_timeout", parse_duration)?,
+            secret_file: self.parse("secret_file")?,
+            log_level: self
+                .parse_with("log.level", check_filter)?
+                .unwrap_or_default(),
+            log_format: self.parse("log.format")?.unwrap_or_default(),
+            rng: self.parse("rng.source")?.unwrap_or_default(),
+            seed: self.parse("rng.seed")?,
+            limits: Limits {
+                max_string_length: self.required("rng.max_string_length")?,
+                max_bytes: self.required("rng.max_bytes")?,
+            },
+            rate_limit: self.parse("rate_limit.quota")?,
+            route_limits: self
+                .parse_with("rate_limit.routes", |routes| {
+                    split_list(routes).map(str::parse).collect()
+                })?
+                .unwrap_or_default(),
+            rate_limit_redis: self.parse("rate_limit.redis")?,
+        })
+    }
+
+    fn parse<T>(&self, name: &'static str) -> Result<Option<T>, ConfigError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.parse_with(name, str::parse)
+    }
+
+    fn parse_with<T, E, F>(&self, name: &'static str, parse: F) -> Result<Option<T>, ConfigError>
+    where
+        F: FnOnce(&str) -> Result<T, E>,
+        E: Display,
+    {
+        match self.values.get(name) {
+            Some((value, source)) => {
+                parse(value)
+                    .map(Some)
+                    .map_err(|reason| ConfigError::Invalid {
+                        key: name,
+                        value: value.clone(),
+                        source: source.clone(),
+                        reason: reason.to_string(),
+                    })
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// For the settings that can't do without a value.
+    fn required<T>(&self, name: &'static str) -> Result<T, ConfigError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.parse(name)?.ok_or_else(|| ConfigError::Invalid {
+            key: name,
+            value: String::new(),
+            source: Source::Default,
+            reason: "it must be set".to_owned(),
+        })
+    }
+
+    /// The merged settings as TOML, with where each one came from.
+    pub fn print(&self) -> String {
+        let mut out = String::new();
+        let mut table = "";
+        for key in KEYS.iter() {
+            let (section, name) = match key.name.find('.') {
+                Some(dot) => (&key.name[..dot], &key.name[dot + 1..]),
+                None => ("", key.name),
+            };
+            if section != table {
+                let _ = writeln!(out, "\n[{}]", section);
+                table = section;
+            }
+            // Writing to a String can't fail
+            let _ = match self.values.get(key.name) {
+                Some((value, source)) => {
+                    writeln!(out, "{} = {} # {}", name, toml_of(key, value), source)
+                }
+                None => writeln!(out, "# {} is not set", name),
+            };
+        }
+        out
+    }
+}
+
+/// The settings the server runs with.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub address: SocketAddr,
+    /// Threads answering requests, one per CPU if not set.
+    pub worker_threads: Option<usize>,
+    pub request_timeout: Option<Duration>,
+    pub header_timeout: Option<Duration>,
+    pub secret_file: Option<PathBuf>,
+    /// A filter like `RUST_LOG` takes.
+    pub log_level: String,
+    pub log_format: LogFormat,
+    pub rng: RngSource,
+    /// Makes the server deterministic, whatever `rng` says.
+    pub seed: Option<u64>,
+    pub limits: Limits,
+    pub rate_limit: Option<Quota>,
+    pub route_limits: Vec<Rule>,
+    pub rate_limit_redis: Option<String>,
+}
+
+/// The keys a running server can take on, see `reload`. The others
+/// need a restart.
+pub const LIVE_KEYS: [&str; 7] = [
+    "request_timeout",
+    "log.level",
+    "log.format",
+    "rng.source",
+    "rng.seed",
+    "rng.max_string_length",
+    "rng.max_bytes",
+];
+
+impl Config {
+    /// The part of the config requests are answered with.
+    pub fn live(&self) -> Live {
+        Live {
+            request_timeout: self.request_timeout,
+            rng: self.rng,
+            seed: self.seed,
+            limits: self.limits,
+        }
+    }
+
+    /// The keys whose values differ in `other`, in the order of `KEYS`.
+    pub fn diff(&self, other: &Config) -> Vec<&'static str> {
+        // Sized by KEYS, so a new key doesn't compile until it's compared here
+        let changed: [(&'static str, bool); KEYS.len()] = [
+            ("address", self.address != other.address),
+            (
+                "worker_threads",
+                self.worker_threads != other.worker_threads,
+            ),
+            (
+                "request_timeout",
+                self.request_timeout != other.request_timeout,
+            ),
+            (
+                "header_timeout",
+                self.header_timeout != other.header_timeout,
+            ),
+            ("secret_file", self.secret_file != other.secret_file),
+            ("log.level", self.log_level != other.log_level),
+            ("log.format", self.log_format != other.log_format),
+            ("rng.source", self.rng != other.rng),
+            ("rng.seed", self.seed != other.seed),
+            (
+                "rng.max_string_length",
+                self.limits.max_string_length != other.limits.max_string_length,
+            ),
+            (
+                "rng.max_bytes",
+                self.limits.max_bytes != other.limits.max_bytes,
+            ),
+            ("rate_limit.quota", self.rate_limit != other.rate_limit),
+            ("rate_limit.routes", self.route_limits != other.route_limits),
+            (
+                "rate_limit.redis",
+                self.rate_limit_redis != other.rate_limit_redis,
+            ),
+        ];
+        changed
+            .iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+/// A TOML value from the file as the text the other layers would give.
+fn text_of(name: &str, value: toml::Value, source: &Source) -> Result<String, ConfigError> {
+    let invalid = |value: &toml::Value, reason: &str| ConfigError::Invalid {
+        key: find(name).map_or("", |key| key.name),
+        value: value.to_string(),
+        source: source.clone(),
+        reason: reason.to_owned(),
+    };
+    match value {
+        toml::Value::String(text) => Ok(text),
+        toml::Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                toml::Value::String(text) if !text.contains(',') => Ok(text.clone()),
+                item => Err(invalid(item, "lists can only hold text without commas")),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|items| items.join(",")),
+        toml::Value::Table(_) => Err(invalid(&value, "tables can't be nested")),
+        value => Ok(value.to_string()),
+    }
+}
+
+/// A merged value written back as TOML.
+fn toml_of(key: &Key, value: &str) -> toml::Value {
+    match key.kind {
+        Type::Number => match value.parse() {
+            Ok(number) => toml::Value::Integer(number),
+            Err(_) => toml::Value::String(value.to_owned()),
+        },
+        Type::List => toml::Value::Array(
+            split_list(value)
+                .map(|item| toml::Value::String(item.to_owned()))
+                .collect(),
+        ),
+        Type::Text => toml::Value::String(value.to_owned()),
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+fn positive(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(0) => Err("must be at least 1".to_owned()),
+        Ok(number) => Ok(number),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+fn check_filter(filter: &str) -> Result<String, String> {
+    env_filter::Builder::new()
+        .try_parse(filter)
+        .map(|_| filter.to_owned())
+        .map_err(|err| err.to_string())
+}
+
+/// Parses `30s`, `500ms`, `2m` or `1h`. A plain number is seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("{:?} doesn't start with a whole number", value))?;
+    let secs = |per: u64| {
+        number
+            .checked_mul(per)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("{:?} is too long", value))
+    };
+    let duration = match unit.trim() {
+        "ms" => Duration::from_millis(number),
+        "" | "s" => Duration::from_secs(number),
+        "m" | "min" => secs(60)?,
+        "h" => secs(3600)?,
+        unit => return Err(format!("unknown unit {:?}, use ms, s, m or h", unit)),
+    };
+    if duration == Duration::from_secs(0) {
+        return Err("must be longer than 0".to_owned());
+    }
+    Ok(duration)
+}
@@ -0,0 +1,253 @@
+use hyper::header::{HeaderName, HeaderValue, ALLOW, CONTENT_TYPE};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, trace, warn};
+use middleware::prometheus::{IntCounterVec, Opts, Registry};
+use middleware::{
+    AccessLog, CatchPanic, Cors, Health, MatchedRoute, Metrics, Next, Pipeline, RateLimit,
+    RequestIds, Timing,
+};
+use rand::prng::ChaChaRng;
+use rand::{Rng, RngCore};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use values::{Kind, Limits, RngSource};
+
+pub mod config;
+pub mod logging;
+pub mod reload;
+pub mod secret;
+pub mod values;
+
+/// Tells which seed `/` used, its body has no room for it.
+pub const SEED_HEADER: &str = "rng-seed";
+
+/// Optional parts of the server, all off by default.
+#[derive(Default)]
+pub struct Options {
+    /// Limits how often each client IP may ask for values.
+    pub rate_limit: Option<RateLimit>,
+    /// Closes connections that take longer to send the request headers.
+    pub header_timeout: Option<Duration>,
+    /// What can still change once the server runs.
+    pub live: LiveSettings,
+}
+
+/// The settings a running server picks up for every request.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Live {
+    /// Answers with a 503 when the rest of the stack takes longer.
+    pub request_timeout: Option<Duration>,
+    pub rng: RngSource,
+    /// Makes every answer reproducible, see `values::seeded`.
+    pub seed: Option<u64>,
+    pub limits: Limits,
+}
+
+/// Shares `Live` settings with the server, cheap to clone.
+#[derive(Clone, Debug, Default)]
+pub struct LiveSettings(Arc<RwLock<Live>>);
+
+impl LiveSettings {
+    pub fn new(live: Live) -> Self {
+        LiveSettings(Arc::new(RwLock::new(live)))
+    }
+
+    pub fn get(&self) -> Live {
+        *self.0.read().unwrap()
+    }
+
+    /// Requests from now on use `live`.
+    pub fn set(&self, live: Live) {
+        *self.0.write().unwrap() = live;
+    }
+}
+
+/// Binds the server to `addr` and returns the address actually used
+/// (handy with port 0) together with the future that runs the server.
+/// Must be called from within a tokio runtime.
+pub fn bind(
+    addr: &SocketAddr,
+    options: Options,
+) -> Result<(SocketAddr, impl Future<Output = Result<(), hyper::Error>>), hyper::Error> {
+    let mut builder = Server::try_bind(addr)?;
+    if let Some(timeout) = options.header_timeout {
+        builder = builder.http1_header_read_timeout(timeout);
+    }
+
+    trace!("Creating service handler...");
+    let registry = Registry::new();
+    let generated = IntCounterVec::new(
+        Opts::new("random_values_total", "Random values handed out"),
+        &["kind"],
+    )
+    .unwrap();
+    // Both names are unique and valid
+    registry.register(Box::new(generated.clone())).unwrap();
+    // Only a shared rate limit has something to be ready for
+    let mut health = Health::new();
+    if let Some(limit) = &options.rate_limit {
+        health = health.check("rate limits", limit.ping());
+    }
+    let mut pipeline = Pipeline::new()
+        .with(Metrics::new(registry).unwrap())
+        .with(health)
+        .with(RequestIds)
+        .with(CatchPanic)
+        .with(AccessLog)
+        .with(Timing)
+        .with(Cors::any().expose_header(HeaderName::from_static(SEED_HEADER)));
+    let live = options.live.clone();
+    pipeline = pipeline.with(move |req, next: Next| {
+        let timeout = live.get().request_timeout;
+        async move {
+            let timeout = match timeout {
+                Some(timeout) => timeout,
+                None => return next.run(req).await,
+            };
+            match tokio::time::timeout(timeout, next.run(req)).await {
+                Ok(response) => response,
+                Err(_) => {
+                    warn!("Request took longer than {:?}", timeout);
+                    json_response(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        json!({"error": "request timed out"}),
+                    )
+                }
+            }
+        }
+    });
+    if let Some(limit) = options.rate_limit {
+        pipeline = pipeline.with(limit);
+    }
+    let (live, seeds) = (options.live, Seeds::default());
+    let stack = pipeline.handler(move |req| {
+        microservice_handler(req, live.get(), seeds.clone(), generated.clone())
+    });
+    let server = builder.serve(make_service_fn(move |conn: &AddrStream| {
+        let stack = stack.clone();
+        let client = conn.remote_addr();
+        async move { Ok::<_, Infallible>(service_fn(move |req| stack.serve(client, req))) }
+    }));
+
+    Ok((server.local_addr(), server))
+}
+
+/// Answers `/` with a random byte as text, like it always has, and the
+/// typed endpoints with JSON, see `values`.
+async fn microservice_handler(
+    req: Request<Body>,
+    live: Live,
+    seeds: Seeds,
+    generated: IntCounterVec,
+) -> Response<Body> {
+    trace!("Incoming request: {} {}", req.method(), req.uri());
+    let path = req.uri().path();
+    let kind = Kind::from_path(path);
+    let route = match kind {
+        Some(kind) => kind.path(),
+        None if path == "/" => "/",
+        None => return json_response(StatusCode::NOT_FOUND, json!({"error": "no such endpoint"})),
+    };
+    let mut response = answer(&req, kind, live, &seeds, &generated);
+    response
+        .extensions_mut()
+        .insert(MatchedRoute(route.to_owned()));
+    response
+}
+
+/// Makes the value for `/` or the endpoint of `kind`.
+fn answer(
+    req: &Request<Body>,
+    kind: Option<Kind>,
+    live: Live,
+    seeds: &Seeds,
+    generated: &IntCounterVec,
+) -> Response<Body> {
+    if kind.is_some() && req.method() != Method::GET && req.method() != Method::HEAD {
+        let mut response = json_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            json!({"error": "only GET is allowed"}),
+        );
+        let allow = HeaderValue::from_static("GET, HEAD");
+        response.headers_mut().insert(ALLOW, allow);
+        return response;
+    }
+
+    let (seed, query) = match values::take_seed(req.uri().query().unwrap_or("")) {
+        Ok(split) => split,
+        Err(err) => return json_response(StatusCode::BAD_REQUEST, json!({"error": err.0})),
+    };
+    // A seed the request brings wins over the server's
+    let seed = seed.or_else(|| seeds.next(live.seed));
+    let mut rng: Box<dyn RngCore> = match seed {
+        Some(seed) => Box::new(values::seeded(seed)),
+        None => match live.rng.rng() {
+            Ok(rng) => rng,
+            Err(err) => {
+                error!("No {} random source: {}", live.rng, err);
+                return json_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({"error": "no random source"}),
+                );
+            }
+        },
+    };
+
+    let kind = match kind {
+        Some(kind) => kind,
+        None => {
+            let random_byte: u8 = rng.gen();
+            trace!("Generated value is: {}", random_byte);
+            generated.with_label_values(&["u8"]).inc();
+            let mut response = Response::new(Body::from(random_byte.to_string()));
+            // The body is just the number, so the seed goes in a header
+            if let Some(seed) = seed {
+                response.headers_mut().insert(SEED_HEADER, seed.into());
+            }
+            return response;
+        }
+    };
+    match values::generate(kind, &query, &live.limits, &mut rng) {
+        Ok(mut value) => {
+            trace!("Generated value is: {}", value["value"]);
+            generated.with_label_values(&[kind.name()]).inc();
+            if let Some(seed) = seed {
+                value["seed"] = json!(seed);
+            }
+            json_response(StatusCode::OK, value)
+        }
+        Err(err) => json_response(StatusCode::BAD_REQUEST, json!({"error": err.0})),
+    }
+}
+
+/// Hands out a seed to every request without one while `Live::seed` is
+/// set. They come from ChaCha20 keyed with it, so a server started with
+/// the same seed answers the same requests in the same order alike.
+#[derive(Clone, Default)]
+struct Seeds(Arc<Mutex<Option<(u64, ChaChaRng)>>>);
+
+impl Seeds {
+    fn next(&self, global: Option<u64>) -> Option<u64> {
+        let global = global?;
+        let mut seeds = self.0.lock().unwrap();
+        // Starts over when a reload brings a new seed
+        if seeds.as_ref().map(|(seed, _)| *seed) != Some(global) {
+            *seeds = Some((global, values::seeded(global)));
+        }
+        seeds.as_mut().map(|(_, rng)| rng.next_u64())
+    }
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    let content_type = HeaderValue::from_static("application/json");
+    response.headers_mut().insert(CONTENT_TYPE, content_type);
+    response
+}
@@ -0,0 +1,191 @@
+//! Log output, filtered with `log.level` or `RUST_LOG` as before.
+//!
+//! `Text` gives one line per record for people to read, `Json` one JSON
+//! object per line for the log pipeline:
+//!
+//! ```text
+//! {"level":"TRACE","message":"Generated value is: 7","request_id":"abc-123","target":"rand_value_server","ts":"2020-05-01T12:00:00Z"}
+//! ```
+//!
+//! Records logged while a request is handled carry its id, the one the
+//! client sent in `X-Request-Id` or a generated one, see `RequestIds`.
+//!
+//! The filter and the format can change while the server runs, see
+//! `LogHandle`.
+
+use env_filter::Filter;
+use env_logger::fmt::Formatter;
+use env_logger::{Builder, Logger};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use middleware::RequestId;
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// How log records are written to stderr.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Every format by name, for the command line.
+pub const LOG_FORMATS: [&str; 2] = ["text", "json"];
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {:?}, use text or json", s)),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogFormat::Text => f.write_str("text"),
+            LogFormat::Json => f.write_str("json"),
+        }
+    }
+}
+
+/// Installs the logger with a filter like `RUST_LOG` takes. Call it
+/// once, before anything is logged. The handle changes both later.
+pub fn init(format: LogFormat, filter: &str) -> LogHandle {
+    let handle = LogHandle::new(format, filter);
+    let shared = handle.0.clone();
+    let mut builder = Builder::new();
+    // Everything gets through to here, `Reloadable` does the filtering
+    builder
+        .filter_level(LevelFilter::Trace)
+        .format(move |buf, record| {
+            if shared.json.load(Ordering::Relaxed) {
+                write_json(buf, record)
+            } else {
+                write_text(buf, record)
+            }
+        });
+    let logger = Reloadable {
+        inner: builder.build(),
+        shared: handle.0.clone(),
+    };
+    log::set_boxed_logger(Box::new(logger)).expect("the logger is set up once");
+    log::set_max_level(handle.0.filter.read().unwrap().filter());
+    handle
+}
+
+/// Changes the logger `init` installed while the server runs.
+#[derive(Clone)]
+pub struct LogHandle(Arc<Shared>);
+
+struct Shared {
+    filter: RwLock<Filter>,
+    json: AtomicBool,
+}
+
+impl LogHandle {
+    /// A handle that isn't installed yet, `init` makes one that is.
+    pub fn new(format: LogFormat, filter: &str) -> Self {
+        LogHandle(Arc::new(Shared {
+            filter: RwLock::new(parse_filter(filter)),
+            json: AtomicBool::new(format == LogFormat::Json),
+        }))
+    }
+
+    /// Switches to `format` and `filter` for every record from now on.
+    pub fn update(&self, format: LogFormat, filter: &str) {
+        let filter = parse_filter(filter);
+        log::set_max_level(filter.filter());
+        *self.0.filter.write().unwrap() = filter;
+        self.0
+            .json
+            .store(format == LogFormat::Json, Ordering::Relaxed);
+    }
+
+    /// Whether a record from `target` at `level` gets logged.
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        let metadata = Metadata::builder().target(target).level(level).build();
+        self.0.filter.read().unwrap().enabled(&metadata)
+    }
+
+    pub fn format(&self) -> LogFormat {
+        if self.0.json.load(Ordering::Relaxed) {
+            LogFormat::Json
+        } else {
+            LogFormat::Text
+        }
+    }
+}
+
+fn parse_filter(filter: &str) -> Filter {
+    env_filter::Builder::new().parse(filter).build()
+}
+
+/// Filters with whatever the handle last set before `inner` writes.
+struct Reloadable {
+    inner: Logger,
+    shared: Arc<Shared>,
+}
+
+impl Log for Reloadable {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.shared.filter.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.shared.filter.read().unwrap().matches(record) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+fn write_text(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    write!(
+        buf,
+        "[{} {:<5} {}] {}",
+        buf.timestamp(),
+        record.level(),
+        record.target(),
+        record.args()
+    )?;
+    match RequestId::current() {
+        Some(id) => writeln!(buf, " request_id={}", id),
+        None => writeln!(buf),
+    }
+}
+
+fn write_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let line = json_line(
+        &buf.timestamp().to_string(),
+        record,
+        RequestId::current().as_ref(),
+    );
+    writeln!(buf, "{}", line)
+}
+
+/// The JSON object for one record. Fields without a value are left out.
+pub fn json_line(timestamp: &str, record: &Record, request_id: Option<&RequestId>) -> Value {
+    let mut line = Map::new();
+    line.insert("ts".into(), timestamp.into());
+    line.insert("level".into(), record.level().as_str().into());
+    line.insert("target".into(), record.target().into());
+    if let Some(id) = request_id {
+        line.insert("request_id".into(), id.0.clone().into());
+    }
+    line.insert("message".into(), record.args().to_string().into());
+    Value::Object(line)
+}
@@ -1,102 +1,433 @@
-use clap::{crate_authors, crate_version, App, AppSettings, Arg, SubCommand};
+use clap::{crate_authors, crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
 use dotenv::dotenv;
-use hyper::rt::Future;
-use hyper::service::service_fn_ok;
-use hyper::{Body, Response, Server};
-use log::{debug, info, trace, warn};
-use serde_derive::Deserialize;
+use log::{debug, error, info, trace, warn};
+use middleware::rate_limit::{MemoryBuckets, RedisBuckets};
+use middleware::{Quota, RateLimit, Rule};
+use rand_value_server::config::{self, Config, ConfigError, Key, Layers, Source};
+use rand_value_server::logging::{self, LogHandle, LOG_FORMATS};
+use rand_value_server::reload::{self, Reloader};
+use rand_value_server::secret::{self, Encoding, SecretError, ENCODINGS};
+use rand_value_server::values::RNG_SOURCES;
+use rand_value_server::{LiveSettings, Options};
 use std::env;
-use std::fs::File;
-use std::io::{self, Read};
-use std::net::SocketAddr;
-
-#[derive(Deserialize)]
-struct Config {
-    address: SocketAddr,
-}
+use std::path::{Path, PathBuf};
 
 fn main() {
     // Enable use of .env file in this program
     dotenv().ok();
-    // Start up the logger implementation
-    pretty_env_logger::init();
+
+    // clap 2 only takes defaults as strings
+    let default_key_length = secret::DEFAULT_BYTES.to_string();
 
     // Get command line args
     let matches = App::new("Server with keys")
         .version(crate_version!())
         .author(crate_authors!())
         .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .global(true)
+                .help("config file to use instead of ./microservice.toml"),
+        )
+        .subcommand(settings(
+            SubCommand::with_name("run").about("run the server"),
+        ))
         .subcommand(
-            SubCommand::with_name("run").about("run the server").arg(
-                Arg::with_name("address")
-                    .short("a")
-                    .long("address")
-                    .value_name("ADDRESS")
-                    .takes_value(true)
-                    .help("address of the server"),
-            ),
+            SubCommand::with_name("config")
+                .about("shows the settings")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(settings(
+                    SubCommand::with_name("print")
+                        .about("prints the merged settings and where each one comes from"),
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("key")
+                .about("generates a secret key for cookies")
+                .arg(
+                    Arg::with_name("length")
+                        .long("length")
+                        .value_name("BYTES")
+                        .default_value(&default_key_length)
+                        .validator(|value| match value.parse::<usize>() {
+                            Ok(bytes)
+                                if (secret::MIN_BYTES..=secret::MAX_BYTES).contains(&bytes) =>
+                            {
+                                Ok(())
+                            }
+                            _ => Err(format!(
+                                "must be between {} and {} bytes",
+                                secret::MIN_BYTES,
+                                secret::MAX_BYTES
+                            )),
+                        })
+                        .help("how many random bytes the key has"),
+                )
+                .arg(
+                    Arg::with_name("encoding")
+                        .long("encoding")
+                        .value_name("ENCODING")
+                        .possible_values(&ENCODINGS)
+                        .default_value("base64")
+                        .help("how the key is written down"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .conflicts_with("dotenv")
+                        .help("writes the key to a new file only you can read"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .requires("output")
+                        .help("replaces the --output file if there is one"),
+                )
+                .arg(
+                    Arg::with_name("dotenv")
+                        .long("dotenv")
+                        .value_name("FILE")
+                        .min_values(0)
+                        .max_values(1)
+                        .help("appends the key to a .env file, ./.env if no file is given"),
+                ),
         )
-        .subcommand(SubCommand::with_name("key").about("generates a secret key for cookies"))
         .get_matches();
 
-    // Gets info from a toml config file
-    let config = File::open("microservice.toml")
-        .and_then(|mut file| {
-            let mut buffer = String::new();
-            file.read_to_string(&mut buffer)?;
-            Ok(buffer)
-        })
-        .and_then(|buffer| {
-            toml::from_str::<Config>(&buffer)
-                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
-        })
-        .map_err(|err| {
-            warn!("Can't read config file: {}", err);
-        })
-        .ok();
+    // Making a key doesn't need the config or the logger
+    if let Some(key) = matches.subcommand_matches("key") {
+        if let Err(err) = make_key(key) {
+            eprintln!("Can't make a key: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    info!("Rand Microservice - v0.1.0");
-    trace!("Starting...");
-
-    // Get the address from an environment variable or default to localhost
-    let localhost = ([127, 0, 0, 1], 8080);
-    let addr: SocketAddr = matches
-        // Prioritize cmd line args
-        .value_of("address")
-        .map(|s| s.to_string())
-        // If none given, try env variable
-        .or(env::var("ADDRESS").ok())
-        .and_then(|addr| addr.parse().ok())
-        // If none given, try config file
-        .or(config.map(|config| config.address))
-        // Default value
-        .or_else(|| Some(localhost.into()))
-        // At this point we are guaranteed a value
-        .unwrap();
+    // The logger is set up from the config, so problems with it can
+    // only go to stderr
+    let (flags, print) = match matches.subcommand() {
+        ("config", Some(config)) => (config.subcommand_matches("print").unwrap(), true),
+        (_, run) => (run.unwrap(), false),
+    };
+    let origin = Origin::new(flags);
+    let layers = match origin.load() {
+        Ok(layers) => layers,
+        Err(err) => {
+            eprintln!("Bad config: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let config = layers.resolve();
+    if print {
+        for warning in layers.warnings() {
+            eprintln!("Warning: {}", warning);
+        }
+        print!("{}", layers.print());
+    }
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Bad config: {}", err);
+            std::process::exit(1);
+        }
+    };
+    if print {
+        return;
+    }
 
-    debug!("Trying to bind server to address: {:?}", addr);
-    let builder = Server::bind(&addr);
-
-    trace!("Creating service handler...");
-    let server = builder.serve(|| {
-        service_fn_ok(|req| {
-            trace!("Incoming request is: {:?}", req);
-            let random_byte: u8 = rand::random();
-            trace!("Generated value is: {}", random_byte);
-            Response::new(Body::from(random_byte.to_string()))
-        })
-    });
-    info!("Used address: {}", server.local_addr());
-
-    // Tell the server to drop any errors in the service function
-    let server = server.map_err(drop);
-
-    // Use RUST_LOG env variable to see logs
+    // Use RUST_LOG or log.level to see logs
     // ex: RUST_LOG=rand_value=trace,warn
     // this sets the log filter level to trace for all targets
     // (crates) with the 'rand_value' prefix and to warn for all
     // other targets. Can also use a .env file without the target
     // specification.
+    let log = logging::init(config.log_format, &config.log_level);
+    for warning in layers.warnings() {
+        warn!("{}", warning);
+    }
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(threads) = config.worker_threads {
+        runtime.worker_threads(threads);
+    }
+    match runtime.enable_all().build() {
+        Ok(runtime) => runtime.block_on(run(config, origin, log)),
+        Err(err) => {
+            error!("Can't start the runtime: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Adds a flag for every setting, see `config::KEYS`.
+fn settings<'a, 'b>(command: App<'a, 'b>) -> App<'a, 'b> {
+    command
+        .arg(
+            Arg::with_name("address")
+                .short("a")
+                .long("address")
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .help("address of the server"),
+        )
+        .arg(
+            Arg::with_name("worker-threads")
+                .long("worker-threads")
+                .value_name("COUNT")
+                .help("threads answering requests, one per CPU by default"),
+        )
+        .arg(
+            Arg::with_name("request-timeout")
+                .long("request-timeout")
+                .value_name("DURATION")
+                .help("answers with a 503 when a request takes longer, e.g. 30s"),
+        )
+        .arg(
+            Arg::with_name("header-timeout")
+                .long("header-timeout")
+                .value_name("DURATION")
+                .help("drops clients that take longer to send their headers"),
+        )
+        .arg(
+            Arg::with_name("secret-file")
+                .long("secret-file")
+                .value_name("FILE")
+                .help("file with the secret key, see the key subcommand"),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .value_name("FILTER")
+                .help("which logs to show, like RUST_LOG"),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .possible_values(&LOG_FORMATS)
+                .help("how log lines look, json is one object per line"),
+        )
+        .arg(
+            Arg::with_name("rng")
+                .long("rng")
+                .value_name("SOURCE")
+                .possible_values(&RNG_SOURCES)
+                .help("where random values come from"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("makes values reproducible, see rng.seed"),
+        )
+        .arg(
+            Arg::with_name("max-string-length")
+                .long("max-string-length")
+                .value_name("CHARS")
+                .help("longest string /string makes"),
+        )
+        .arg(
+            Arg::with_name("max-bytes")
+                .long("max-bytes")
+                .value_name("BYTES")
+                .help("most bytes /bytes hands out at once"),
+        )
+        .arg(
+            Arg::with_name("rate-limit")
+                .long("rate-limit")
+                .value_name("QUOTA")
+                .validator(|value| value.parse::<Quota>().map(drop))
+                .help("how often each client may call, e.g. 100/min"),
+        )
+        .arg(
+            Arg::with_name("route-limit")
+                .long("route-limit")
+                .value_name("RULE")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|value| value.parse::<Rule>().map(drop))
+                .help("a quota for some routes instead, e.g. \"GET /=10/s\", may be repeated"),
+        )
+        .arg(
+            Arg::with_name("rate-limit-redis")
+                .long("rate-limit-redis")
+                .value_name("REDIS_URL")
+                .help("shares rate limits with other instances through this redis"),
+        )
+}
+
+/// Where the config comes from besides the environment, kept to load
+/// it again on reload.
+struct Origin {
+    /// The file given with `--config`.
+    file: Option<PathBuf>,
+    /// The flags given for settings, by key.
+    flags: Vec<(&'static Key, String)>,
+}
+
+impl Origin {
+    fn new(flags: &ArgMatches) -> Self {
+        let settings = config::KEYS
+            .iter()
+            .filter_map(|key| {
+                let values = flags.values_of(key.flag)?;
+                Some((key, values.collect::<Vec<_>>().join(",")))
+            })
+            .collect();
+        Origin {
+            // A global arg shows up in the subcommand's matches
+            file: flags.value_of("config").map(PathBuf::from),
+            flags: settings,
+        }
+    }
+
+    /// The config file to use.
+    fn path(&self) -> PathBuf {
+        self.file
+            .clone()
+            .unwrap_or_else(|| config::DEFAULT_PATH.into())
+    }
+
+    /// Merges the defaults, the config file, the environment and the flags.
+    fn load(&self) -> Result<Layers, ConfigError> {
+        let mut layers = Layers::new();
+        // Only a file asked for by name has to be there
+        layers.file(self.path(), self.file.is_some())?;
+        // Not UTF-8 can't be one of ours
+        layers.env(env::vars_os().filter_map(|(var, value)| {
+            Some((var.into_string().ok()?, value.into_string().ok()?))
+        }))?;
+        for (key, value) in &self.flags {
+            layers.set(key.name, value, Source::Flag(key.flag))?;
+        }
+        Ok(layers)
+    }
+}
+
+async fn run(config: Config, origin: Origin, log: LogHandle) {
+    info!("Rand Microservice - v0.1.0");
+    trace!("Starting...");
+
+    check_secrets(config.secret_file.as_deref());
+    let rate_limit = rate_limit(&config).await;
+    let live = LiveSettings::new(config.live());
+    let options = Options {
+        rate_limit,
+        header_timeout: config.header_timeout,
+        live: live.clone(),
+    };
+
+    // Safe settings change on SIGHUP or when the file does, see reload
+    let reloader = Reloader::new(config.clone(), log, live);
+    tokio::spawn(reload::watch(
+        origin.path(),
+        reload::POLL_INTERVAL,
+        move || {
+            let layers = origin.load()?;
+            for warning in layers.warnings() {
+                warn!("{}", warning);
+            }
+            layers.resolve()
+        },
+        reloader,
+    ));
+
+    let addr = config.address;
+    debug!("Trying to bind server to address: {:?}", addr);
+    let server = match rand_value_server::bind(&addr, options) {
+        Ok((addr, server)) => {
+            info!("Used address: {}", addr);
+            server
+        }
+        Err(err) => {
+            error!("Can't bind server to {}: {}", addr, err);
+            std::process::exit(1);
+        }
+    };
+
     debug!("Run!");
-    hyper::rt::run(server);
+    // Drop any errors from the service function
+    let _ = server.await;
+}
+
+/// Makes a secret and puts it where the `key` subcommand was told to.
+fn make_key(matches: &clap::ArgMatches<'_>) -> Result<(), SecretError> {
+    // Both were validated by clap
+    let length = matches.value_of("length").unwrap().parse().unwrap();
+    let encoding: Encoding = matches.value_of("encoding").unwrap().parse().unwrap();
+    let key = secret::generate(length, encoding)?;
+    if let Some(path) = matches.value_of("output") {
+        secret::write_file(path, &key, matches.is_present("force"))?;
+        eprintln!("Wrote a {} byte key to {}", length, path);
+    } else if matches.is_present("dotenv") {
+        let path = matches.value_of("dotenv").unwrap_or(".env");
+        secret::append_env(path, &key)?;
+        eprintln!("Added {} to {}", secret::ENV_VAR, path);
+    } else {
+        println!("{}", key);
+    }
+    Ok(())
+}
+
+/// Exits if a secret the server was given can't be used.
+fn check_secrets(secret_file: Option<&Path>) {
+    if let Some(path) = secret_file {
+        if let Err(err) = secret::read_file(path) {
+            error!("Can't use secret file {}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+        match secret::readable_by_others(path) {
+            Ok(true) => warn!(
+                "Secret file {} can be read by others, chmod 600 it",
+                path.display()
+            ),
+            Ok(false) => {}
+            Err(err) => warn!("Can't check who may read {}: {}", path.display(), err),
+        }
+        debug!("Secret read from {}", path.display());
+    }
+    // dotenv() put it there if it's in .env
+    if let Ok(key) = env::var(secret::ENV_VAR) {
+        if let Err(err) = secret::check(&key) {
+            error!("Can't use {}: {}", secret::ENV_VAR, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Builds the rate limiter the config asks for, if any.
+async fn rate_limit(config: &Config) -> Option<RateLimit> {
+    if config.rate_limit.is_none() && config.route_limits.is_empty() {
+        if config.rate_limit_redis.is_some() {
+            warn!("rate_limit.redis does nothing without a quota or route limits");
+        }
+        return None;
+    }
+
+    let mut limit = match &config.rate_limit_redis {
+        Some(url) => match RedisBuckets::connect(url).await {
+            Ok(buckets) => RateLimit::new(buckets),
+            Err(err) => {
+                error!("Can't connect to rate limits at {}: {}", url, err);
+                std::process::exit(1);
+            }
+        },
+        None => RateLimit::new(MemoryBuckets::new()),
+    };
+    if let Some(quota) = config.rate_limit {
+        limit = limit.default_quota(quota);
+    }
+    // The first matching route limit wins
+    Some(
+        config
+            .route_limits
+            .iter()
+            .cloned()
+            .fold(limit, RateLimit::route),
+    )
 }
@@ -0,0 +1,165 @@
+//! Picks up config changes while the server runs.
+//!
+//! The config is loaded again on SIGHUP and whenever the file changes,
+//! which is checked every `POLL_INTERVAL`. Only the `LIVE_KEYS` are
+//! taken on: the log filter and format, the request timeout and how
+//! values are made. Anything else, like the address, needs a restart and
+//! keeps its running value with a warning. A config that doesn't load is
+//! logged and ignored, the server goes on with the last good one.
+
+use crate::config::{Config, ConfigError, LIVE_KEYS};
+use crate::logging::LogHandle;
+use crate::LiveSettings;
+use log::{error, info, warn};
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often the config file is checked for changes.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What a reload changed, by key.
+#[derive(Debug, Default, PartialEq)]
+pub struct Outcome {
+    pub applied: Vec<&'static str>,
+    /// Changed, but only a restart would take them on.
+    pub rejected: Vec<&'static str>,
+}
+
+/// Holds the running config and hands new ones to the server.
+pub struct Reloader {
+    running: Config,
+    log: LogHandle,
+    live: LiveSettings,
+}
+
+impl Reloader {
+    pub fn new(running: Config, log: LogHandle, live: LiveSettings) -> Self {
+        Reloader { running, log, live }
+    }
+
+    /// The config the server runs with right now.
+    pub fn running(&self) -> &Config {
+        &self.running
+    }
+
+    /// Loads a config with `load` and applies it. One that doesn't load
+    /// changes nothing, even if loading panics, so the watcher lives on.
+    pub fn reload<F>(&mut self, load: F) -> Option<Outcome>
+    where
+        F: FnOnce() -> Result<Config, ConfigError>,
+    {
+        // Nothing is shared with load, so asserting unwind safety is fine
+        match panic::catch_unwind(AssertUnwindSafe(load)) {
+            Ok(Ok(config)) => Some(self.apply(config)),
+            Ok(Err(err)) => {
+                error!("Can't reload config, keeping the running one: {}", err);
+                None
+            }
+            Err(_) => {
+                error!("Reloading the config panicked, keeping the running one");
+                None
+            }
+        }
+    }
+
+    /// Takes on the live settings of `new` and warns about the others.
+    pub fn apply(&mut self, new: Config) -> Outcome {
+        let mut outcome = Outcome::default();
+        for key in self.running.diff(&new) {
+            if LIVE_KEYS.contains(&key) {
+                outcome.applied.push(key);
+            } else {
+                warn!("{} changed, but only a restart takes that on", key);
+                outcome.rejected.push(key);
+            }
+        }
+        if outcome.applied.is_empty() {
+            info!("Config reloaded, nothing to change");
+            return outcome;
+        }
+
+        let running = &mut self.running;
+        running.request_timeout = new.request_timeout;
+        running.log_level = new.log_level;
+        running.log_format = new.log_format;
+        running.rng = new.rng;
+        running.seed = new.seed;
+        running.limits = new.limits;
+        self.live.set(running.live());
+        self.log.update(running.log_format, &running.log_level);
+        info!("Config reloaded, changed {}", outcome.applied.join(", "));
+        outcome
+    }
+}
+
+/// Reloads with `load` on SIGHUP and when the file at `path` changes,
+/// checking it every `every`. Runs until the server stops.
+pub async fn watch<F>(path: PathBuf, every: Duration, mut load: F, mut reloader: Reloader)
+where
+    F: FnMut() -> Result<Config, ConfigError>,
+{
+    let mut hangups = Hangups::new();
+    let mut last = modified(&path);
+    let mut interval = tokio::time::interval(every);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let now = modified(&path);
+                if now == last {
+                    continue;
+                }
+                last = now;
+                info!("Reloading config after a change to {}", path.display());
+            }
+            _ = hangups.next() => info!("Reloading config after SIGHUP"),
+        }
+        reloader.reload(&mut load);
+    }
+}
+
+/// When the file was last written and how long it is, if it's there.
+fn modified(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// SIGHUPs sent to the process. Waits forever where there are none.
+struct Hangups {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangups {
+    #[cfg(unix)]
+    fn new() -> Self {
+        use tokio::signal::unix::{signal, SignalKind};
+        let signal = match signal(SignalKind::hangup()) {
+            Ok(signal) => Some(signal),
+            Err(err) => {
+                warn!("Can't listen for SIGHUP, only file changes reload: {}", err);
+                None
+            }
+        };
+        Hangups { signal }
+    }
+
+    #[cfg(not(unix))]
+    fn new() -> Self {
+        Hangups {}
+    }
+
+    async fn next(&mut self) {
+        #[cfg(unix)]
+        {
+            if let Some(signal) = &mut self.signal {
+                if signal.recv().await.is_some() {
+                    return;
+                }
+                self.signal = None;
+            }
+        }
+        std::future::pending().await
+    }
+}
@@ -0,0 +1,205 @@
+//! The secret key for cookies.
+//!
+//! `key` makes one from the OS random source and prints it, writes it to
+//! a file only the owner can read, or appends it to `.env` as
+//! `SECRET_KEY`. Secrets are stored as hex or base64 text, and `run`
+//! refuses any that is shorter than `MIN_BYTES` once decoded.
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The variable `.env` keeps the secret in.
+pub const ENV_VAR: &str = "SECRET_KEY";
+/// Shortest secret `run` accepts, in bytes.
+pub const MIN_BYTES: usize = 16;
+/// How long `key` makes secrets unless asked otherwise, in bytes.
+pub const DEFAULT_BYTES: usize = 32;
+/// Longest secret `key` makes, in bytes.
+pub const MAX_BYTES: usize = 1024;
+
+/// How a secret is written down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Hex,
+    Base64,
+}
+
+/// Every encoding by name, for the command line.
+pub const ENCODINGS: [&str; 2] = ["hex", "base64"];
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hex" => Ok(Encoding::Hex),
+            "base64" => Ok(Encoding::Base64),
+            _ => Err(format!("unknown encoding {:?}, use hex or base64", s)),
+        }
+    }
+}
+
+/// Why a secret can't be made, stored or used.
+#[derive(Debug)]
+pub enum SecretError {
+    Io(io::Error),
+    /// The OS random source failed.
+    Random(rand::Error),
+    /// Neither hex nor base64.
+    NotEncoded,
+    /// Fewer than `MIN_BYTES` bytes.
+    TooShort(usize),
+    /// Every byte is the same, like a placeholder full of zeros.
+    Repetitive,
+    /// `.env` already has a secret, which would win over a new one.
+    AlreadySet,
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SecretError::Io(err) => write!(f, "{}", err),
+            SecretError::Random(err) => write!(f, "no secure random source: {}", err),
+            SecretError::NotEncoded => f.write_str("secret is neither hex nor base64"),
+            SecretError::TooShort(bytes) => write!(
+                f,
+                "secret has only {} bytes, it needs at least {}",
+                bytes, MIN_BYTES
+            ),
+            SecretError::Repetitive => f.write_str("secret is the same byte over and over"),
+            SecretError::AlreadySet => write!(f, "{} is already set, remove it first", ENV_VAR),
+        }
+    }
+}
+
+impl Error for SecretError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SecretError::Io(err) => Some(err),
+            SecretError::Random(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SecretError {
+    fn from(err: io::Error) -> Self {
+        SecretError::Io(err)
+    }
+}
+
+/// Makes a secret of `bytes` random bytes.
+pub fn generate(bytes: usize, encoding: Encoding) -> Result<String, SecretError> {
+    let mut secret = vec![0; bytes];
+    let mut rng = OsRng::new().map_err(SecretError::Random)?;
+    rng.try_fill_bytes(&mut secret)
+        .map_err(SecretError::Random)?;
+    Ok(encode(&secret, encoding))
+}
+
+fn encode(bytes: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Hex => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        Encoding::Base64 => base64::encode(bytes),
+    }
+}
+
+/// Reads a secret back into bytes, whichever way it was written down.
+pub fn decode(text: &str) -> Result<Vec<u8>, SecretError> {
+    let text = text.trim();
+    let is_hex = !text.is_empty()
+        && text.len().is_multiple_of(2)
+        && text.chars().all(|c| c.is_ascii_hexdigit());
+    if is_hex {
+        // Only hex digits get here, so every pair parses
+        return Ok((0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect());
+    }
+    base64::decode(text).map_err(|_| SecretError::NotEncoded)
+}
+
+/// Checks a secret is good enough to use and returns its bytes.
+pub fn check(text: &str) -> Result<Vec<u8>, SecretError> {
+    let bytes = decode(text)?;
+    if bytes.len() < MIN_BYTES {
+        return Err(SecretError::TooShort(bytes.len()));
+    }
+    if bytes.iter().all(|byte| *byte == bytes[0]) {
+        return Err(SecretError::Repetitive);
+    }
+    Ok(bytes)
+}
+
+/// Reads and checks the secret in `path`.
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, SecretError> {
+    check(&fs::read_to_string(path)?)
+}
+
+/// Whether others than the owner may read `path`.
+#[cfg(unix)]
+pub fn readable_by_others<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(fs::metadata(path)?.permissions().mode() & 0o077 != 0)
+}
+
+#[cfg(not(unix))]
+pub fn readable_by_others<P: AsRef<Path>>(_path: P) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Writes `secret` to a new file at `path` that only its owner can read
+/// or write. An existing file is only replaced with `overwrite`.
+pub fn write_file<P: AsRef<Path>>(path: P, secret: &str, overwrite: bool) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if overwrite {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&path)?;
+    // The mode only applies to new files
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    writeln!(file, "{}", secret)
+}
+
+/// Appends `SECRET_KEY=<secret>` to the env file at `path`, creating it
+/// if needed. Refuses if the file sets the secret already, since the
+/// first one would keep winning.
+pub fn append_env<P: AsRef<Path>>(path: P, secret: &str) -> Result<(), SecretError> {
+    let path = path.as_ref();
+    let prefix = format!("{}=", ENV_VAR);
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let set = contents.lines().any(|line| {
+        let line = line.trim_start();
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        line.trim_start().starts_with(&prefix)
+    });
+    if set {
+        return Err(SecretError::AlreadySet);
+    }
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        writeln!(file)?;
+    }
+    writeln!(file, "{}{}", prefix, secret)?;
+    Ok(())
+}
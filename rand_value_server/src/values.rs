@@ -0,0 +1,381 @@
+//! Typed random values, one endpoint per type:
+//!
+//! * `/int?min=0&max=100` a whole number in `min..=max`
+//! * `/float?min=0&max=1` a number in `min..max`
+//! * `/bool?p=0.5` true with probability `p`
+//! * `/uuid` a version 4 UUID
+//! * `/string?length=16&alphabet=alphanumeric` characters from a named
+//!   alphabet, or from your own with `chars=` instead
+//! * `/bytes?length=16&encoding=hex` bytes as hex or base64
+//!
+//! Every parameter is optional. Answers are JSON, `{"value": ...}` when
+//! it worked and `{"error": "..."}` with a 400 when a parameter is bad.
+//!
+//! Every endpoint also takes `seed=<u64>`, which makes the value come
+//! from `seeded` instead of the configured source. The answer says which
+//! seed it used, `{"value": ..., "seed": 42}`, so asking again with it
+//! gives the same value.
+
+use rand::distributions::Uniform;
+use rand::prng::ChaChaRng;
+use rand::rngs::OsRng;
+use rand::{Rng, RngCore, SeedableRng};
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Longest string `/string` makes unless configured otherwise, in characters.
+pub const MAX_STRING_LENGTH: usize = 1024;
+/// Most bytes `/bytes` hands out at once unless configured otherwise.
+pub const MAX_BYTES: usize = 4096;
+
+/// How much a single request may ask for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    pub max_string_length: usize,
+    pub max_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_string_length: MAX_STRING_LENGTH,
+            max_bytes: MAX_BYTES,
+        }
+    }
+}
+
+/// Where random values come from.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RngSource {
+    /// The fast thread local generator, seeded from the OS.
+    #[default]
+    Thread,
+    /// The OS random source itself, slower but nothing is kept in memory.
+    Os,
+}
+
+/// Every source by name, for the command line.
+pub const RNG_SOURCES: [&str; 2] = ["thread", "os"];
+
+impl RngSource {
+    /// A generator for one request.
+    pub fn rng(self) -> Result<Box<dyn RngCore>, rand::Error> {
+        match self {
+            RngSource::Thread => Ok(Box::new(rand::thread_rng())),
+            RngSource::Os => Ok(Box::new(OsRng::new()?)),
+        }
+    }
+}
+
+impl FromStr for RngSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "thread" => Ok(RngSource::Thread),
+            "os" => Ok(RngSource::Os),
+            _ => Err(format!("unknown rng source {:?}, use thread or os", s)),
+        }
+    }
+}
+
+impl fmt::Display for RngSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RngSource::Thread => f.write_str("thread"),
+            RngSource::Os => f.write_str("os"),
+        }
+    }
+}
+
+/// The types of values there are endpoints for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Int,
+    Float,
+    Bool,
+    Uuid,
+    String,
+    Bytes,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 6] = [
+        Kind::Int,
+        Kind::Float,
+        Kind::Bool,
+        Kind::Uuid,
+        Kind::String,
+        Kind::Bytes,
+    ];
+
+    /// The kind served at `path`, if any.
+    pub fn from_path(path: &str) -> Option<Kind> {
+        Kind::ALL.iter().copied().find(|kind| kind.path() == path)
+    }
+
+    pub fn path(self) -> &'static str {
+        match self {
+            Kind::Int => "/int",
+            Kind::Float => "/float",
+            Kind::Bool => "/bool",
+            Kind::Uuid => "/uuid",
+            Kind::String => "/string",
+            Kind::Bytes => "/bytes",
+        }
+    }
+
+    /// The name used in metrics.
+    pub fn name(self) -> &'static str {
+        &self.path()[1..]
+    }
+}
+
+/// A parameter is wrong, the message says which and how.
+#[derive(Debug, PartialEq)]
+pub struct BadRequest(pub String);
+
+impl fmt::Display for BadRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for BadRequest {}
+
+fn bad<T>(message: impl Into<String>) -> Result<T, BadRequest> {
+    Err(BadRequest(message.into()))
+}
+
+/// ChaCha20 keyed with `seed` in little endian, followed by zeros. The
+/// same seed gives the same values, at least with the same `rand`.
+pub fn seeded(seed: u64) -> ChaChaRng {
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(&seed.to_le_bytes());
+    ChaChaRng::from_seed(key)
+}
+
+/// Takes `seed` out of `query`, returning it and the other parameters.
+pub fn take_seed(query: &str) -> Result<(Option<u64>, String), BadRequest> {
+    let mut pairs: Vec<(String, String)> = parse(query)?;
+    let seed = match pairs.iter().position(|(name, _)| name == "seed") {
+        Some(at) => match pairs.remove(at).1.parse() {
+            Ok(seed) => Some(seed),
+            Err(_) => return bad("seed must be a whole number from 0 to 2^64-1"),
+        },
+        None => None,
+    };
+    if pairs.iter().any(|(name, _)| name == "seed") {
+        return bad("give seed only once");
+    }
+    // Pairs that were just parsed always serialize again
+    Ok((seed, serde_urlencoded::to_string(&pairs).unwrap()))
+}
+
+fn parse<T: DeserializeOwned>(query: &str) -> Result<T, BadRequest> {
+    serde_urlencoded::from_str(query).map_err(|err| BadRequest(err.to_string()))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IntQuery {
+    #[serde(default)]
+    min: i64,
+    #[serde(default = "IntQuery::default_max")]
+    max: i64,
+}
+
+impl IntQuery {
+    fn default_max() -> i64 {
+        100
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FloatQuery {
+    #[serde(default)]
+    min: f64,
+    #[serde(default = "FloatQuery::default_max")]
+    max: f64,
+}
+
+impl FloatQuery {
+    fn default_max() -> f64 {
+        1.0
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoolQuery {
+    #[serde(default = "BoolQuery::default_p")]
+    p: f64,
+}
+
+impl BoolQuery {
+    fn default_p() -> f64 {
+        0.5
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoQuery {}
+
+/// The alphabets `/string` knows by name.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Alphabet {
+    Alphanumeric,
+    Letters,
+    Lowercase,
+    Uppercase,
+    Digits,
+    Hex,
+}
+
+impl Alphabet {
+    fn chars(self) -> &'static str {
+        match self {
+            Alphabet::Alphanumeric => {
+                "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789"
+            }
+            Alphabet::Letters => "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz",
+            Alphabet::Lowercase => "abcdefghijklmnopqrstuvwxyz",
+            Alphabet::Uppercase => "ABCDEFGHIJKLMNOPQRSTUVWXYZ",
+            Alphabet::Digits => "0123456789",
+            Alphabet::Hex => "0123456789abcdef",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StringQuery {
+    #[serde(default = "default_length")]
+    length: usize,
+    alphabet: Option<Alphabet>,
+    /// Characters to pick from instead of a named alphabet.
+    chars: Option<String>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    Hex,
+    Base64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BytesQuery {
+    #[serde(default = "default_length")]
+    length: usize,
+    #[serde(default = "BytesQuery::default_encoding")]
+    encoding: Encoding,
+}
+
+impl BytesQuery {
+    fn default_encoding() -> Encoding {
+        Encoding::Hex
+    }
+}
+
+fn default_length() -> usize {
+    16
+}
+
+/// Makes a value of `kind` as asked for in `query`, the query string
+/// without the `?`.
+pub fn generate<R: Rng>(
+    kind: Kind,
+    query: &str,
+    limits: &Limits,
+    rng: &mut R,
+) -> Result<Value, BadRequest> {
+    let value = match kind {
+        Kind::Int => {
+            let IntQuery { min, max } = parse(query)?;
+            if min > max {
+                return bad("min must not be larger than max");
+            }
+            json!(rng.sample(Uniform::new_inclusive(min, max)))
+        }
+        Kind::Float => {
+            let FloatQuery { min, max } = parse(query)?;
+            if !(max - min).is_finite() {
+                return bad("min and max must be finite and not too far apart");
+            }
+            if min >= max {
+                return bad("min must be smaller than max");
+            }
+            json!(rng.gen_range(min, max))
+        }
+        Kind::Bool => {
+            let BoolQuery { p } = parse(query)?;
+            if !(0.0..=1.0).contains(&p) {
+                return bad("p must be between 0 and 1");
+            }
+            json!(rng.gen_bool(p))
+        }
+        Kind::Uuid => {
+            let NoQuery {} = parse(query)?;
+            let mut bytes = [0; 16];
+            rng.fill(&mut bytes);
+            json!(uuid::Builder::from_random_bytes(bytes)
+                .into_uuid()
+                .to_string())
+        }
+        Kind::String => {
+            let StringQuery {
+                length,
+                alphabet,
+                chars,
+            } = parse(query)?;
+            if length > limits.max_string_length {
+                return bad(format!(
+                    "length can be at most {}",
+                    limits.max_string_length
+                ));
+            }
+            let chars = match (alphabet, chars) {
+                (Some(_), Some(_)) => return bad("give either alphabet or chars, not both"),
+                (None, Some(chars)) => chars,
+                (alphabet, None) => alphabet
+                    .unwrap_or(Alphabet::Alphanumeric)
+                    .chars()
+                    .to_owned(),
+            };
+            let chars = chars.chars().collect::<Vec<_>>();
+            if chars.is_empty() {
+                return bad("chars must not be empty");
+            }
+            let pick = Uniform::new(0, chars.len());
+            let value = (0..length)
+                .map(|_| chars[rng.sample(pick)])
+                .collect::<String>();
+            json!(value)
+        }
+        Kind::Bytes => {
+            let BytesQuery { length, encoding } = parse(query)?;
+            if length > limits.max_bytes {
+                return bad(format!("length can be at most {}", limits.max_bytes));
+            }
+            let mut bytes = vec![0; length];
+            rng.fill(&mut bytes[..]);
+            match encoding {
+                Encoding::Hex => json!(bytes
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect::<String>()),
+                Encoding::Base64 => json!(base64::encode(&bytes)),
+            }
+        }
+    };
+    Ok(json!({ "value": value }))
+}
@@ -0,0 +1,131 @@
+// Shared by several test binaries, each of which only uses some helpers
+#![allow(dead_code)]
+
+use hyper::header::HeaderMap;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use rand_value_server::Options;
+use serde_json::Value;
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+/// What came back for a request.
+pub struct Reply {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl Reply {
+    /// Parses the body as JSON, failing the test if it isn't.
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body)
+            .unwrap_or_else(|err| panic!("body is not JSON ({}): {}", err, self.body))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|value| value.to_str().unwrap())
+    }
+}
+
+/// Starts the server on an ephemeral port and returns its address.
+pub async fn start() -> SocketAddr {
+    start_with(Options::default()).await
+}
+
+/// Starts the server with the given options on an ephemeral port.
+pub async fn start_with(options: Options) -> SocketAddr {
+    let (addr, server) = rand_value_server::bind(&([127, 0, 0, 1], 0).into(), options).unwrap();
+    tokio::spawn(server);
+    addr
+}
+
+/// Sends a request without a body to the server.
+pub async fn send(addr: SocketAddr, method: Method, path: &str) -> Reply {
+    send_with(addr, method, path, &[]).await
+}
+
+/// Sends a request without a body but with some headers to the server.
+pub async fn send_with(
+    addr: SocketAddr,
+    method: Method,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> Reply {
+    let mut req = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path));
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+    let req = req.body(Body::empty()).unwrap();
+    let resp = Client::new().request(req).await.unwrap();
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    let bytes = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    Reply {
+        status,
+        headers,
+        body: String::from_utf8(bytes.to_vec()).unwrap(),
+    }
+}
+
+pub async fn get(addr: SocketAddr, path: &str) -> Reply {
+    send(addr, Method::GET, path).await
+}
+
+/// The real binary, killed when dropped.
+pub struct Server {
+    child: Child,
+    lines: Receiver<String>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Server {
+    /// A command for the binary, to add arguments and environment to.
+    pub fn command() -> Command {
+        Command::new(env!("CARGO_BIN_EXE_rand_value_server"))
+    }
+
+    /// Spawns `command`, reading the log lines it writes to stderr.
+    pub fn spawn(mut command: Command) -> Self {
+        let mut child = command.stderr(Stdio::piped()).spawn().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let (tx, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stderr).lines() {
+                if tx.send(line.unwrap()).is_err() {
+                    break;
+                }
+            }
+        });
+        Server { child, lines }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Waits for the first log line matching `find` and returns it.
+    pub fn wait_for(&self, find: impl Fn(&str) -> bool) -> String {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let line = self
+                .lines
+                .recv_timeout(left)
+                .expect("no matching log line in time");
+            if find(&line) {
+                return line;
+            }
+        }
+    }
+}
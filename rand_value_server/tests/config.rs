@@ -0,0 +1,240 @@
+use rand_value_server::config::{parse_duration, ConfigError, Layers, Source, KEYS};
+use rand_value_server::logging::LogFormat;
+use rand_value_server::values::RngSource;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(var, value)| (var.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn later_layers_win() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("server.toml");
+    fs::write(
+        &path,
+        r#"
+address = "0.0.0.0:9876"
+worker_threads = 3
+
+[log]
+format = "json"
+
+[rng]
+max_bytes = 64
+
+[rate_limit]
+routes = ["GET /=10/s", "/bytes=1/min"]
+"#,
+    )
+    .unwrap();
+
+    let mut layers = Layers::new();
+    assert!(layers.file(&path, true).unwrap());
+    layers
+        .env(vars(&[
+            ("RUST_LOG", "debug"),
+            ("RAND_VALUE_WORKER_THREADS", "5"),
+            ("RAND_VALUE_RNG_SOURCE", "os"),
+            ("PATH", "/bin"),
+        ]))
+        .unwrap();
+    layers
+        .set("worker_threads", "7", Source::Flag("worker-threads"))
+        .unwrap();
+
+    let config = layers.resolve().unwrap();
+    assert_eq!(config.address, ([0, 0, 0, 0], 9876).into());
+    assert_eq!(config.worker_threads, Some(7));
+    assert_eq!(config.log_level, "debug");
+    assert_eq!(config.log_format, LogFormat::Json);
+    assert_eq!(config.rng, RngSource::Os);
+    assert_eq!(config.limits.max_bytes, 64);
+    assert_eq!(config.limits.max_string_length, 1024);
+    assert_eq!(config.request_timeout, Some(Duration::from_secs(30)));
+    assert_eq!(config.route_limits.len(), 2);
+    assert_eq!(config.rate_limit, None);
+
+    assert_eq!(layers.get("address").unwrap().1, &Source::File(path));
+    assert_eq!(
+        layers.get("rng.source").unwrap().1,
+        &Source::Env("RAND_VALUE_RNG_SOURCE".into())
+    );
+    assert_eq!(
+        layers.get("worker_threads").unwrap().1,
+        &Source::Flag("worker-threads")
+    );
+    assert_eq!(layers.get("header_timeout").unwrap().1, &Source::Default);
+}
+
+#[test]
+fn prefixed_log_level_beats_rust_log() {
+    let mut layers = Layers::new();
+    layers
+        .env(vars(&[
+            ("RAND_VALUE_LOG_LEVEL", "warn"),
+            ("RUST_LOG", "trace"),
+        ]))
+        .unwrap();
+    assert_eq!(
+        layers.get("log.level"),
+        Some(("warn", &Source::Env("RAND_VALUE_LOG_LEVEL".into())))
+    );
+
+    // An empty value unsets, here back to no timeout at all
+    layers
+        .env(vars(&[("RAND_VALUE_REQUEST_TIMEOUT", "")]))
+        .unwrap();
+    assert_eq!(layers.resolve().unwrap().request_timeout, None);
+}
+
+#[test]
+fn old_and_unknown_variables_only_warn() {
+    let mut layers = Layers::new();
+    layers
+        .env(vars(&[
+            ("ADDRESS", "0.0.0.0:1"),
+            ("RAND_VALUE_ADRESS", "0.0.0.0:2"),
+        ]))
+        .unwrap();
+    assert_eq!(
+        layers.get("address"),
+        Some(("0.0.0.0:1", &Source::Env("ADDRESS".into())))
+    );
+    assert_eq!(layers.warnings().len(), 2);
+    assert!(layers.warnings()[0].starts_with("ADDRESS is deprecated"));
+    assert!(layers.warnings()[1].contains("RAND_VALUE_ADRESS"));
+
+    // The prefixed one wins, whatever order they come in
+    let mut layers = Layers::new();
+    layers
+        .env(vars(&[
+            ("RAND_VALUE_ADDRESS", "0.0.0.0:3"),
+            ("ADDRESS", "0.0.0.0:1"),
+        ]))
+        .unwrap();
+    assert_eq!(layers.get("address").unwrap().0, "0.0.0.0:3");
+}
+
+#[test]
+fn mistakes_are_reported_with_their_source() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("server.toml");
+
+    fs::write(&path, "adress = \"0.0.0.0:1\"").unwrap();
+    let err = Layers::new().file(&path, true).unwrap_err();
+    assert!(matches!(err, ConfigError::Unknown(ref name, _) if name == "adress"));
+
+    fs::write(&path, "[log]\nformat = \"yaml\"").unwrap();
+    let mut layers = Layers::new();
+    layers.file(&path, true).unwrap();
+    let err = layers.resolve().unwrap_err().to_string();
+    assert!(
+        err.starts_with("log.format = \"yaml\" from file "),
+        "{}",
+        err
+    );
+
+    let mut layers = Layers::new();
+    layers
+        .set("worker_threads", "0", Source::Flag("worker-threads"))
+        .unwrap();
+    assert!(layers.resolve().is_err());
+
+    // Only a file asked for by name has to be there
+    let missing = dir.path().join("missing.toml");
+    assert!(!Layers::new().file(&missing, false).unwrap());
+    assert!(matches!(
+        Layers::new().file(&missing, true),
+        Err(ConfigError::Read(..))
+    ));
+}
+
+#[test]
+fn durations() {
+    assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+    assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+    assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+    assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+    assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+    assert!(parse_duration("0s").is_err());
+    assert!(parse_duration("s").is_err());
+    assert!(parse_duration("3 days").is_err());
+    assert!(parse_duration("999999999999999999h").is_err());
+    assert!(parse_duration("99999999999999999999s").is_err());
+}
+
+#[test]
+fn prints_the_merged_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("server.toml");
+    fs::write(&path, "address = \"0.0.0.0:9876\"\n").unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_rand_value_server"))
+        .args([
+            "--config",
+            "server.toml",
+            "config",
+            "print",
+            "--max-bytes",
+            "8",
+        ])
+        .current_dir(dir.path())
+        .env("RAND_VALUE_LOG_FORMAT", "json")
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let printed = String::from_utf8(out.stdout).unwrap();
+    let lines = printed.lines().collect::<Vec<_>>();
+    assert!(lines.contains(&"address = \"0.0.0.0:9876\" # file server.toml"));
+    assert!(lines.contains(&"format = \"json\" # env RAND_VALUE_LOG_FORMAT"));
+    assert!(lines.contains(&"max_bytes = 8 # flag --max-bytes"));
+    assert!(lines.contains(&"request_timeout = \"30s\" # default"));
+    assert!(lines.contains(&"# secret_file is not set"));
+
+    // What's printed reads back as the same config
+    let copy = dir.path().join("copy.toml");
+    fs::write(&copy, &printed).unwrap();
+    let mut layers = Layers::new();
+    layers.file(&copy, true).unwrap();
+    assert_eq!(layers.resolve().unwrap().limits.max_bytes, 8);
+    assert_eq!(
+        layers.get("address").unwrap().1,
+        &Source::File(PathBuf::from(&copy))
+    );
+}
+
+#[test]
+fn diff_names_every_key_in_order() {
+    let mut layers = Layers::new();
+    for (key, value) in &[
+        ("address", "127.0.0.1:9090"),
+        ("worker_threads", "2"),
+        ("request_timeout", "1s"),
+        ("header_timeout", "1s"),
+        ("secret_file", "secret.key"),
+        ("log.level", "debug"),
+        ("log.format", "json"),
+        ("rng.source", "os"),
+        ("rng.seed", "1"),
+        ("rng.max_string_length", "8"),
+        ("rng.max_bytes", "8"),
+        ("rate_limit.quota", "10/s"),
+        ("rate_limit.routes", "GET /=10/s"),
+        ("rate_limit.redis", "redis://127.0.0.1/"),
+    ] {
+        layers.set(key, value, Source::Flag("test")).unwrap();
+    }
+    let changed = layers.resolve().unwrap();
+    let defaults = Layers::new().resolve().unwrap();
+
+    let names = KEYS.iter().map(|key| key.name).collect::<Vec<_>>();
+    assert_eq!(defaults.diff(&changed), names);
+    assert!(changed.diff(&changed).is_empty());
+}
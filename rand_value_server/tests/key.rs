@@ -0,0 +1,154 @@
+//! Runs the `key` subcommand and checks `run` refuses bad secrets.
+
+use rand_value_server::secret::{self, SecretError};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// Runs the binary in `dir`, so it finds no config or `.env` of ours.
+fn command(dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rand_value_server"));
+    command
+        .args(args)
+        .current_dir(dir)
+        .env_remove(secret::ENV_VAR)
+        .env_remove("RAND_VALUE_SECRET_FILE")
+        .env_remove("RAND_VALUE_ADDRESS");
+    command
+}
+
+fn output(dir: &Path, args: &[&str]) -> Output {
+    command(dir, args).output().unwrap()
+}
+
+#[test]
+fn prints_keys_of_any_length_and_encoding() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let out = output(dir.path(), &["key"]);
+    assert!(out.status.success());
+    let key = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(secret::check(&key).unwrap().len(), secret::DEFAULT_BYTES);
+
+    let out = output(dir.path(), &["key", "--length", "20", "--encoding", "hex"]);
+    assert!(out.status.success());
+    let key = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(key.trim().len(), 40);
+    assert!(key.trim().chars().all(|c| c.is_ascii_hexdigit()));
+
+    // Too short to be worth having
+    let out = output(dir.path(), &["key", "--length", "8"]);
+    assert!(!out.status.success());
+}
+
+#[test]
+fn writes_keys_only_the_owner_can_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("secret");
+
+    let out = output(dir.path(), &["key", "--output", "secret"]);
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+    let first = fs::read_to_string(&path).unwrap();
+    secret::check(&first).unwrap();
+    assert!(!secret::readable_by_others(&path).unwrap());
+
+    // An existing key is only replaced when asked to
+    let out = output(dir.path(), &["key", "--output", "secret"]);
+    assert!(!out.status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), first);
+    let out = output(dir.path(), &["key", "--output", "secret", "--force"]);
+    assert!(out.status.success());
+    assert_ne!(fs::read_to_string(&path).unwrap(), first);
+}
+
+#[test]
+fn appends_keys_to_dotenv_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".env");
+    fs::write(&path, "RUST_LOG=info").unwrap();
+
+    let out = output(dir.path(), &["key", "--dotenv"]);
+    assert!(out.status.success());
+    let contents = fs::read_to_string(&path).unwrap();
+    let mut lines = contents.lines();
+    assert_eq!(lines.next(), Some("RUST_LOG=info"));
+    let key = lines.next().unwrap().strip_prefix("SECRET_KEY=").unwrap();
+    secret::check(key).unwrap();
+    assert_eq!(lines.next(), None);
+
+    // dotenv keeps the first one, so a second would be silently ignored
+    let out = output(dir.path(), &["key", "--dotenv", ".env"]);
+    assert!(!out.status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+}
+
+#[test]
+fn run_refuses_missing_or_weak_secrets() {
+    let dir = tempfile::tempdir().unwrap();
+    let run = |args: &[&str]| {
+        let mut all = vec!["run", "--address", "127.0.0.1:0"];
+        all.extend_from_slice(args);
+        output(dir.path(), &all)
+    };
+
+    let out = run(&["--secret-file", "missing"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Can't use secret file missing"));
+
+    fs::write(dir.path().join("weak"), "00000000000000000000000000000000").unwrap();
+    let out = run(&["--secret-file", "weak"]);
+    assert!(!out.status.success());
+
+    fs::write(dir.path().join(".env"), "SECRET_KEY=c2hvcnQ=\n").unwrap();
+    let out = run(&[]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Can't use SECRET_KEY"));
+}
+
+#[test]
+fn run_starts_with_a_good_secret() {
+    let dir = tempfile::tempdir().unwrap();
+    assert!(output(dir.path(), &["key", "--output", "secret"])
+        .status
+        .success());
+
+    let mut child = command(
+        dir.path(),
+        &["run", "--address", "127.0.0.1:0", "--secret-file", "secret"],
+    )
+    .env("RUST_LOG", "rand_value_server=info")
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let started = BufReader::new(stderr)
+        .lines()
+        .map(Result::unwrap)
+        .any(|line| line.contains("Used address: "));
+    let _ = child.kill();
+    let _ = child.wait();
+    assert!(started);
+}
+
+#[test]
+fn checks_secrets() {
+    let key = secret::generate(16, secret::Encoding::Hex).unwrap();
+    assert_eq!(secret::decode(&key).unwrap().len(), 16);
+    let key = secret::generate(16, secret::Encoding::Base64).unwrap();
+    assert_eq!(secret::check(&key).unwrap().len(), 16);
+
+    assert!(matches!(
+        secret::check("deadbeef"),
+        Err(SecretError::TooShort(4))
+    ));
+    assert!(matches!(
+        secret::check(&"ab".repeat(32)),
+        Err(SecretError::Repetitive)
+    ));
+    assert!(matches!(
+        secret::check("not a key!"),
+        Err(SecretError::NotEncoded)
+    ));
+}
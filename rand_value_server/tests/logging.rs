@@ -0,0 +1,81 @@
+//! Runs the real binary to see what its logs look like.
+
+mod common;
+
+use common::Server;
+use hyper::{Body, Client, Request};
+use rand_value_server::logging::json_line;
+use serde_json::Value;
+use std::net::SocketAddr;
+
+/// Starts the server with JSON logs.
+fn start() -> Server {
+    let mut command = Server::command();
+    command
+        .args(["run", "--address", "127.0.0.1:0", "--log-format", "json"])
+        .env("RUST_LOG", "rand_value_server=trace,access=info");
+    Server::spawn(command)
+}
+
+/// Waits for the first line matching `find`, every line on the way has to be JSON.
+fn wait_for(server: &Server, find: impl Fn(&Value) -> bool) -> Value {
+    let parse = |line: &str| -> Value {
+        serde_json::from_str(line).unwrap_or_else(|err| panic!("not JSON ({}): {}", err, line))
+    };
+    parse(&server.wait_for(|line| find(&parse(line))))
+}
+
+fn message(line: &Value) -> &str {
+    line["message"].as_str().unwrap()
+}
+
+#[tokio::test]
+async fn json_lines_carry_the_request_id() {
+    let server = start();
+    let line = wait_for(&server, |line| message(line).starts_with("Used address: "));
+    assert_eq!(line["level"], "INFO");
+    assert!(line["ts"].is_string());
+    assert!(line.get("request_id").is_none());
+    let addr: SocketAddr = message(&line)["Used address: ".len()..].parse().unwrap();
+
+    let req = Request::get(format!("http://{}/", addr))
+        .header("x-request-id", "trace-me-123")
+        .body(Body::empty())
+        .unwrap();
+    let resp = Client::new().request(req).await.unwrap();
+    assert_eq!(resp.headers()["x-request-id"], "trace-me-123");
+
+    // Every line about the request has its id, the access log's too
+    let line = wait_for(&server, |line| {
+        message(line).starts_with("Generated value is: ")
+    });
+    assert_eq!(line["request_id"], "trace-me-123");
+    assert_eq!(line["target"], "rand_value_server");
+    let line = wait_for(&server, |line| line["target"] == "access");
+    assert_eq!(line["request_id"], "trace-me-123");
+
+    // Without one an id is made up, and still on every line
+    let req = Request::get(format!("http://{}/", addr))
+        .body(Body::empty())
+        .unwrap();
+    let resp = Client::new().request(req).await.unwrap();
+    let id = resp.headers()["x-request-id"].to_str().unwrap();
+    let line = wait_for(&server, |line| {
+        message(line).starts_with("Incoming request: ")
+    });
+    assert_eq!(line["request_id"], id);
+}
+
+#[test]
+fn json_lines_leave_out_missing_ids() {
+    let record = log::Record::builder()
+        .args(format_args!("hello \"world\""))
+        .level(log::Level::Warn)
+        .target("somewhere")
+        .build();
+    let line = json_line("2020-05-01T12:00:00Z", &record, None);
+    assert_eq!(
+        line.to_string(),
+        r#"{"level":"WARN","message":"hello \"world\"","target":"somewhere","ts":"2020-05-01T12:00:00Z"}"#
+    );
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use common::{get, start_with, Server};
+use hyper::StatusCode;
+use log::Level;
+use rand_value_server::config::{Config, ConfigError, Layers, Source};
+use rand_value_server::logging::{LogFormat, LogHandle};
+use rand_value_server::reload::{Outcome, Reloader};
+use rand_value_server::values::RngSource;
+use rand_value_server::{LiveSettings, Options};
+use std::fs;
+use std::net::SocketAddr;
+use std::process::Command;
+use std::time::Duration;
+
+fn config(settings: &[(&'static str, &str)]) -> Config {
+    let mut layers = Layers::new();
+    for (key, value) in settings {
+        layers.set(key, value, Source::Flag("test")).unwrap();
+    }
+    layers.resolve().unwrap()
+}
+
+fn reloader(config: &Config) -> (Reloader, LogHandle, LiveSettings) {
+    let log = LogHandle::new(config.log_format, &config.log_level);
+    let live = LiveSettings::new(config.live());
+    let reloader = Reloader::new(config.clone(), log.clone(), live.clone());
+    (reloader, log, live)
+}
+
+#[test]
+fn only_safe_settings_change_live() {
+    let running = config(&[("address", "127.0.0.1:1000")]);
+    let (mut reloader, log, live) = reloader(&running);
+    assert!(!log.enabled("rand_value_server", Level::Info));
+
+    let outcome = reloader.apply(config(&[
+        ("address", "127.0.0.1:2000"),
+        ("log.level", "rand_value_server=info"),
+        ("log.format", "json"),
+        ("rng.source", "os"),
+        ("rng.max_bytes", "8"),
+        ("request_timeout", "1s"),
+        ("rate_limit.quota", "10/s"),
+    ]));
+    assert_eq!(
+        outcome,
+        Outcome {
+            applied: vec![
+                "request_timeout",
+                "log.level",
+                "log.format",
+                "rng.source",
+                "rng.max_bytes"
+            ],
+            rejected: vec!["address", "rate_limit.quota"],
+        }
+    );
+
+    let now = live.get();
+    assert_eq!(now.rng, RngSource::Os);
+    assert_eq!(now.limits.max_bytes, 8);
+    assert_eq!(now.request_timeout, Some(Duration::from_secs(1)));
+    assert!(log.enabled("rand_value_server", Level::Info));
+    assert!(!log.enabled("access", Level::Info));
+    assert_eq!(log.format(), LogFormat::Json);
+    // What needs a restart keeps running as it was
+    assert_eq!(reloader.running().address, running.address);
+    assert_eq!(reloader.running().rate_limit, None);
+    assert_eq!(reloader.running().limits.max_bytes, 8);
+}
+
+#[test]
+fn bad_configs_change_nothing() {
+    let running = config(&[("rng.max_bytes", "8")]);
+    let (mut reloader, _, live) = reloader(&running);
+
+    let outcome = reloader.reload(|| {
+        let mut layers = Layers::new();
+        layers.set("rng.max_bytes", "lots", Source::Flag("test"))?;
+        layers.resolve()
+    });
+    assert_eq!(outcome, None);
+    let outcome =
+        reloader.reload(|| Err(ConfigError::Unknown("adress".into(), Source::Flag("test"))));
+    assert_eq!(outcome, None);
+    let outcome = reloader.reload(|| panic!("boom"));
+    assert_eq!(outcome, None);
+    assert_eq!(live.get(), running.live());
+    assert!(reloader.running().diff(&running).is_empty());
+}
+
+#[tokio::test]
+async fn running_servers_pick_up_live_settings() {
+    let running = config(&[]);
+    let (mut reloader, _, live) = reloader(&running);
+    let options = Options {
+        live,
+        ..Options::default()
+    };
+    let addr = start_with(options).await;
+
+    assert_eq!(get(addr, "/bytes?length=9").await.status, StatusCode::OK);
+    reloader.apply(config(&[("rng.max_bytes", "8")]));
+    let reply = get(addr, "/bytes?length=9").await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn reloads_on_file_changes_and_sighup() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("server.toml");
+    fs::write(&path, "[rng]\nmax_bytes = 8\n").unwrap();
+
+    let mut command = Server::command();
+    command
+        .args(["run", "--config", "server.toml", "--address", "127.0.0.1:0"])
+        .current_dir(dir.path())
+        .env("RUST_LOG", "rand_value_server=info");
+    let server = Server::spawn(command);
+    let line = server.wait_for(|line| line.contains("Used address: "));
+    let addr: SocketAddr = line.rsplit(' ').next().unwrap().parse().unwrap();
+    let status =
+        |length: usize| async move { get(addr, &format!("/bytes?length={}", length)).await.status };
+    assert_eq!(status(9).await, StatusCode::BAD_REQUEST);
+
+    fs::write(&path, "[rng]\nmax_bytes = 16\n").unwrap();
+    server.wait_for(|line| line.contains("Config reloaded, changed rng.max_bytes"));
+    assert_eq!(status(9).await, StatusCode::OK);
+
+    // A broken file is reported, and the server goes on as it was
+    fs::write(&path, "[rng\nmax_bytes = 4\n").unwrap();
+    server.wait_for(|line| line.contains("Can't reload config, keeping the running one"));
+    assert_eq!(status(9).await, StatusCode::OK);
+
+    // So is a duration too long to count, and later edits still get in
+    fs::write(&path, "request_timeout = \"999999999999999999h\"\n").unwrap();
+    server.wait_for(|line| line.contains("is too long"));
+    fs::write(&path, "[rng]\nmax_bytes = 4\n").unwrap();
+    server.wait_for(|line| line.contains("Config reloaded, changed rng.max_bytes"));
+    assert_eq!(status(9).await, StatusCode::BAD_REQUEST);
+    fs::write(&path, "[rng]\nmax_bytes = 16\n").unwrap();
+    server.wait_for(|line| line.contains("Config reloaded, changed rng.max_bytes"));
+
+    fs::write(&path, "worker_threads = 2\n[rng]\nmax_bytes = 16\n").unwrap();
+    let kill = Command::new("kill")
+        .args(["-HUP", &server.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(kill.success());
+    server.wait_for(|line| line.contains("Reloading config after SIGHUP"));
+    server
+        .wait_for(|line| line.contains("worker_threads changed, but only a restart takes that on"));
+    assert_eq!(status(9).await, StatusCode::OK);
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::{get, send_with, start_with};
+use hyper::{Method, StatusCode};
+use rand::{Rng, RngCore};
+use rand_value_server::values::{self, Kind, Limits};
+use rand_value_server::{Live, LiveSettings, Options, SEED_HEADER};
+use serde_json::json;
+use std::net::SocketAddr;
+
+async fn start(seed: Option<u64>) -> SocketAddr {
+    start_with(Options {
+        live: LiveSettings::new(Live {
+            seed,
+            ..Live::default()
+        }),
+        ..Options::default()
+    })
+    .await
+}
+
+#[test]
+fn seeds_key_chacha20() {
+    let mut rng = values::seeded(1);
+    assert_eq!(rng.next_u64(), 10597511851372368837);
+    assert_eq!(rng.next_u64(), 9609124134916180088);
+}
+
+#[tokio::test]
+async fn requests_can_bring_their_own_seed() {
+    let addr = start(None).await;
+    for kind in Kind::ALL.iter().copied() {
+        let expected = values::generate(kind, "", &Limits::default(), &mut values::seeded(42))
+            .unwrap()["value"]
+            .clone();
+        let path = format!("{}?seed=42", kind.path());
+        let reply = get(addr, &path).await;
+        assert_eq!(reply.status, StatusCode::OK, "{}: {}", path, reply.body);
+        let body = reply.json();
+        assert_eq!(body, json!({"value": expected, "seed": 42}), "{}", path);
+        assert_eq!(get(addr, &path).await.json(), body);
+    }
+
+    // The other parameters still count
+    let body = get(addr, "/int?min=1&seed=7&max=1").await.json();
+    assert_eq!(body, json!({"value": 1, "seed": 7}));
+
+    let reply = get(addr, "/int?seed=-1").await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+    assert!(reply.json()["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("seed must be"));
+
+    // Without a seed there's nothing to echo
+    let body = get(addr, "/int").await.json();
+    assert_eq!(body.get("seed"), None);
+}
+
+#[tokio::test]
+async fn the_root_says_its_seed_in_a_header() {
+    let addr = start(None).await;
+    let reply = get(addr, "/?seed=42").await;
+    assert_eq!(reply.header(SEED_HEADER), Some("42"));
+    let expected: u8 = values::seeded(42).gen();
+    assert_eq!(reply.body, expected.to_string());
+}
+
+#[tokio::test]
+async fn pages_on_other_origins_can_read_the_seed() {
+    let addr = start(None).await;
+    let headers = [("origin", "https://example.com")];
+    let reply = send_with(addr, Method::GET, "/?seed=42", &headers).await;
+    assert_eq!(reply.header(SEED_HEADER), Some("42"));
+    let exposed = reply.header("access-control-expose-headers").unwrap();
+    assert!(
+        exposed.split(", ").any(|name| name == SEED_HEADER),
+        "{}",
+        exposed
+    );
+    assert!(exposed.contains("x-request-id"), "{}", exposed);
+}
+
+#[tokio::test]
+async fn a_global_seed_makes_the_server_deterministic() {
+    let first = start(Some(1)).await;
+    let second = start(Some(1)).await;
+    let plain = start(None).await;
+
+    // Each request gets the next seed from ChaCha20 keyed with the global one
+    let mut seeds = values::seeded(1);
+    for _ in 0..3 {
+        let body = get(first, "/float").await.json();
+        assert_eq!(body["seed"], json!(seeds.next_u64()));
+        assert_eq!(get(second, "/float").await.json(), body);
+
+        // A replay with the echoed seed gives the same value
+        let replay = format!("/float?seed={}", body["seed"]);
+        assert_eq!(get(plain, &replay).await.json(), body);
+    }
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::{get, start, start_with};
+use hyper::StatusCode;
+use middleware::rate_limit::MemoryBuckets;
+use middleware::{Quota, RateLimit};
+use rand_value_server::Options;
+
+#[tokio::test]
+async fn answers_with_a_random_byte() {
+    let addr = start().await;
+    let reply = get(addr, "/").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert!(reply.header("x-request-id").is_some());
+    assert!(
+        reply.body.parse::<u8>().is_ok(),
+        "not a byte: {}",
+        reply.body
+    );
+}
+
+#[tokio::test]
+async fn clients_over_their_quota_are_told_to_wait() {
+    let options = Options {
+        rate_limit: Some(RateLimit::new(MemoryBuckets::new()).default_quota(Quota::per_minute(1))),
+        ..Options::default()
+    };
+    let addr = start_with(options).await;
+
+    let reply = get(addr, "/").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.header("ratelimit-limit"), Some("1"));
+    assert_eq!(reply.header("ratelimit-remaining"), Some("0"));
+
+    let reply = get(addr, "/").await;
+    assert_eq!(reply.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(reply.header("retry-after"), Some("60"));
+}
+
+#[tokio::test]
+async fn metrics_count_values_handed_out() {
+    let addr = start().await;
+    for _ in 0..3 {
+        get(addr, "/").await;
+    }
+
+    let reply = get(addr, "/metrics").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let lines = reply.body.lines().collect::<Vec<_>>();
+    for line in &[
+        r#"http_requests_total{method="GET",route="/",status="200"} 3"#,
+        r#"random_values_total{kind="u8"} 3"#,
+    ] {
+        assert!(lines.contains(line), "no {} in\n{}", line, reply.body);
+    }
+}
+
+#[tokio::test]
+async fn answers_probes() {
+    let addr = start().await;
+    for path in &["/healthz", "/readyz"] {
+        assert_eq!(get(addr, path).await.status, StatusCode::OK, "{}", path);
+    }
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use common::{get, send, start, start_with};
+use hyper::{Method, StatusCode};
+use rand_value_server::values::{self, BadRequest, Kind, Limits, RngSource};
+use rand_value_server::{Live, LiveSettings, Options};
+use serde_json::Value;
+use std::net::SocketAddr;
+
+async fn value(addr: SocketAddr, path: &str) -> Value {
+    let reply = get(addr, path).await;
+    assert_eq!(reply.status, StatusCode::OK, "{}: {}", path, reply.body);
+    assert_eq!(reply.header("content-type"), Some("application/json"));
+    reply.json()["value"].clone()
+}
+
+async fn error(addr: SocketAddr, path: &str) -> String {
+    let reply = get(addr, path).await;
+    assert_eq!(
+        reply.status,
+        StatusCode::BAD_REQUEST,
+        "{}: {}",
+        path,
+        reply.body
+    );
+    assert_eq!(reply.header("content-type"), Some("application/json"));
+    reply.json()["error"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn every_type_has_an_endpoint() {
+    let addr = start().await;
+
+    let int = value(addr, "/int").await.as_i64().unwrap();
+    assert!((0..=100).contains(&int));
+    for _ in 0..20 {
+        let int = value(addr, "/int?min=-3&max=-1").await.as_i64().unwrap();
+        assert!((-3..=-1).contains(&int));
+    }
+    assert_eq!(value(addr, "/int?min=7&max=7").await, 7);
+
+    let float = value(addr, "/float?min=2.5&max=3").await.as_f64().unwrap();
+    assert!((2.5..3.0).contains(&float));
+    assert!(value(addr, "/float").await.is_f64());
+
+    assert_eq!(value(addr, "/bool?p=1").await, true);
+    assert_eq!(value(addr, "/bool?p=0").await, false);
+
+    let uuid = value(addr, "/uuid").await;
+    let uuid = uuid.as_str().unwrap();
+    assert_eq!(uuid.len(), 36);
+    assert_eq!(&uuid[14..15], "4", "not version 4: {}", uuid);
+
+    let string = value(addr, "/string").await;
+    let string = string.as_str().unwrap();
+    assert_eq!(string.len(), 16);
+    assert!(string.chars().all(|c| c.is_ascii_alphanumeric()));
+    let string = value(addr, "/string?length=40&alphabet=digits").await;
+    assert!(string.as_str().unwrap().chars().all(|c| c.is_ascii_digit()));
+    let string = value(addr, "/string?length=5&chars=%C3%A9%21").await;
+    assert_eq!(string.as_str().unwrap().chars().count(), 5);
+    assert!(string
+        .as_str()
+        .unwrap()
+        .chars()
+        .all(|c| c == 'é' || c == '!'));
+    assert_eq!(value(addr, "/string?length=0").await, "");
+
+    let hex = value(addr, "/bytes?length=4").await;
+    let hex = hex.as_str().unwrap();
+    assert_eq!(hex.len(), 8);
+    assert!(hex.chars().all(|c| c.is_ascii_hexdigit()));
+    let base64 = value(addr, "/bytes?length=3&encoding=base64").await;
+    assert_eq!(base64.as_str().unwrap().len(), 4);
+}
+
+#[tokio::test]
+async fn bad_parameters_are_explained() {
+    let addr = start().await;
+    assert!(error(addr, "/int?min=5&max=1").await.contains("max"));
+    assert!(error(addr, "/int?max=lots").await.contains("invalid digit"));
+    assert!(error(addr, "/float?min=1&max=1").await.contains("smaller"));
+    assert!(error(addr, "/float?max=inf").await.contains("finite"));
+    assert!(error(addr, "/bool?p=1.5").await.contains("between 0 and 1"));
+    assert!(error(addr, "/uuid?version=1")
+        .await
+        .contains("unknown field"));
+    assert!(error(addr, "/string?alphabet=klingon")
+        .await
+        .contains("unknown variant"));
+    assert!(error(addr, "/string?alphabet=hex&chars=ab")
+        .await
+        .contains("not both"));
+    assert!(error(addr, "/string?chars=").await.contains("empty"));
+    assert!(error(addr, "/string?length=5000").await.contains("at most"));
+    assert!(error(addr, "/bytes?encoding=base32")
+        .await
+        .contains("unknown variant"));
+    assert!(error(addr, "/bytes?length=-1")
+        .await
+        .contains("invalid digit"));
+}
+
+#[tokio::test]
+async fn only_the_root_hands_out_bytes() {
+    let addr = start().await;
+    assert_eq!(get(addr, "/nope").await.status, StatusCode::NOT_FOUND);
+    let reply = send(addr, Method::POST, "/int").await;
+    assert_eq!(reply.status, StatusCode::METHOD_NOT_ALLOWED);
+
+    // The root still answers with plain text
+    assert!(get(addr, "/").await.body.parse::<u8>().is_ok());
+}
+
+#[tokio::test]
+async fn limits_and_sources_are_configurable() {
+    let options = Options {
+        live: LiveSettings::new(Live {
+            rng: RngSource::Os,
+            limits: Limits {
+                max_string_length: 4,
+                max_bytes: 2,
+            },
+            ..Live::default()
+        }),
+        ..Options::default()
+    };
+    let addr = start_with(options).await;
+
+    assert_eq!(
+        value(addr, "/bytes?length=2").await.as_str().unwrap().len(),
+        4
+    );
+    assert_eq!(
+        error(addr, "/bytes?length=3").await,
+        "length can be at most 2"
+    );
+    assert_eq!(
+        error(addr, "/string?length=5").await,
+        "length can be at most 4"
+    );
+}
+
+#[test]
+fn kinds_are_found_by_path() {
+    for kind in Kind::ALL.iter() {
+        assert_eq!(Kind::from_path(kind.path()), Some(*kind));
+    }
+    assert_eq!(Kind::from_path("/"), None);
+    assert_eq!(Kind::Uuid.name(), "uuid");
+
+    let mut rng = rand::thread_rng();
+    assert_eq!(
+        values::generate(Kind::Bool, "p=2", &Limits::default(), &mut rng),
+        Err(BadRequest("p must be between 0 and 1".into()))
+    );
+}
@@ -0,0 +1,287 @@
+use hyper::header::CONTENT_TYPE;
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use middleware::prometheus::{IntCounterVec, Opts, Registry};
+use middleware::{
+    AccessLog, BoxFuture, CatchPanic, Cors, Health, MatchedRoute, Metrics, Pipeline, RequestIds,
+    Timing,
+};
+use rand::distributions::{Bernoulli, Normal, Uniform};
+use rand::prng::ChaChaRng;
+use rand::{Rng, RngCore, SeedableRng};
+use schemars::{JsonSchema, SchemaGenerator};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, OnceLock};
+
+pub mod openapi;
+
+/// Stores a random number.
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RngResponse {
+    pub value: f64,
+    /// The seed the number was sampled with, if it was seeded. Sending
+    /// it again gives the same number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+/// Options of the server, all off by default.
+#[derive(Default)]
+pub struct Options {
+    /// Makes the server deterministic. Requests without their own seed
+    /// each get the next one from a generator seeded with this.
+    pub seed: Option<u64>,
+}
+
+/// Types of random number requests.
+#[derive(Deserialize, JsonSchema)]
+#[serde(tag = "distribution", content = "parameters", rename_all = "lowercase")]
+pub enum RngRequest {
+    /// A whole number in `start..end`.
+    Uniform {
+        #[serde(flatten)]
+        range: Range<i32>,
+    },
+    /// A normally distributed number.
+    Normal { mean: f64, std_dev: f64 },
+    /// 1 with probability `p`, 0 otherwise.
+    Bernoulli { p: f64 },
+}
+
+impl RngRequest {
+    /// The name of the distribution, as in the `distribution` tag.
+    pub fn distribution(&self) -> &'static str {
+        match self {
+            RngRequest::Uniform { .. } => "uniform",
+            RngRequest::Normal { .. } => "normal",
+            RngRequest::Bernoulli { .. } => "bernoulli",
+        }
+    }
+
+    /// Checks the parameters are possible for the distribution, `rand`
+    /// panics on the ones that aren't.
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            RngRequest::Uniform { range } if range.start >= range.end => Err(format!(
+                "uniform needs start below end, got {}..{}",
+                range.start, range.end
+            )),
+            RngRequest::Normal { std_dev, .. } if std_dev.is_nan() || *std_dev < 0.0 => Err(
+                format!("normal needs a std_dev of at least 0, got {}", std_dev),
+            ),
+            RngRequest::Bernoulli { p } if !(0.0..=1.0).contains(p) => {
+                Err(format!("bernoulli needs a p from 0 to 1, got {}", p))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What `/random` reads, a distribution and maybe a seed.
+#[derive(Deserialize, JsonSchema)]
+pub struct RandomRequest {
+    #[serde(flatten)]
+    pub request: RngRequest,
+    /// Samples deterministically, see `handle_request`.
+    pub seed: Option<u64>,
+}
+
+/// A route of the service together with its documentation.
+pub struct Route {
+    pub method: Method,
+    pub path: &'static str,
+    handler: fn(Request<Body>, State) -> BoxFuture,
+    /// Builds the OpenAPI operation object of the route.
+    pub operation: fn(&mut SchemaGenerator) -> Value,
+}
+
+/// Every route of the service, `/openapi.json` is generated from these.
+pub fn routes() -> Vec<Route> {
+    vec![
+        Route {
+            method: Method::POST,
+            path: "/random",
+            handler: |req, state| Box::pin(random(req, state)),
+            operation: |generator| {
+                json!({
+                    "summary": "Samples a random number from a distribution",
+                    "requestBody": openapi::json_body(generator.subschema_for::<RandomRequest>()),
+                    "responses": {
+                        "200": openapi::json_reply("The sampled number", generator.subschema_for::<RngResponse>()),
+                        "400": openapi::text_reply("The body couldn't be read"),
+                        "422": openapi::text_reply("Not a known distribution, or parameters impossible for it"),
+                    },
+                })
+            },
+        },
+        Route {
+            method: Method::GET,
+            path: "/openapi.json",
+            handler: |_req, _state| Box::pin(async { openapi_json() }),
+            operation: |_generator| {
+                json!({
+                    "summary": "This document",
+                    "responses": {
+                        "200": {
+                            "description": "The OpenAPI document",
+                            "content": {"application/json": {}},
+                        },
+                    },
+                })
+            },
+        },
+    ]
+}
+
+/// What the handlers share.
+#[derive(Clone)]
+struct State {
+    /// Counts values generated by distribution.
+    generated: IntCounterVec,
+    /// Hands out seeds to requests without one, with `Options::seed`.
+    seeds: Option<Arc<Mutex<ChaChaRng>>>,
+}
+
+async fn microservice_handler(
+    req: Request<Body>,
+    routes: Arc<Vec<Route>>,
+    state: State,
+) -> Response<Body> {
+    let route = routes
+        .iter()
+        .find(|route| route.method == req.method() && route.path == req.uri().path());
+    match route {
+        Some(route) => {
+            let mut response = (route.handler)(req, state).await;
+            let matched = MatchedRoute(route.path.to_owned());
+            response.extensions_mut().insert(matched);
+            response
+        }
+        None => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Not Found".into())
+            .unwrap(),
+    }
+}
+
+/// Samples the distribution described by the body.
+async fn random(req: Request<Body>, state: State) -> Response<Body> {
+    let chunks = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(chunks) => chunks,
+        Err(err) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(err.to_string().into())
+                .unwrap()
+        }
+    };
+    let RandomRequest { request, seed } = match serde_json::from_slice(chunks.as_ref()) {
+        Ok(request) => request,
+        Err(err) => return unprocessable(err.to_string()),
+    };
+    if let Err(err) = request.check() {
+        return unprocessable(err);
+    }
+    let seed = seed.or_else(|| {
+        let seeds = state.seeds.as_ref()?;
+        Some(seeds.lock().unwrap().next_u64())
+    });
+    let distribution = request.distribution();
+    let response = handle_request(request, seed);
+    state.generated.with_label_values(&[distribution]).inc();
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        // Two plain fields always serialize
+        .body(serde_json::to_string(&response).unwrap().into())
+        .unwrap()
+}
+
+fn unprocessable(message: String) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::UNPROCESSABLE_ENTITY)
+        .body(message.into())
+        .unwrap()
+}
+
+fn openapi_json() -> Response<Body> {
+    // Routes don't change while running, build the document once
+    static SPEC: OnceLock<String> = OnceLock::new();
+    let spec = SPEC.get_or_init(|| openapi::spec(&routes()).to_string());
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(spec.as_str()))
+        .unwrap()
+}
+
+/// Samples `request`. Without a seed the thread's generator is used.
+/// With one it's ChaCha20 keyed with the seed, so the same seed always
+/// gives the same number, at least with the same version of `rand`.
+/// Panics if the parameters are impossible, see `RngRequest::check`.
+pub fn handle_request(request: RngRequest, seed: Option<u64>) -> RngResponse {
+    let value = match seed {
+        Some(seed) => sample(request, &mut seeded(seed)),
+        None => sample(request, &mut rand::thread_rng()),
+    };
+    RngResponse { value, seed }
+}
+
+fn sample<R: Rng>(request: RngRequest, rng: &mut R) -> f64 {
+    match request {
+        RngRequest::Uniform { range } => rng.sample(Uniform::from(range)) as f64,
+        RngRequest::Normal { mean, std_dev } => rng.sample(Normal::new(mean, std_dev)),
+        RngRequest::Bernoulli { p } => rng.sample(Bernoulli::new(p)) as i8 as f64,
+    }
+}
+
+/// ChaCha20 keyed with `seed` in little endian, followed by zeros.
+pub fn seeded(seed: u64) -> ChaChaRng {
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(&seed.to_le_bytes());
+    ChaChaRng::from_seed(key)
+}
+
+/// Binds the server to `addr` and returns the address actually used
+/// (handy with port 0) together with the future that runs the server.
+/// Must be called from within a tokio runtime.
+pub fn bind(
+    addr: &SocketAddr,
+    options: Options,
+) -> Result<(SocketAddr, impl Future<Output = Result<(), hyper::Error>>), hyper::Error> {
+    let builder = Server::try_bind(addr)?;
+    let registry = Registry::new();
+    let generated = IntCounterVec::new(
+        Opts::new("random_values_total", "Random values sampled"),
+        &["distribution"],
+    )
+    .unwrap();
+    // Both names are unique and valid
+    registry.register(Box::new(generated.clone())).unwrap();
+    let state = State {
+        generated,
+        seeds: options.seed.map(|seed| Arc::new(Mutex::new(seeded(seed)))),
+    };
+    // All connections share one routing table
+    let routes = Arc::new(routes());
+    let stack = Pipeline::new()
+        .with(Metrics::new(registry).unwrap())
+        // Nothing to depend on, ready as soon as it listens
+        .with(Health::new())
+        .with(RequestIds)
+        .with(CatchPanic)
+        .with(AccessLog)
+        .with(Timing)
+        .with(Cors::any())
+        .handler(move |req| microservice_handler(req, routes.clone(), state.clone()));
+    let server = builder.serve(make_service_fn(move |conn: &AddrStream| {
+        let stack = stack.clone();
+        let client = conn.remote_addr();
+        async move { Ok::<_, Infallible>(service_fn(move |req| stack.serve(client, req))) }
+    }));
+    Ok((server.local_addr(), server))
+}
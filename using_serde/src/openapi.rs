@@ -0,0 +1,62 @@
+//! The OpenAPI 3.1 document served at `/openapi.json`, built from
+//! `routes()`. Schemas come from the request and response types through
+//! `schemars`, so `RandomRequest` shows up as the tagged union serde
+//! reads: one variant per `distribution`, each with its own `parameters`,
+//! and the optional `seed` next to them.
+
+use crate::Route;
+use schemars::generate::SchemaSettings;
+use schemars::Schema;
+use serde_json::{json, Map, Value};
+
+pub const OPENAPI_VERSION: &str = "3.1.0";
+
+/// Builds the whole document from the routes.
+pub fn spec(routes: &[Route]) -> Value {
+    let mut generator = SchemaSettings::draft2020_12()
+        .with(|settings| {
+            settings.definitions_path = "#/components/schemas".into();
+            settings.meta_schema = None;
+        })
+        .into_generator();
+
+    let mut paths = Map::new();
+    for route in routes {
+        let path = paths.entry(route.path).or_insert_with(|| json!({}));
+        path[route.method.as_str().to_lowercase()] = (route.operation)(&mut generator);
+    }
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": "Random numbers",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {"schemas": generator.take_definitions(true)},
+    })
+}
+
+/// A JSON request body.
+pub fn json_body(schema: Schema) -> Value {
+    json!({
+        "required": true,
+        "content": {"application/json": {"schema": schema}},
+    })
+}
+
+/// A JSON response.
+pub fn json_reply(description: &str, schema: Schema) -> Value {
+    json!({
+        "description": description,
+        "content": {"application/json": {"schema": schema}},
+    })
+}
+
+/// A plain text response, used for errors.
+pub fn text_reply(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {"text/plain": {"schema": {"type": "string"}}},
+    })
+}
@@ -0,0 +1,64 @@
+// Shared by several test binaries, each of which only uses some helpers
+#![allow(dead_code)]
+
+use hyper::header::HeaderMap;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use serde_json::Value;
+use std::net::SocketAddr;
+use using_serde::{Options, RngResponse};
+
+/// What came back for a request.
+pub struct Reply {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl Reply {
+    /// Parses the body as JSON, failing the test if it isn't.
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body)
+            .unwrap_or_else(|err| panic!("body is not JSON ({}): {}", err, self.body))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|value| value.to_str().unwrap())
+    }
+}
+
+/// Starts the server on an ephemeral port and returns its address.
+pub async fn start() -> SocketAddr {
+    start_with(Options::default()).await
+}
+
+/// Starts the server with the given options on an ephemeral port.
+pub async fn start_with(options: Options) -> SocketAddr {
+    let (addr, server) = using_serde::bind(&([127, 0, 0, 1], 0).into(), options).unwrap();
+    tokio::spawn(server);
+    addr
+}
+
+/// Sends a request with `body` to the server.
+pub async fn send(addr: SocketAddr, method: Method, path: &str, body: &str) -> Reply {
+    let req = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path))
+        .body(Body::from(body.to_owned()))
+        .unwrap();
+    let resp = Client::new().request(req).await.unwrap();
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    let bytes = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    Reply {
+        status,
+        headers,
+        body: String::from_utf8(bytes.to_vec()).unwrap(),
+    }
+}
+
+/// Asks `/random` for a sample, which has to succeed.
+pub async fn random(addr: SocketAddr, request: &str) -> RngResponse {
+    let reply = send(addr, Method::POST, "/random", request).await;
+    assert_eq!(reply.status, StatusCode::OK, "{}: {}", request, reply.body);
+    serde_json::from_str(&reply.body).unwrap()
+}
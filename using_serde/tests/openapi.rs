@@ -0,0 +1,112 @@
+//! Checks `/openapi.json` against the handlers, so the document can't
+//! drift from what the service actually does.
+
+mod common;
+
+use common::{send, start};
+use hyper::{Method, StatusCode};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+
+async fn spec(addr: SocketAddr) -> Value {
+    let reply = send(addr, Method::GET, "/openapi.json", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.header("content-type"), Some("application/json"));
+    reply.json()
+}
+
+fn validate(spec: &Value, schema: &Value, instance: &Value) {
+    // References point into the components of the whole document
+    let mut root = schema.clone();
+    root["components"] = spec["components"].clone();
+    let validator = jsonschema::draft202012::new(&root).unwrap();
+    if let Err(err) = validator.validate(instance) {
+        panic!("{} doesn't match the document: {}", instance, err);
+    }
+}
+
+#[tokio::test]
+async fn served_document_is_the_generated_one() {
+    let addr = start().await;
+    let spec = spec(addr).await;
+    assert_eq!(spec, using_serde::openapi::spec(&using_serde::routes()));
+    assert_eq!(spec["openapi"], using_serde::openapi::OPENAPI_VERSION);
+
+    // Every route is documented
+    for route in using_serde::routes() {
+        let method = route.method.as_str().to_lowercase();
+        assert!(spec["paths"][route.path][&method]["summary"].is_string());
+    }
+
+    // RngRequest is a union tagged by distribution, with parameters
+    let variants = spec["components"]["schemas"]["RandomRequest"]["oneOf"]
+        .as_array()
+        .unwrap();
+    let names = variants
+        .iter()
+        .map(|variant| {
+            variant["properties"]["distribution"]["const"]
+                .as_str()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["uniform", "normal", "bernoulli"]);
+    for variant in variants {
+        assert_eq!(variant["required"], json!(["distribution", "parameters"]));
+    }
+    let uniform = &variants[0]["properties"]["parameters"];
+    assert_eq!(uniform["required"], json!(["start", "end"]));
+}
+
+#[tokio::test]
+async fn handlers_do_what_the_document_says() {
+    let addr = start().await;
+    let spec = spec(addr).await;
+    let random = &spec["paths"]["/random"]["post"];
+    let request_schema = &random["requestBody"]["content"]["application/json"]["schema"];
+    let reply_schema = &random["responses"]["200"]["content"]["application/json"]["schema"];
+
+    // One example per distribution the document knows about
+    let examples = [
+        json!({"distribution": "uniform", "parameters": {"start": 1, "end": 10}}),
+        json!({"distribution": "normal", "parameters": {"mean": 2.0, "std_dev": 5.3}}),
+        json!({"distribution": "bernoulli", "parameters": {"p": 0.5}, "seed": 7}),
+    ];
+    let documented = spec["components"]["schemas"]["RandomRequest"]["oneOf"]
+        .as_array()
+        .unwrap()
+        .len();
+    assert_eq!(
+        examples.len(),
+        documented,
+        "add an example for new distributions"
+    );
+    for example in &examples {
+        validate(&spec, request_schema, example);
+        let reply = send(addr, Method::POST, "/random", &example.to_string()).await;
+        assert_eq!(reply.status, StatusCode::OK);
+        assert_eq!(reply.header("content-type"), Some("application/json"));
+        validate(&spec, reply_schema, &reply.json());
+    }
+
+    // Failures get documented statuses
+    let bad = [
+        r#"{"distribution": "poisson", "parameters": {}}"#,
+        r#"{"distribution": "bernoulli", "parameters": {"p": 2.0}}"#,
+    ];
+    for body in &bad {
+        let status = send(addr, Method::POST, "/random", body).await.status;
+        let documented = &random["responses"][status.as_str()];
+        assert!(
+            documented.is_object(),
+            "undocumented {} for {}",
+            status,
+            body
+        );
+    }
+
+    // Nothing answers that isn't in the document
+    let status = send(addr, Method::GET, "/random", "").await.status;
+    assert!(spec["paths"]["/random"].get("get").is_none());
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,84 @@
+//! Seeded samples are pinned down exactly, so a change to how they are
+//! made shows up here before it breaks anyone's replays.
+
+mod common;
+
+use common::{random, start, start_with};
+use rand::RngCore;
+use using_serde::{handle_request, seeded, Options, RngRequest, RngResponse};
+
+fn sample(request: RngRequest, seed: u64) -> f64 {
+    let response = handle_request(request, Some(seed));
+    assert_eq!(response.seed, Some(seed));
+    response.value
+}
+
+#[test]
+fn same_seed_same_sample_for_every_distribution() {
+    let uniform = |start, end| RngRequest::Uniform { range: start..end };
+    assert_eq!(sample(uniform(1, 10), 42), 2.0);
+    assert_eq!(sample(uniform(1, 10), 7), 7.0);
+    assert_eq!(sample(uniform(-1000, 1000), 42), -697.0);
+    assert_eq!(sample(uniform(-1000, 1000), 7), 452.0);
+
+    let normal = || RngRequest::Normal {
+        mean: 2.0,
+        std_dev: 5.3,
+    };
+    assert_eq!(sample(normal(), 42), -0.107749145946753);
+    assert_eq!(sample(normal(), 7), 0.4825888079983307);
+
+    let bernoulli = || RngRequest::Bernoulli { p: 0.5 };
+    assert_eq!(sample(bernoulli(), 1), 0.0);
+    assert_eq!(sample(bernoulli(), 2), 1.0);
+
+    // Without a seed there's nothing to echo
+    let response = handle_request(uniform(1, 10), None);
+    assert_eq!(response.seed, None);
+}
+
+#[test]
+fn seeds_key_chacha20() {
+    let mut rng = seeded(1);
+    assert_eq!(rng.next_u64(), 10597511851372368837);
+    assert_eq!(rng.next_u64(), 9609124134916180088);
+}
+
+#[tokio::test]
+async fn requests_can_bring_their_own_seed() {
+    let addr = start().await;
+    let body =
+        r#"{"distribution": "uniform", "parameters": {"start": -1000, "end": 1000}, "seed": 42}"#;
+    let expected = RngResponse {
+        value: -697.0,
+        seed: Some(42),
+    };
+    assert_eq!(random(addr, body).await, expected);
+    assert_eq!(random(addr, body).await, expected);
+
+    let body = r#"{"distribution": "uniform", "parameters": {"start": 1, "end": 10}}"#;
+    assert_eq!(random(addr, body).await.seed, None);
+}
+
+#[tokio::test]
+async fn a_global_seed_makes_the_server_deterministic() {
+    let body = r#"{"distribution": "normal", "parameters": {"mean": 2.0, "std_dev": 5.3}}"#;
+    let first = start_with(Options { seed: Some(1) }).await;
+    let second = start_with(Options { seed: Some(1) }).await;
+
+    // Each request gets the next seed from ChaCha20 keyed with the global one
+    let mut seeds = seeded(1);
+    for _ in 0..3 {
+        let response = random(first, body).await;
+        assert_eq!(response.seed, Some(seeds.next_u64()));
+        assert_eq!(random(second, body).await, response);
+
+        // A replay with the echoed seed gives the same sample
+        let replay = format!(
+            r#"{{"distribution": "normal", "parameters": {{"mean": 2.0, "std_dev": 5.3}}, "seed": {}}}"#,
+            response.seed.unwrap()
+        );
+        let plain = start().await;
+        assert_eq!(random(plain, &replay).await, response);
+    }
+}
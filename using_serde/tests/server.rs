@@ -0,0 +1,95 @@
+mod common;
+
+use common::{random, send, start};
+use hyper::{Method, StatusCode};
+
+#[tokio::test]
+async fn samples_every_distribution() {
+    let addr = start().await;
+
+    let uniform = r#"{"distribution": "uniform", "parameters": {"start": 1, "end": 10}}"#;
+    let value = random(addr, uniform).await.value;
+    assert!((1.0..10.0).contains(&value));
+
+    let normal = r#"{"distribution": "normal", "parameters": {"mean": 2.0, "std_dev": 5.3}}"#;
+    assert!(random(addr, normal).await.value.is_finite());
+
+    let bernoulli = r#"{"distribution": "bernoulli", "parameters": {"p": 1.0}}"#;
+    assert_eq!(random(addr, bernoulli).await.value, 1.0);
+}
+
+#[tokio::test]
+async fn rejects_unknown_distributions() {
+    let addr = start().await;
+    let request = r#"{"distribution": "poisson", "parameters": {}}"#;
+    let reply = send(addr, Method::POST, "/random", request).await;
+    assert_eq!(reply.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn other_routes_are_not_found() {
+    let addr = start().await;
+    let reply = send(addr, Method::GET, "/random", "").await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn impossible_parameters_are_unprocessable() {
+    let addr = start().await;
+    for (request, message) in &[
+        (
+            r#"{"distribution": "bernoulli", "parameters": {"p": 2.0}}"#,
+            "bernoulli needs a p from 0 to 1, got 2",
+        ),
+        (
+            r#"{"distribution": "normal", "parameters": {"mean": 0.0, "std_dev": -1.0}}"#,
+            "normal needs a std_dev of at least 0, got -1",
+        ),
+        (
+            r#"{"distribution": "uniform", "parameters": {"start": 5, "end": 5}}"#,
+            "uniform needs start below end, got 5..5",
+        ),
+    ] {
+        let reply = send(addr, Method::POST, "/random", request).await;
+        assert_eq!(
+            reply.status,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "{}",
+            request
+        );
+        assert_eq!(reply.body, *message);
+    }
+}
+
+#[tokio::test]
+async fn metrics_count_samples_by_distribution() {
+    let addr = start().await;
+    let uniform = r#"{"distribution": "uniform", "parameters": {"start": 1, "end": 10}}"#;
+    random(addr, uniform).await;
+    random(addr, uniform).await;
+    let bernoulli = r#"{"distribution": "bernoulli", "parameters": {"p": 0.5}}"#;
+    random(addr, bernoulli).await;
+    send(addr, Method::GET, "/random", "").await;
+
+    let reply = send(addr, Method::GET, "/metrics", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let lines = reply.body.lines().collect::<Vec<_>>();
+    for line in &[
+        r#"http_requests_total{method="POST",route="/random",status="200"} 3"#,
+        r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#,
+        r#"random_values_total{distribution="uniform"} 2"#,
+        r#"random_values_total{distribution="bernoulli"} 1"#,
+    ] {
+        assert!(lines.contains(line), "no {} in\n{}", line, reply.body);
+    }
+}
+
+#[tokio::test]
+async fn answers_probes() {
+    let addr = start().await;
+    let reply = send(addr, Method::GET, "/healthz", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let reply = send(addr, Method::GET, "/readyz", "").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.body, "ready\n");
+}